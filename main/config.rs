@@ -5,6 +5,7 @@ use serde::ser::Serializer;
 use serde::de::{self, Deserializer, Visitor, Unexpected};
 use serde_json;
 use log::LogLevelFilter;
+use vm::interp::Limits;
 
 pub const DEFAULT_CONFIG_PATH: &'static str = "./scifiweb.json";
 
@@ -148,10 +149,15 @@ impl Default for DefaultTimeZone {
 #[serde(default, rename_all="camelCase")]
 pub struct Config {
   pub program: String,
+  /// Where to look for included files that aren't next to the file
+  /// including them. Relative paths start at the config file.
+  pub include_dirs: Vec<String>,
   pub server: ServerConfig,
   pub log: LogOpts,
   pub out: OutDirs,
   pub default_time_zone: DefaultTimeZone,
+  /// For each invocation of an event or function.
+  pub limits: Limits,
 }
 
 impl Config {
@@ -176,10 +182,12 @@ impl Default for Config {
   fn default() -> Self {
     Config {
       program: "-".into(),
+      include_dirs: Vec::new(),
       server: Default::default(),
       log: Default::default(),
       out: Default::default(),
       default_time_zone: Default::default(),
+      limits: Default::default(),
     }
   }
 }
@@ -17,11 +17,19 @@ extern crate scifi_util as util;
 mod config;
 mod options;
 
+use std::io::{self, Read};
+use std::sync::Arc;
 use std::path::Path;
 use std::fs::File;
 use docopt::Docopt;
 use model_mem::MemoryAccessor;
+use util::termcolor::ColorChoice;
 use vm::ast::Ast;
+use vm::ast::ty::{BaseCustomType, PrimitiveType};
+use vm::bc::{CodeKind, Program};
+use vm::interp::{Interpreter, Limits, MemoryHost, State, Value};
+use vm::compile::{CompileOptions, Renderer, ToDiagnostic};
+use vm::strings::{StringFormat, Translations};
 use self::config::{Config, DEFAULT_CONFIG_PATH};
 use self::options::DebugOptions;
 
@@ -35,8 +43,8 @@ SciFiWeb Game Management Server
 Usage:
   scifiweb [options]
   scifiweb init <dir>
-  scifiweb build [-t <target>] [options]
-  scifiweb run <file> [options]
+  scifiweb build [-t <target>] [-o <file>] [options]
+  scifiweb run <file> [<event>] [options]
   scifiweb console [-u <user> (-k <key-file> | -p [<password>])]
   scifiweb --help
 
@@ -45,22 +53,36 @@ Options:
                                   The default is './scifiweb.json'.
   -c <key=value> ...              Override a configuration option.
   -t <target> --target=<target>   Specify the build target.
-                                  Valid targets: all, csharp, sql.
-  -z <debug-options> ...          Set a debug option.
+                                  Valid targets: all, bytecode, csharp,
+                                  sql, strings.
+  -o <file> --output=<file>       Where to write the build output. The
+                                  strings target writes gettext for .po
+                                  and .pot files, otherwise JSON. The
+                                  default is './strings.json', or
+                                  './program.json' for bytecode.
+  --translations=<file>           Show localized text from run in the
+                                  locale the file is named after, like
+                                  'fr.po' or 'pt-BR.json'. The file is a
+                                  translated copy of the strings output.
+  -z <debug-options> ...          Set a debug option: save-ast, dump-bc.
 
 Command overview:
   (none)      Start a server for the program listed in the configuration file.
   init        Create an initial configuration and source file in <dir>.
   build       Build the specified target.
-  run         Run a self-contained program.
+  run         Run a self-contained program. Sends <event> (default 'Main')
+              from a new user with new instances for its parameters, or
+              calls the function with that name, and prints the result.
   console     Start the interactive console.
 ";
 
 #[derive(Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
 enum Target {
   All,
+  Bytecode,
   CSharp,
   Sql,
+  Strings,
 }
 
 impl Default for Target {
@@ -77,9 +99,12 @@ struct Args {
   cmd_console: bool,
   arg_dir: String,
   arg_file: String,
+  arg_event: Option<String>,
   flag_config: Option<String>,
   flag_c: Vec<String>,
   flag_target: Option<Target>,
+  flag_output: Option<String>,
+  flag_translations: Option<String>,
   flag_z: DebugOptions,
 }
 
@@ -139,12 +164,33 @@ fn main() {
     warn!("Couldn't read '{}', using default config.", config_path);
   }
 
+  let config_dir = Path::new(config_path).parent().unwrap_or(Path::new("."));
+  let options = CompileOptions {
+    include_dirs: config.include_dirs.iter().map(|dir| config_dir.join(dir)).collect(),
+  };
+
   if args.cmd_build {
     trace!("Starting build for {}, target {:?}", &config.program, args.flag_target);
-    build(&config.program, args.flag_z.save_ast);
+    let target = args.flag_target.unwrap_or_default();
+    let output = args.flag_output.as_ref().map(String::as_str);
+    build(&config.program, &options, Some((target, output)), &args.flag_z);
   } else if args.cmd_run {
     trace!("Running {}", args.arg_file);
-    build(&args.arg_file, args.flag_z.save_ast);
+    if let Some(program) = build(&args.arg_file, &options, None, &args.flag_z) {
+      let name = args.arg_event.as_ref().map(String::as_str).unwrap_or("Main");
+      let translations = match args.flag_translations {
+        Some(ref path) => match read_translations(Path::new(path)) {
+          Ok(translations) => Some(translations),
+          Err(e) => {
+            error!("Couldn't read translations from '{}': {}", path, e);
+            return;
+          }
+        },
+        None => None,
+      };
+      let locale = translations.as_ref().map(|&(ref locale, ref t)| (t, locale.as_str()));
+      run(&program, name, config.limits, locale);
+    }
   } else {
     model::initialize();
     let accessor = MemoryAccessor::new();
@@ -153,14 +199,186 @@ fn main() {
   }
 }
 
-fn build(filename: &str, save_ast: bool) {
-  match vm::compile_file(Path::new(filename)) {
-    Ok(ast) => {
-      info!("Loaded program.");
-      if save_ast {
-        write_ast(&ast.awake());
-      }
+fn write_strings<'a>(ast: &Ast<'a>, output: Option<&str>) {
+  let path = Path::new(output.unwrap_or("./strings.json"));
+  let strings = ast.localized_strings();
+  let result = File::create(path)
+    .and_then(|mut file| strings.export(&mut file, StringFormat::from_path(path)));
+  match result {
+    Ok(_) => info!("Wrote {} localized string(s) to {}", strings.len(), path.display()),
+    Err(e) => error!("{}", e),
+  }
+}
+
+/// Returns the locale, which is the file name without its extension.
+fn read_translations(path: &Path) -> io::Result<(String, Translations)> {
+  let locale = match path.file_stem().and_then(|s| s.to_str()) {
+    Some(locale) => locale.to_owned(),
+    None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no locale in file name")),
+  };
+  let mut translations = Translations::default();
+  let mut file = File::open(path)?;
+  match StringFormat::from_path(path) {
+    StringFormat::Po => {
+      let mut po = String::new();
+      file.read_to_string(&mut po)?;
+      translations.load_po(&locale, &po);
+    }
+    StringFormat::Json => {
+      translations
+        .load_json(&locale, file)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+  }
+  Ok((locale, translations))
+}
+
+fn write_bytecode(program: &Program, output: Option<&str>) {
+  let path = Path::new(output.unwrap_or("./program.json"));
+  let file = match File::create(path) {
+    Ok(f) => f,
+    Err(e) => {
+      error!("{}", e);
+      return;
     }
+  };
+  match program.write_json(file) {
+    Ok(_) => info!("Wrote bytecode to {}", path.display()),
     Err(e) => error!("{}", e),
   }
 }
+
+/// Returns the bytecode when it's needed, which is when
+/// running, dumping it or building the bytecode target.
+fn build(
+  filename: &str,
+  options: &CompileOptions,
+  target: Option<(Target, Option<&str>)>,
+  debug: &DebugOptions,
+) -> Option<Program> {
+  let compiled = vm::compile_file(Path::new(filename), options);
+  if compiled.is_ok() {
+    info!("Loaded program.");
+    if debug.save_ast {
+      write_ast(&compiled.ast.awake());
+    }
+    match target {
+      Some((Target::All, output)) | Some((Target::Strings, output)) => {
+        write_strings(&compiled.ast.awake(), output);
+      }
+      _ => {}
+    }
+    let bytecode_output = match target {
+      Some((Target::Bytecode, output)) => Some(output),
+      _ => None,
+    };
+    if target.is_some() && bytecode_output.is_none() && !debug.dump_bytecode {
+      return None;
+    }
+    let program = match vm::bc::compile(&compiled.ast.awake()) {
+      Ok(program) => program,
+      Err(errors) => {
+        let renderer = Renderer::new(ColorChoice::Auto);
+        let diagnostics: Vec<_> = errors.iter().map(ToDiagnostic::to_diagnostic).collect();
+        renderer.emit_all(&diagnostics);
+        error!("Bytecode compilation failed with {} error(s).", errors.len());
+        return None;
+      }
+    };
+    if debug.dump_bytecode {
+      if let Err(e) = vm::bc::disassemble(&program, &mut io::stdout()) {
+        error!("{}", e);
+      }
+    }
+    if let Some(output) = bytecode_output {
+      write_bytecode(&program, output);
+    }
+    Some(program)
+  } else {
+    let renderer = Renderer::new(ColorChoice::Auto);
+    let diagnostics: Vec<_> = compiled.errors.iter().map(ToDiagnostic::to_diagnostic).collect();
+    renderer.emit_all(&diagnostics);
+    error!("Build failed with {} error(s).", compiled.errors.len());
+    None
+  }
+}
+
+/// Everything is made up on the spot in memory: the sender is a user
+/// of the type the event names, or the first user type if it doesn't
+/// name one, and each parameter gets a new instance or an empty value.
+fn run(program: &Program, name: &str, limits: Limits, locale: Option<(&Translations, &str)>) {
+  let mut host = MemoryHost::new();
+  let code = match program.event(name).or_else(|| program.function(name)) {
+    Some(code) => code,
+    None => {
+      error!("There's no event or function named '{}'.", name);
+      return;
+    }
+  };
+  let args = code.param_types.iter().map(|ty| new_value(program, &mut host, ty)).collect();
+  let mut interpreter = Interpreter::with_limits(program, &mut host, limits);
+  if let Some((translations, locale)) = locale {
+    interpreter.set_locale(translations, locale);
+  }
+  let mut outcome = if code.kind == CodeKind::Function {
+    interpreter.call_function(name, args)
+  } else {
+    let is_user = |ty: &str| {
+      program.type_info(ty).map_or(false, |t| t.base_type == BaseCustomType::User)
+    };
+    let sender_type = match code.sender_type {
+      Some(ref ty) if is_user(ty) => Some(ty),
+      _ => program.types.iter().map(|t| &t.name).find(|ty| is_user(ty)),
+    };
+    let sender_type = sender_type.cloned().unwrap_or_else(|| "user".into());
+    let sender = interpreter.host().create(sender_type);
+    interpreter.send_event(name, sender, args)
+  };
+
+  // There's nobody to wait for, so every wait is triggered
+  // right away, first to last.
+  let mut award_count = 0;
+  loop {
+    let (awards, state) = match outcome {
+      Ok(outcome) => {
+        let record = &outcome.record;
+        info!("Ran {} with seed {}.", record.invocation.name(), record.seed);
+        (outcome.awards, outcome.state)
+      }
+      Err(failure) => {
+        error!("{}", failure);
+        return;
+      }
+    };
+    for award in &awards {
+      println!("{}", award);
+    }
+    award_count += awards.len();
+    let continuation = match state {
+      State::Finished(value) => {
+        if code.kind == CodeKind::Function {
+          println!("{}", value);
+        }
+        info!("{} finished with {} award(s).", name, award_count);
+        return;
+      }
+      State::Waiting(continuation) => continuation,
+    };
+    let id = {
+      let (id, wait) = continuation.waits()[0];
+      info!("Triggering {}", wait);
+      id
+    };
+    outcome = interpreter.resume(continuation, id);
+  }
+}
+
+fn new_value(program: &Program, host: &mut MemoryHost, ty: &Arc<str>) -> Value {
+  if program.type_info(ty).is_some() {
+    return Value::Instance(host.create(ty.clone()));
+  }
+  PrimitiveType::iter()
+    .find(|p| p.as_str() == &**ty)
+    .map(Value::default_for)
+    .unwrap_or(Value::Void)
+}
@@ -4,12 +4,14 @@ use serde::de::{self, Deserialize, Deserializer, Visitor, SeqAccess, Unexpected}
 #[derive(Debug)]
 pub struct DebugOptions {
   pub save_ast: bool,
+  pub dump_bytecode: bool,
 }
 
 impl Default for DebugOptions {
   fn default() -> Self {
     DebugOptions {
       save_ast: false,
+      dump_bytecode: false,
     }
   }
 }
@@ -26,6 +28,8 @@ impl DebugOptionsVisitor {
   pub fn set_option(&mut self, option: &str) -> bool {
     if option == "save-ast" {
       self.opts.save_ast = true;
+    } else if option == "dump-bc" {
+      self.opts.dump_bytecode = true;
     } else {
       return false;
     }
@@ -190,6 +190,11 @@ impl<'a, T: ?Sized + 'a> GraphRef<'a, T> {
     }
   }
 
+  /// Whether `awake` would panic because of a mutable borrow.
+  pub fn is_awake_mut(&self) -> bool {
+    self.borrow_count.get() == WRITING
+  }
+
   fn map_data<'b, F, U>(&self, map_fn: F) -> U
   where
     'a: 'b,
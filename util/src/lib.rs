@@ -6,7 +6,7 @@ extern crate crossbeam;
 extern crate futures;
 #[macro_use]
 extern crate log;
-extern crate termcolor;
+pub extern crate termcolor;
 extern crate fxhash;
 extern crate chrono;
 extern crate serde;
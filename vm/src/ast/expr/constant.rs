@@ -0,0 +1,261 @@
+use std::sync::Arc;
+use std::fmt::{self, Display};
+use std::i64;
+use compile::TokenSpan;
+use ast::errors::*;
+use ast::ty::PrimitiveType;
+use super::{BinaryOperator, PrefixOperator};
+
+/// The value of a constant expression, worked out while typechecking
+/// so code generators don't have to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Constant {
+  Option(bool),
+  Text(Arc<str>),
+  LocalizedText(Arc<str>),
+  Integer(i64),
+  Decimal(f64),
+  /// In milliseconds.
+  TimeSpan(i64),
+  Array(Vec<Constant>),
+}
+
+impl Constant {
+  pub fn primitive_type(&self) -> PrimitiveType {
+    match *self {
+      Constant::Option(_) => PrimitiveType::Option,
+      Constant::Text(_) => PrimitiveType::Text,
+      Constant::LocalizedText(_) => PrimitiveType::LocalizedText,
+      Constant::Integer(_) => PrimitiveType::Integer,
+      Constant::Decimal(_) => PrimitiveType::Decimal,
+      Constant::TimeSpan(_) => PrimitiveType::TimeSpan,
+      Constant::Array(_) => PrimitiveType::Array,
+    }
+  }
+
+  fn as_decimal(&self) -> Option<f64> {
+    match *self {
+      Constant::Integer(i) => Some(i as f64),
+      Constant::Decimal(d) => Some(d),
+      _ => None,
+    }
+  }
+
+  pub fn prefix(operator: PrefixOperator, value: &Constant, span: &TokenSpan)
+    -> Result<Option<Constant>>
+  {
+    Ok(Some(match (operator, value) {
+      (PrefixOperator::Parens, value) => value.clone(),
+      (PrefixOperator::Not, &Constant::Option(o)) => Constant::Option(!o),
+      (PrefixOperator::Neg, &Constant::Integer(i)) => {
+        Constant::Integer(checked(i.checked_neg(), span)?)
+      }
+      (PrefixOperator::Neg, &Constant::Decimal(d)) => Constant::Decimal(-d),
+      (PrefixOperator::Neg, &Constant::TimeSpan(t)) => {
+        Constant::TimeSpan(checked(t.checked_neg(), span)?)
+      }
+      _ => return Ok(None),
+    }))
+  }
+
+  /// Returns `None` for operators that can't be folded. The operand
+  /// types have already been checked, so this only has to pick the
+  /// right arithmetic for them.
+  pub fn binary(
+    operator: BinaryOperator,
+    left: &Constant,
+    right: &Constant,
+    span: &TokenSpan,
+  ) -> Result<Option<Constant>>
+  {
+    use self::BinaryOperator as B;
+    use self::Constant as C;
+
+    Ok(Some(match (operator, left, right) {
+      (B::Add, &C::Text(ref l), &C::Text(ref r)) => {
+        C::Text(format!("{}{}", l, r).into())
+      }
+      (B::And, &C::Option(l), &C::Option(r)) => C::Option(l && r),
+      (B::Or, &C::Option(l), &C::Option(r)) => C::Option(l || r),
+
+      (B::Add, &C::TimeSpan(l), &C::TimeSpan(r)) => C::TimeSpan(checked(l.checked_add(r), span)?),
+      (B::Sub, &C::TimeSpan(l), &C::TimeSpan(r)) => C::TimeSpan(checked(l.checked_sub(r), span)?),
+      (B::Mul, &C::TimeSpan(t), &C::Integer(n)) | (B::Mul, &C::Integer(n), &C::TimeSpan(t)) => {
+        C::TimeSpan(checked(t.checked_mul(n), span)?)
+      }
+      (B::Mul, &C::TimeSpan(t), &C::Decimal(n)) | (B::Mul, &C::Decimal(n), &C::TimeSpan(t)) => {
+        C::TimeSpan(decimal_to_integer(t as f64 * n, span)?)
+      }
+      (B::Div, &C::TimeSpan(t), &C::Integer(n)) => {
+        if n == 0 {
+          return Err(ErrorKind::DivisionByZero(span.clone()).into());
+        }
+        C::TimeSpan(checked(t.checked_div(n), span)?)
+      }
+      (B::Div, &C::TimeSpan(t), &C::Decimal(n)) => {
+        if n == 0.0 {
+          return Err(ErrorKind::DivisionByZero(span.clone()).into());
+        }
+        C::TimeSpan(decimal_to_integer(t as f64 / n, span)?)
+      }
+      (B::Mod, &C::TimeSpan(l), &C::TimeSpan(r)) => {
+        if r == 0 {
+          return Err(ErrorKind::DivisionByZero(span.clone()).into());
+        }
+        C::TimeSpan(checked(l.checked_rem(r), span)?)
+      }
+      (B::Div, &C::TimeSpan(l), &C::TimeSpan(r)) => {
+        if r == 0 {
+          return Err(ErrorKind::DivisionByZero(span.clone()).into());
+        }
+        C::Decimal(l as f64 / r as f64)
+      }
+
+      (_, &C::Integer(l), &C::Integer(r)) => match integer_op(operator, l, r, span)? {
+        Some(value) => value,
+        None => return Ok(None),
+      },
+      (_, l, r) if l.as_decimal().is_some() && r.as_decimal().is_some() => {
+        match decimal_op(operator, l.as_decimal().unwrap(), r.as_decimal().unwrap(), span)? {
+          Some(value) => value,
+          None => return Ok(None),
+        }
+      }
+
+      (B::Eq, l, r) => C::Option(l == r),
+      (B::Ne, l, r) => C::Option(l != r),
+      (B::Lt, &C::Text(ref l), &C::Text(ref r)) => C::Option(l < r),
+      (B::Le, &C::Text(ref l), &C::Text(ref r)) => C::Option(l <= r),
+      (B::Gt, &C::Text(ref l), &C::Text(ref r)) => C::Option(l > r),
+      (B::Ge, &C::Text(ref l), &C::Text(ref r)) => C::Option(l >= r),
+      (B::Lt, &C::TimeSpan(l), &C::TimeSpan(r)) => C::Option(l < r),
+      (B::Le, &C::TimeSpan(l), &C::TimeSpan(r)) => C::Option(l <= r),
+      (B::Gt, &C::TimeSpan(l), &C::TimeSpan(r)) => C::Option(l > r),
+      (B::Ge, &C::TimeSpan(l), &C::TimeSpan(r)) => C::Option(l >= r),
+      _ => return Ok(None),
+    }))
+  }
+}
+
+impl Display for Constant {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Constant::Option(o) => f.write_str(if o { "yes" } else { "no" }),
+      Constant::Text(ref t) => write!(f, "'{}'", t),
+      Constant::LocalizedText(ref t) => write!(f, "localized '{}'", t),
+      Constant::Integer(i) => write!(f, "{}", i),
+      Constant::Decimal(d) => write!(f, "{}", d),
+      Constant::TimeSpan(t) => write!(f, "{} milliseconds", t),
+      Constant::Array(ref a) => {
+        f.write_str("[")?;
+        for (i, c) in a.iter().enumerate() {
+          if i > 0 { f.write_str(", ")?; }
+          write!(f, "{}", c)?;
+        }
+        f.write_str("]")
+      }
+    }
+  }
+}
+
+fn checked(value: Option<i64>, span: &TokenSpan) -> Result<i64> {
+  value.ok_or_else(|| ErrorKind::IntegerOutOfRange(span.clone()).into())
+}
+
+fn decimal_to_integer(value: f64, span: &TokenSpan) -> Result<i64> {
+  let value = value.round();
+  if value.is_nan() || value < i64::MIN as f64 || value >= i64::MAX as f64 {
+    Err(ErrorKind::IntegerOutOfRange(span.clone()).into())
+  } else {
+    Ok(value as i64)
+  }
+}
+
+fn integer_op(operator: BinaryOperator, l: i64, r: i64, span: &TokenSpan)
+  -> Result<Option<Constant>>
+{
+  use self::BinaryOperator as B;
+
+  let value = match operator {
+    B::Add => l.checked_add(r),
+    B::Sub => l.checked_sub(r),
+    B::Mul => l.checked_mul(r),
+    B::Div | B::Mod if r == 0 => {
+      return Err(ErrorKind::DivisionByZero(span.clone()).into());
+    }
+    B::Div => l.checked_div(r),
+    B::Mod => l.checked_rem(r),
+    B::Pow => {
+      if r < 0 {
+        return Err(ErrorKind::ValueOutOfRange(
+          r.to_string(),
+          "integers can't be raised to a negative power",
+          span.clone(),
+        ).into());
+      }
+      checked_pow(l, r)
+    }
+    B::Eq => return Ok(Some(Constant::Option(l == r))),
+    B::Ne => return Ok(Some(Constant::Option(l != r))),
+    B::Lt => return Ok(Some(Constant::Option(l < r))),
+    B::Le => return Ok(Some(Constant::Option(l <= r))),
+    B::Gt => return Ok(Some(Constant::Option(l > r))),
+    B::Ge => return Ok(Some(Constant::Option(l >= r))),
+    B::Dot | B::And | B::Or => return Ok(None),
+  };
+  checked(value, span).map(|i| Some(Constant::Integer(i)))
+}
+
+fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+  match base {
+    0 | 1 => return Some(if exponent == 0 { 1 } else { base }),
+    -1 => return Some(if exponent % 2 == 0 { 1 } else { -1 }),
+    _ => {}
+  }
+  // Anything else overflows long before this.
+  if exponent > 64 {
+    return None;
+  }
+  let mut result: i64 = 1;
+  for _ in 0..exponent {
+    result = match result.checked_mul(base) {
+      Some(result) => result,
+      None => return None,
+    };
+  }
+  Some(result)
+}
+
+fn decimal_op(operator: BinaryOperator, l: f64, r: f64, span: &TokenSpan)
+  -> Result<Option<Constant>>
+{
+  use self::BinaryOperator as B;
+
+  let value = match operator {
+    B::Add => l + r,
+    B::Sub => l - r,
+    B::Mul => l * r,
+    B::Div | B::Mod if r == 0.0 => {
+      return Err(ErrorKind::DivisionByZero(span.clone()).into());
+    }
+    B::Div => l / r,
+    B::Mod => l % r,
+    B::Pow => l.powf(r),
+    B::Eq => return Ok(Some(Constant::Option(l == r))),
+    B::Ne => return Ok(Some(Constant::Option(l != r))),
+    B::Lt => return Ok(Some(Constant::Option(l < r))),
+    B::Le => return Ok(Some(Constant::Option(l <= r))),
+    B::Gt => return Ok(Some(Constant::Option(l > r))),
+    B::Ge => return Ok(Some(Constant::Option(l >= r))),
+    B::Dot | B::And | B::Or => return Ok(None),
+  };
+  if value.is_finite() {
+    Ok(Some(Constant::Decimal(value)))
+  } else {
+    Err(ErrorKind::ValueOutOfRange(
+      value.to_string(),
+      "the result isn't a finite number",
+      span.clone(),
+    ).into())
+  }
+}
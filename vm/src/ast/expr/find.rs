@@ -0,0 +1,249 @@
+use std::sync::Arc;
+use std::fmt::{self, Display};
+use util::graph_cell::GraphRef;
+use util::later::Later;
+use compile::{TokenSpan, TokenValue};
+use ast::{Ast, SourceItem, ItemRef, Named};
+use ast::ty::{AmountRange, BaseCustomType, Collectable, Event, PrimitiveType, Type};
+use ast::stmt::expect_base_type;
+use ast::errors::*;
+use bc::{CodeBuilder, Instr};
+use query::{Query, UserFilter};
+use super::{Expression, BoxExpression, ExpressionKind, expect_primitive, expect_assignable};
+
+/// What a `find` expression looks for.
+#[derive(Debug, Serialize)]
+pub enum FindTarget<'a> {
+  /// `find event Chest.BeginOpen for chest.owner` is whether the
+  /// user has an instance of the event that hasn't finished yet.
+  Event {
+    event: ItemRef<'a, Event<'a>>,
+    owner: BoxExpression<'a>,
+  },
+  /// `find user Player with similar amount of collectable Level`
+  User {
+    user_type: ItemRef<'a, Type<'a>>,
+    filter: Option<FindUserFilter<'a>>,
+  },
+  /// `find gameserver with switch accepting = yes` is the server's id.
+  GameServer {
+    switch: TokenValue<Arc<str>>,
+    value: BoxExpression<'a>,
+  },
+}
+
+#[derive(Debug, Serialize)]
+pub enum FindUserFilter<'a> {
+  /// `with .level = 3`
+  Property {
+    name: TokenValue<Arc<str>>,
+    value: BoxExpression<'a>,
+  },
+  /// `with amount range 1 to 5 of collectable Level`
+  Amount {
+    collectable: ItemRef<'a, Collectable<'a>>,
+    amount: AmountRange,
+  },
+  /// `with similar amount of collectable Level`
+  SimilarAmount {
+    collectable: ItemRef<'a, Collectable<'a>>,
+  },
+}
+
+impl<'a> Display for FindUserFilter<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      FindUserFilter::Property { ref name, ref value } => {
+        write!(f, "with .{} = {}", name.value(), value)
+      }
+      FindUserFilter::Amount { ref collectable, ref amount } => {
+        write!(f, "with amount {} of collectable {}", amount, collectable.name().value())
+      }
+      FindUserFilter::SimilarAmount { ref collectable } => {
+        write!(f, "with similar amount of collectable {}", collectable.name().value())
+      }
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FindExpr<'a> {
+  target: FindTarget<'a>,
+  ty: Later<GraphRef<'a, Type<'a>>>,
+  span: TokenSpan,
+  #[serde(skip)]
+  ast: GraphRef<'a, Ast<'a>>,
+}
+
+impl<'a> FindExpr<'a> {
+  pub fn new(target: FindTarget<'a>, span: TokenSpan, ast: GraphRef<'a, Ast<'a>>) -> Self {
+    FindExpr {
+      target,
+      ty: Later::new(),
+      span,
+      ast,
+    }
+  }
+
+  pub fn target(&self) -> &FindTarget<'a> {
+    &self.target
+  }
+
+  /// The query this runs, with `operand` called on each expression
+  /// it needs the value of, in source order.
+  pub fn query<V, E, F>(&self, mut operand: F) -> ::std::result::Result<Query<V>, E>
+  where F: FnMut(&Expression<'a>) -> ::std::result::Result<V, E>
+  {
+    Ok(match self.target {
+      FindTarget::Event { ref event, ref owner } => Query::EventInstance {
+        event: event.name().value().clone(),
+        owner: operand(&**owner)?,
+      },
+      FindTarget::User { ref user_type, ref filter } => Query::User {
+        user_type: user_type.name().value().clone(),
+        filter: match *filter {
+          Some(FindUserFilter::Property { ref name, ref value }) => {
+            Some(UserFilter::Property {
+              name: name.value().clone(),
+              value: operand(&**value)?,
+            })
+          }
+          Some(FindUserFilter::Amount { ref collectable, ref amount }) => {
+            Some(UserFilter::Amount {
+              collectable: collectable.name().value().clone(),
+              min: amount.min(),
+              max: amount.max(),
+            })
+          }
+          Some(FindUserFilter::SimilarAmount { ref collectable }) => {
+            Some(UserFilter::SimilarAmount {
+              collectable: collectable.name().value().clone(),
+            })
+          }
+          None => None,
+        },
+      },
+      FindTarget::GameServer { ref switch, ref value } => Query::GameServer {
+        switch: switch.value().clone(),
+        value: operand(&**value)?,
+      },
+    })
+  }
+}
+
+impl<'a> Display for FindExpr<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.target {
+      FindTarget::Event { ref event, ref owner } => {
+        write!(f, "find event {} for {}", event.name().value(), owner)
+      }
+      FindTarget::User { ref user_type, ref filter } => {
+        write!(f, "find user {}", user_type.name().value())?;
+        if let Some(ref filter) = *filter {
+          write!(f, " {}", filter)?;
+        }
+        Ok(())
+      }
+      FindTarget::GameServer { ref switch, ref value } => {
+        write!(f, "find gameserver with switch {} = {}", switch.value(), value)
+      }
+    }
+  }
+}
+
+impl<'a> SourceItem for FindExpr<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    match self.target {
+      FindTarget::Event { ref mut event, ref mut owner } => {
+        event.resolve()?;
+        owner.resolve()
+      }
+      FindTarget::User { ref mut user_type, ref mut filter } => {
+        user_type.resolve()?;
+        match *filter {
+          Some(FindUserFilter::Property { ref mut value, .. }) => value.resolve(),
+          Some(FindUserFilter::Amount { ref mut collectable, .. })
+          | Some(FindUserFilter::SimilarAmount { ref mut collectable }) => collectable.resolve(),
+          None => Ok(()),
+        }
+      }
+      FindTarget::GameServer { ref mut value, .. } => value.resolve(),
+    }
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    let ty = match self.target {
+      FindTarget::Event { ref mut owner, .. } => {
+        owner.typecheck()?;
+        expect_base_type(
+          &**owner,
+          &[BaseCustomType::User, BaseCustomType::UserGroup],
+          "user",
+        )?;
+        self.ast.awake().primitive().option()
+      }
+      FindTarget::User { ref user_type, ref mut filter } => {
+        let ty = user_type.unwrap();
+        let base_type = ty.awake().as_custom().map(|c| c.base_type());
+        if base_type != Some(BaseCustomType::User)
+          && base_type != Some(BaseCustomType::UserGroup)
+        {
+          return Err(ErrorKind::TypeResolution(
+            "user".into(),
+            user_type.name().clone(),
+          ).into());
+        }
+        if let Some(FindUserFilter::Property { ref name, ref mut value }) = *filter {
+          value.typecheck()?;
+          let var = ty.awake().as_custom().and_then(|c| c.property(name.value()));
+          let var = match var {
+            Some(var) => var,
+            None => return Err(ErrorKind::NoProperty(
+              name.clone(),
+              user_type.name().value().clone(),
+            ).into()),
+          };
+          let var_ty = var.awake().ty();
+          expect_assignable(&mut **value, var_ty)?;
+        }
+        ty
+      }
+      FindTarget::GameServer { ref mut value, .. } => {
+        value.typecheck()?;
+        expect_primitive(
+          &**value,
+          &[PrimitiveType::Option, PrimitiveType::Text, PrimitiveType::Integer],
+        )?;
+        self.ast.awake().primitive().text()
+      }
+    };
+    Later::set(&mut self.ty, ty);
+    Ok(())
+  }
+}
+
+impl<'a> Expression<'a> for FindExpr<'a> {
+  fn kind(&self) -> ExpressionKind {
+    ExpressionKind::Find
+  }
+
+  fn ty(&self) -> GraphRef<'a, Type<'a>> {
+    *self.ty
+  }
+
+  fn is_constant(&self) -> bool {
+    false
+  }
+
+  /// The query's operands are left on the stack in source order.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    let query = self.query(|expr| code.expr(expr))?;
+    let index = code.query(query);
+    code.emit(Instr::Find(index));
+    Ok(())
+  }
+}
@@ -3,15 +3,22 @@ use serde::{Serialize, Serializer};
 use erased_serde::Serialize as ErasedSerialize;
 use util::graph_cell::GraphRef;
 use util::cast::*;
-use ast::SourceItem;
+use compile::TokenValue;
+use ast::{SourceItem, Named};
 use ast::var::{ScopeFilter, ScopeKind};
-use ast::ty::Type;
+use ast::ty::{PrimitiveType, Type};
+use ast::errors::*;
+use bc::CodeBuilder;
 
+mod constant;
 mod primary;
 mod oper;
+mod find;
 
+pub use self::constant::*;
 pub use self::primary::*;
 pub use self::oper::*;
+pub use self::find::*;
 
 #[derive(Debug, Serialize, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum ExpressionKind {
@@ -21,6 +28,8 @@ pub enum ExpressionKind {
   UnaryOp,
   BinaryOp,
   ListOp,
+  Call,
+  Find,
 }
 
 pub trait Expression<'a>
@@ -34,9 +43,37 @@ pub trait Expression<'a>
   fn kind(&self) -> ExpressionKind;
   fn ty(&self) -> GraphRef<'a, Type<'a>>;
   fn is_constant(&self) -> bool;
+  /// The folded value of a constant expression.
+  /// Only valid after the expression has been typechecked.
+  fn constant(&self) -> Option<&Constant> { None }
   fn precedence(&self) -> u8 { 0 }
+  /// Whether the expression names something that can be assigned to.
+  fn is_lvalue(&self) -> bool { false }
   fn set_scope_filter(&mut self, _filter: ScopeFilter<'a>) -> bool { false }
   fn set_scope_filter_kind(&mut self, _kind: ScopeKind) -> bool { false }
+  /// Marks a variable as a property, which is looked up while
+  /// typechecking since super types aren't known before then.
+  fn set_member(&mut self) -> bool { false }
+  /// Looks up a property marked by `set_member` on the type before the dot.
+  fn resolve_member(&mut self, _ty: &Type<'a>) -> Result<()> { Ok(()) }
+  /// Lets literals whose type depends on where they're used, like
+  /// `[1, 2]` and `{ key: value }`, take the type they're assigned to
+  /// if their contents fit it. Called after `typecheck`.
+  fn infer_type(&mut self, _expected: GraphRef<'a, Type<'a>>) -> Result<()> { Ok(()) }
+  /// Appends code that leaves the value on the stack. Use
+  /// `CodeBuilder::expr`, which takes care of constants.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()>;
+  /// Appends code that stores `value` here. Only lvalues support this.
+  fn compile_assign(&self, _code: &mut CodeBuilder, _value: &Expression<'a>) -> Result<()> {
+    Err(ErrorKind::NotAssignable(self.to_string(), self.span().clone()).into())
+  }
+  /// Appends code for a property after a dot, with the instance
+  /// on the stack. With a value, the property is set instead.
+  fn compile_member(&self, _code: &mut CodeBuilder, _value: Option<&Expression<'a>>)
+    -> Result<()>
+  {
+    Err(ErrorKind::InvalidExpression(self.to_string(), self.span().clone()).into())
+  }
 }
 
 pub type BoxExpression<'a> = Box<Expression<'a> + 'a>;
@@ -48,3 +85,52 @@ impl<'a> Serialize for Expression<'a> {
     self.cast().serialize(serializer)
   }
 }
+
+/// Fails with a type error unless the expression has type `expected`.
+/// Only valid after the expression has been typechecked.
+pub fn expect_type<'a>(expr: &Expression<'a>, expected: &Type<'a>) -> Result<()> {
+  let ty = expr.ty();
+  let ty = ty.awake();
+  if *ty == *expected {
+    Ok(())
+  } else {
+    Err(type_error(expr, &ty, expected.name().value().clone()))
+  }
+}
+
+/// `expect_type` for values assigned to something with a declared type.
+pub fn expect_assignable<'a>(expr: &mut Expression<'a>, expected: GraphRef<'a, Type<'a>>)
+  -> Result<()>
+{
+  expr.infer_type(expected)?;
+  expect_type(expr, &expected.awake())
+}
+
+/// Fails with a type error unless the expression has one of
+/// the primitive types in `allowed`.
+pub fn expect_primitive<'a>(expr: &Expression<'a>, allowed: &[PrimitiveType])
+  -> Result<PrimitiveType>
+{
+  let ty = expr.ty();
+  let ty = ty.awake();
+  match ty.as_primitive() {
+    Some(p) if allowed.contains(&p) => Ok(p),
+    _ => {
+      let expected = allowed
+        .iter()
+        .map(PrimitiveType::as_str)
+        .collect::<Vec<_>>()
+        .join(" or ");
+      Err(type_error(expr, &ty, expected.into()))
+    }
+  }
+}
+
+fn type_error<'a>(expr: &Expression<'a>, found: &Type<'a>, expected: ::std::sync::Arc<str>)
+  -> Error
+{
+  ErrorKind::TypeResolution(
+    expected,
+    TokenValue::new(found.name().value().clone(), expr.span().clone()),
+  ).into()
+}
@@ -2,11 +2,12 @@ use std::fmt::{self, Display};
 use util::later::Later;
 use util::graph_cell::GraphRef;
 use compile::{TokenValue, TokenSpan};
-use ast::{SourceItem, ItemRef};
-use ast::ty::Type;
-use ast::var::{ScopeFilter, ScopeKind, Scoped};
+use ast::{Ast, SourceItem, ItemRef, Named};
+use ast::ty::{Array, CastType, PrimitiveType, PrimitiveTypeSet, Type};
+use ast::var::ScopeKind;
 use ast::errors::*;
-use super::{Expression, BoxExpression, ExpressionKind};
+use bc::{CodeBuilder, Instr};
+use super::{Expression, BoxExpression, ExpressionKind, Constant, expect_primitive};
 
 #[derive(Debug, Serialize, Copy, Clone, PartialEq, Eq)]
 pub enum PrefixOperator {
@@ -53,7 +54,7 @@ impl Display for PrefixOperator {
   }
 }
 
-#[derive(Debug, Serialize, Copy, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 pub enum BinaryOperator {
   Dot,
   Mul,
@@ -121,6 +122,85 @@ impl BinaryOperator {
   pub fn right_recursive(&self) -> bool {
     *self == BinaryOperator::Pow
   }
+
+  /// The type of `left <op> right`, or `None` if the operator
+  /// doesn't apply to these operand types. Not valid for `Dot`.
+  ///
+  /// Integers are promoted to decimals when mixed with them. Time spans
+  /// can be added to each other and to date-times, scaled by numbers,
+  /// and the difference of two date-times is a time span. Options only
+  /// take `and`, `or` and equality.
+  pub fn result_type<'a>(
+    &self,
+    left: &Type<'a>,
+    right: &Type<'a>,
+    primitive: &PrimitiveTypeSet<'a>,
+  ) -> Option<GraphRef<'a, Type<'a>>>
+  {
+    use self::BinaryOperator as B;
+    use ast::ty::PrimitiveType as P;
+
+    let (l, r) = match (left.as_primitive(), right.as_primitive()) {
+      (Some(l), Some(r)) => (l, r),
+      // Custom types can only be compared for identity.
+      _ => return match *self {
+        B::Eq | B::Ne if left == right => Some(primitive.option()),
+        _ => None,
+      },
+    };
+    let is_number = |p: PrimitiveType| p == P::Integer || p == P::Decimal;
+
+    match *self {
+      B::Dot => None,
+      B::Add if l == P::Text && r == P::Text => Some(primitive.text()),
+      B::Add | B::Sub | B::Mod if l == P::TimeSpan && r == P::TimeSpan => {
+        Some(primitive.time_span())
+      }
+      B::Add if (l == P::DateTime && r == P::TimeSpan) || (l == P::TimeSpan && r == P::DateTime) => {
+        Some(primitive.date_time())
+      }
+      B::Sub if l == P::DateTime && r == P::TimeSpan => Some(primitive.date_time()),
+      B::Sub if l == P::DateTime && r == P::DateTime => Some(primitive.time_span()),
+      B::Mul if (l == P::TimeSpan && is_number(r)) || (is_number(l) && r == P::TimeSpan) => {
+        Some(primitive.time_span())
+      }
+      B::Div if l == P::TimeSpan && is_number(r) => Some(primitive.time_span()),
+      // How many times one time span fits into another.
+      B::Div if l == P::TimeSpan && r == P::TimeSpan => Some(primitive.decimal()),
+      B::Mul | B::Div | B::Mod | B::Pow | B::Add | B::Sub => {
+        if l == P::Integer && r == P::Integer {
+          Some(primitive.integer())
+        } else if is_number(l) && is_number(r) {
+          Some(primitive.decimal())
+        } else {
+          None
+        }
+      }
+      B::Eq | B::Ne => {
+        if l == r || (is_number(l) && is_number(r)) {
+          Some(primitive.option())
+        } else {
+          None
+        }
+      }
+      B::Lt | B::Le | B::Gt | B::Ge => {
+        if (is_number(l) && is_number(r))
+          || (l == r && (l == P::DateTime || l == P::TimeSpan || l == P::Text))
+        {
+          Some(primitive.option())
+        } else {
+          None
+        }
+      }
+      B::And | B::Or => {
+        if l == P::Option && r == P::Option {
+          Some(primitive.option())
+        } else {
+          None
+        }
+      }
+    }
+  }
 }
 
 impl Display for BinaryOperator {
@@ -163,14 +243,18 @@ impl Display for PostfixListOperator {
 pub struct PrefixExpr<'a> {
   operator: TokenValue<PrefixOperator>,
   subexpr: BoxExpression<'a>,
-  ty: Later<ItemRef<'a, Type<'a>>>,
+  ty: Later<GraphRef<'a, Type<'a>>>,
   span: TokenSpan,
+  value: Option<Constant>,
+  #[serde(skip)]
+  ast: GraphRef<'a, Ast<'a>>,
 }
 
 impl<'a> PrefixExpr<'a> {
   pub fn new(
     operator: TokenValue<PrefixOperator>,
     subexpr: BoxExpression<'a>,
+    ast: GraphRef<'a, Ast<'a>>,
   ) -> Self
   {
     let span = operator.span().from_to(subexpr.span());
@@ -179,6 +263,8 @@ impl<'a> PrefixExpr<'a> {
       subexpr,
       ty: Later::new(),
       span,
+      value: None,
+      ast,
     }
   }
 }
@@ -206,7 +292,8 @@ impl<'a> SourceItem for PrefixExpr<'a> {
   fn resolve(&mut self) -> Result<()> {
     if *self.operator.value() == PrefixOperator::Dot {
       if self.subexpr.kind() != ExpressionKind::Var
-        || !self.subexpr.set_scope_filter_kind(ScopeKind::TYPE)
+        || !self.subexpr.set_scope_filter_kind(ScopeKind::TYPE | ScopeKind::RECURSIVE)
+        || !self.subexpr.set_member()
       {
         return Err(ErrorKind::InvalidExpression(
           self.subexpr.to_string(),
@@ -218,6 +305,25 @@ impl<'a> SourceItem for PrefixExpr<'a> {
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    self.subexpr.typecheck()?;
+    let ty = match *self.operator.value() {
+      PrefixOperator::Parens | PrefixOperator::Dot => self.subexpr.ty(),
+      PrefixOperator::Not => {
+        expect_primitive(&*self.subexpr, &[PrimitiveType::Option])?;
+        self.subexpr.ty()
+      }
+      PrefixOperator::Neg => {
+        expect_primitive(
+          &*self.subexpr,
+          &[PrimitiveType::Integer, PrimitiveType::Decimal, PrimitiveType::TimeSpan],
+        )?;
+        self.subexpr.ty()
+      }
+    };
+    Later::set(&mut self.ty, ty);
+    if let Some(value) = self.subexpr.constant() {
+      self.value = Constant::prefix(*self.operator.value(), value, &self.span)?;
+    }
     Ok(())
   }
 }
@@ -228,12 +334,33 @@ impl<'a> Expression<'a> for PrefixExpr<'a> {
   }
 
   fn ty(&self) -> GraphRef<'a, Type<'a>> {
-    self.ty.item().unwrap()
+    *self.ty
   }
 
   fn is_constant(&self) -> bool {
     self.subexpr.is_constant()
   }
+
+  fn constant(&self) -> Option<&Constant> {
+    self.value.as_ref()
+  }
+
+  fn is_lvalue(&self) -> bool {
+    *self.operator.value() == PrefixOperator::Dot
+  }
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    code.expr(&*self.subexpr)?;
+    match *self.operator.value() {
+      PrefixOperator::Parens | PrefixOperator::Dot => return Ok(()),
+      PrefixOperator::Not => code.emit(Instr::Not),
+      PrefixOperator::Neg => code.emit(Instr::Neg),
+    };
+    Ok(())
+  }
+
+  fn compile_assign(&self, code: &mut CodeBuilder, value: &Expression<'a>) -> Result<()> {
+    self.subexpr.compile_assign(code, value)
+  }
 }
 
 #[derive(Debug, Serialize)]
@@ -241,8 +368,11 @@ pub struct BinaryExpr<'a> {
   operator: TokenValue<BinaryOperator>,
   left: BoxExpression<'a>,
   right: BoxExpression<'a>,
-  ty: Later<ItemRef<'a, Type<'a>>>,
+  ty: Later<GraphRef<'a, Type<'a>>>,
   span: TokenSpan,
+  value: Option<Constant>,
+  #[serde(skip)]
+  ast: GraphRef<'a, Ast<'a>>,
 }
 
 impl<'a> BinaryExpr<'a> {
@@ -250,6 +380,7 @@ impl<'a> BinaryExpr<'a> {
     operator: TokenValue<BinaryOperator>,
     left: BoxExpression<'a>,
     right: BoxExpression<'a>,
+    ast: GraphRef<'a, Ast<'a>>,
   ) -> Self
   {
     let span = left.span().from_to(right.span());
@@ -259,6 +390,8 @@ impl<'a> BinaryExpr<'a> {
       right,
       ty: Later::new(),
       span,
+      value: None,
+      ast,
     }
   }
 }
@@ -277,17 +410,9 @@ impl<'a> SourceItem for BinaryExpr<'a> {
   fn resolve(&mut self) -> Result<()> {
     self.left.resolve()?;
     // Special case: the '.' operator requires an `ExprVar` on the right,
-    // and changes its scope filter.
+    // which is looked up in the left side's type while typechecking.
     if *self.operator.value() == BinaryOperator::Dot {
-      if self.right.kind() != ExpressionKind::Var
-        || !{
-          let ty = self.left.ty();
-          let scope = ty.awake().scope();
-          let kind = scope.awake().kind();
-          let filter = ScopeFilter::new(scope, kind);
-          self.right.set_scope_filter(filter)
-        }
-      {
+      if self.right.kind() != ExpressionKind::Var || !self.right.set_member() {
         return Err(ErrorKind::InvalidExpression(
           self.right.to_string(),
           self.right.span().clone(),
@@ -298,6 +423,34 @@ impl<'a> SourceItem for BinaryExpr<'a> {
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    self.left.typecheck()?;
+    if *self.operator.value() == BinaryOperator::Dot {
+      let ty = self.left.ty();
+      self.right.resolve_member(&ty.awake())?;
+    }
+    self.right.typecheck()?;
+    let operator = *self.operator.value();
+    let ty = if operator == BinaryOperator::Dot {
+      self.right.ty()
+    } else {
+      let left = self.left.ty();
+      let left = left.awake();
+      let right = self.right.ty();
+      let right = right.awake();
+      let ast = self.ast.awake();
+      match operator.result_type(&left, &right, ast.primitive()) {
+        Some(ty) => ty,
+        None => return Err(ErrorKind::InvalidOperands(
+          TokenValue::new(operator.as_str(), self.operator.span().clone()),
+          TokenValue::new(left.name().value().clone(), self.left.span().clone()),
+          TokenValue::new(right.name().value().clone(), self.right.span().clone()),
+        ).into()),
+      }
+    };
+    Later::set(&mut self.ty, ty);
+    if let (Some(left), Some(right)) = (self.left.constant(), self.right.constant()) {
+      self.value = Constant::binary(operator, left, right, &self.span)?;
+    }
     Ok(())
   }
 }
@@ -308,11 +461,52 @@ impl<'a> Expression<'a> for BinaryExpr<'a> {
   }
 
   fn ty(&self) -> GraphRef<'a, Type<'a>> {
-    self.ty.item().unwrap()
+    *self.ty
   }
 
   fn is_constant(&self) -> bool {
-    self.left.is_constant() && self.right.is_constant()
+    self.operator.value() != &BinaryOperator::Dot
+      && self.left.is_constant()
+      && self.right.is_constant()
+  }
+
+  fn constant(&self) -> Option<&Constant> {
+    self.value.as_ref()
+  }
+
+  fn is_lvalue(&self) -> bool {
+    *self.operator.value() == BinaryOperator::Dot && self.right.is_lvalue()
+  }
+  /// `and` and `or` skip the right side when the left
+  /// side already decides the result.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    code.expr(&*self.left)?;
+    let operator = *self.operator.value();
+    match operator {
+      BinaryOperator::Dot => return self.right.compile_member(code, None),
+      BinaryOperator::And | BinaryOperator::Or => {
+        code.emit(Instr::Dup);
+        let jump = code.emit(if operator == BinaryOperator::And {
+          Instr::JumpUnless(0)
+        } else {
+          Instr::JumpIf(0)
+        });
+        code.emit(Instr::Pop);
+        code.expr(&*self.right)?;
+        code.patch(jump);
+      }
+      _ => {
+        code.expr(&*self.right)?;
+        code.emit(Instr::Binary(operator));
+      }
+    }
+    Ok(())
+  }
+
+  /// Only property access is an lvalue.
+  fn compile_assign(&self, code: &mut CodeBuilder, value: &Expression<'a>) -> Result<()> {
+    code.expr(&*self.left)?;
+    self.right.compile_member(code, Some(value))
   }
 }
 
@@ -323,6 +517,7 @@ pub struct PostfixListExpr<'a> {
   right: Vec<BoxExpression<'a>>,
   ty: Later<ItemRef<'a, Type<'a>>>,
   span: TokenSpan,
+  value: Option<Constant>,
 }
 
 impl<'a> PostfixListExpr<'a> {
@@ -339,6 +534,7 @@ impl<'a> PostfixListExpr<'a> {
       right,
       ty: Later::new(),
       span,
+      value: None,
     }
   }
 }
@@ -369,7 +565,49 @@ impl<'a> SourceItem for PostfixListExpr<'a> {
     Ok(())
   }
 
+  /// Indexing takes one integer and needs to know the element type,
+  /// so it only works on typed arrays. Calls to a function's name are
+  /// parsed as `ExprCall`, so any call left here isn't valid.
   fn typecheck(&mut self) -> Result<()> {
+    self.left.typecheck()?;
+    for e in &mut self.right {
+      e.typecheck()?;
+    }
+    if *self.operator.value() == PostfixListOperator::Call || self.right.len() != 1 {
+      return Err(ErrorKind::InvalidExpression(
+        self.to_string(),
+        self.span.clone(),
+      ).into());
+    }
+    expect_primitive(&*self.right[0], &[PrimitiveType::Integer])?;
+    let left_ty = self.left.ty();
+    let element_type = left_ty
+      .awake()
+      .as_custom()
+      .and_then(Array::try_cast)
+      .and_then(Array::element_type);
+    match element_type {
+      Some(ty) => {
+        let name = ty.awake().name().clone();
+        Later::set(&mut self.ty, ItemRef::with_item(name, ty));
+      }
+      None => return Err(ErrorKind::TypeResolution(
+        "typed array".into(),
+        TokenValue::new(left_ty.awake().name().value().clone(), self.left.span().clone()),
+      ).into()),
+    }
+    if let (Some(&Constant::Array(ref array)), Some(&Constant::Integer(index)))
+      = (self.left.constant(), self.right[0].constant())
+    {
+      if index < 0 || index as usize >= array.len() {
+        return Err(ErrorKind::ValueOutOfRange(
+          index.to_string(),
+          "index past the end of the array",
+          self.right[0].span().clone(),
+        ).into());
+      }
+      self.value = Some(array[index as usize].clone());
+    }
     Ok(())
   }
 }
@@ -384,6 +622,20 @@ impl<'a> Expression<'a> for PostfixListExpr<'a> {
   }
 
   fn is_constant(&self) -> bool {
-    false
+    *self.operator.value() == PostfixListOperator::Idx
+      && self.left.is_constant()
+      && self.right.iter().all(|e| e.is_constant())
+  }
+
+  fn constant(&self) -> Option<&Constant> {
+    self.value.as_ref()
+  }
+
+  /// Calls here don't typecheck, so this is always an index.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    code.expr(&*self.left)?;
+    code.expr(&*self.right[0])?;
+    code.emit(Instr::Index);
+    Ok(())
   }
 }
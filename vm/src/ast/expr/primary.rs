@@ -7,7 +7,8 @@ use compile::{TokenSpan, TokenValue};
 //use ast::var::{Scope, Variable};
 //use ast::ty::{PrimitiveType, Type};
 use ast::*;
-use super::{Expression, ExpressionKind, BoxExpression};
+use bc::{CodeBuilder, Instr};
+use super::{Expression, ExpressionKind, BoxExpression, Constant, expect_assignable};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
 #[repr(u16)]
@@ -45,6 +46,21 @@ impl TimeSpanUnit {
       TimeSpanUnit::Years => 5,
     }
   }
+
+  /// Length of one unit in milliseconds. Months and years
+  /// use 30 and 365 days.
+  pub fn milliseconds(&self) -> i64 {
+    match *self {
+      TimeSpanUnit::Milliseconds => 1,
+      TimeSpanUnit::Seconds => 1000,
+      TimeSpanUnit::Minutes => 60 * 1000,
+      TimeSpanUnit::Hours => 60 * 60 * 1000,
+      TimeSpanUnit::Days => 24 * 60 * 60 * 1000,
+      TimeSpanUnit::Weeks => 7 * 24 * 60 * 60 * 1000,
+      TimeSpanUnit::Months => 30 * 24 * 60 * 60 * 1000,
+      TimeSpanUnit::Years => 365 * 24 * 60 * 60 * 1000,
+    }
+  }
 }
 
 impl Display for TimeSpanUnit {
@@ -101,6 +117,10 @@ impl TimeSpanPart {
     self.unit
   }
 
+  pub fn milliseconds(&self) -> i64 {
+    self.amount as i64 * self.unit.milliseconds()
+  }
+
   pub fn span(&self) -> &TokenSpan {
     &self.span
   }
@@ -111,6 +131,7 @@ pub struct ExprVar<'a> {
   name: TokenValue<Arc<str>>,
   scope_filter: ScopeFilter<'a>,
   var: Later<GraphRef<'a, Variable<'a>>>,
+  member: bool,
 }
 
 impl<'a> ExprVar<'a> {
@@ -123,7 +144,24 @@ impl<'a> ExprVar<'a> {
       name,
       scope_filter,
       var: Later::new(),
+      member: false,
+    }
+  }
+
+  /// The variable this refers to. Only valid after typechecking.
+  pub fn var(&self) -> GraphRef<'a, Variable<'a>> {
+    *self.var
+  }
+
+  fn load_self(&self, code: &mut CodeBuilder) -> Result<()> {
+    if !code.has_self() {
+      return Err(ErrorKind::InvalidExpression(
+        format!(".{}", self.name.value()),
+        self.span().clone(),
+      ).into());
     }
+    code.emit(Instr::LoadSelf);
+    Ok(())
   }
 }
 
@@ -139,14 +177,26 @@ impl<'a> SourceItem for ExprVar<'a> {
   }
 
   fn resolve(&mut self) -> Result<()> {
+    if self.member {
+      return Ok(());
+    }
     match self.scope_filter.find(&self.name) {
       Some(v) => Ok(self.var.set(v)),
       None => Err(ErrorKind::NotDefined(self.name.clone(), "variable").into()),
     }
   }
 
+  /// A member without a type before the dot is a property of the
+  /// implicit instance, found in the enclosing type scopes. These
+  /// include collectable groups' scopes once their members are resolved.
   fn typecheck(&mut self) -> Result<()> {
-    Ok(())
+    if !self.member || self.var.is_set() {
+      return Ok(());
+    }
+    match self.scope_filter.find(&self.name) {
+      Some(v) => Ok(self.var.set(v)),
+      None => Err(ErrorKind::NotDefined(self.name.clone(), "property").into()),
+    }
   }
 }
 
@@ -163,6 +213,10 @@ impl<'a> Expression<'a> for ExprVar<'a> {
     false
   }
 
+  fn is_lvalue(&self) -> bool {
+    true
+  }
+
   fn set_scope_filter(&mut self, filter: ScopeFilter<'a>) -> bool {
     self.scope_filter = filter;
     true
@@ -172,6 +226,179 @@ impl<'a> Expression<'a> for ExprVar<'a> {
     self.scope_filter.set_kind(kind);
     true
   }
+
+  fn set_member(&mut self) -> bool {
+    self.member = true;
+    true
+  }
+
+  fn resolve_member(&mut self, ty: &Type<'a>) -> Result<()> {
+    match ty.as_custom().and_then(|ty| ty.property(&self.name)) {
+      Some(v) => Ok(self.var.set(v)),
+      None => Err(ErrorKind::NoProperty(self.name.clone(), ty.name().value().clone()).into()),
+    }
+  }
+
+  /// Variables are parameters if there's one with the name,
+  /// otherwise globals. Members without a type before the dot are
+  /// properties of the instance an initializer is running for.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    if self.member {
+      self.load_self(code)?;
+      return self.compile_member(code, None);
+    }
+    match code.local(self.name.value()) {
+      Some(slot) => code.emit(Instr::LoadLocal(slot)),
+      None => {
+        let name = code.name(self.name.value());
+        code.emit(Instr::LoadGlobal(name))
+      }
+    };
+    Ok(())
+  }
+
+  fn compile_assign(&self, code: &mut CodeBuilder, value: &Expression<'a>) -> Result<()> {
+    if self.member {
+      self.load_self(code)?;
+      return self.compile_member(code, Some(value));
+    }
+    code.expr(value)?;
+    match code.local(self.name.value()) {
+      Some(slot) => code.emit(Instr::StoreLocal(slot)),
+      None => {
+        let name = code.name(self.name.value());
+        code.emit(Instr::StoreGlobal(name))
+      }
+    };
+    Ok(())
+  }
+
+  fn compile_member(&self, code: &mut CodeBuilder, value: Option<&Expression<'a>>)
+    -> Result<()>
+  {
+    let name = code.name(self.name.value());
+    match value {
+      Some(value) => {
+        code.expr(value)?;
+        code.emit(Instr::SetProperty(name));
+      }
+      None => {
+        code.emit(Instr::GetProperty(name));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// A call to a function declared in the program. Calls can only
+/// name the function directly, so they're parsed as primaries.
+#[derive(Debug, Serialize)]
+pub struct ExprCall<'a> {
+  function: ItemRef<'a, Function<'a>>,
+  args: Vec<BoxExpression<'a>>,
+  ty: Later<GraphRef<'a, Type<'a>>>,
+  span: TokenSpan,
+  #[serde(skip)]
+  ast: GraphRef<'a, Ast<'a>>,
+}
+
+impl<'a> ExprCall<'a> {
+  pub fn new(
+    function: ItemRef<'a, Function<'a>>,
+    args: Vec<BoxExpression<'a>>,
+    span: TokenSpan,
+    ast: GraphRef<'a, Ast<'a>>,
+  ) -> Self
+  {
+    ExprCall {
+      function,
+      args,
+      ty: Later::new(),
+      span,
+      ast,
+    }
+  }
+}
+
+impl<'a> Display for ExprCall<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let args = self.args.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+    write!(f, "{}({})", self.function, args.join(", "))
+  }
+}
+
+impl<'a> SourceItem for ExprCall<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.function.resolve()?;
+    for arg in &mut self.args {
+      arg.resolve()?;
+    }
+    Ok(())
+  }
+
+  /// The function being typechecked is borrowed, so
+  /// a function can't call itself directly.
+  fn typecheck(&mut self) -> Result<()> {
+    for arg in &mut self.args {
+      arg.typecheck()?;
+    }
+    let function = self.function.unwrap();
+    if function.is_awake_mut() {
+      return Err(ErrorKind::InvalidExpression(
+        self.to_string(),
+        self.span.clone(),
+      ).into());
+    }
+    let function = function.awake();
+    let params = function.params();
+    if params.len() != self.args.len() {
+      return Err(ErrorKind::ArgumentCount(
+        self.function.name().clone(),
+        params.len(),
+        self.args.len(),
+      ).into());
+    }
+    let scope = function.param_scope();
+    let scope = scope.awake();
+    for (param, arg) in params.iter().zip(&mut self.args) {
+      let var = scope.find_filtered(param.value(), ScopeKind::FN_PARAM).unwrap();
+      let ty = var.awake().ty();
+      expect_assignable(&mut **arg, ty)?;
+    }
+    let ty = match function.return_type() {
+      Some(ty) => ty.unwrap(),
+      None => self.ast.awake().primitive().void(),
+    };
+    Later::set(&mut self.ty, ty);
+    Ok(())
+  }
+}
+
+impl<'a> Expression<'a> for ExprCall<'a> {
+  fn kind(&self) -> ExpressionKind {
+    ExpressionKind::Call
+  }
+
+  fn ty(&self) -> GraphRef<'a, Type<'a>> {
+    *self.ty
+  }
+
+  fn is_constant(&self) -> bool {
+    false
+  }
+
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    for arg in &self.args {
+      code.expr(&**arg)?;
+    }
+    let name = code.name(self.function.name().value());
+    code.emit(Instr::Call(name, self.args.len() as u16));
+    Ok(())
+  }
 }
 
 #[derive(Debug, Serialize)]
@@ -218,8 +445,24 @@ impl<'a> Display for Literal<'a> {
         }
         Ok(())
       }
-      Literal::Object(ref _o) => unimplemented!(),
-      Literal::Array(ref _a) => unimplemented!(),
+      Literal::Object(ref o) => {
+        let mut keys = o.keys().collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.value().cmp(b.value()));
+        f.write_str("{")?;
+        for (i, key) in keys.into_iter().enumerate() {
+          if i > 0 { f.write_str(",")?; }
+          write!(f, " {}: {}", key.value(), o[key])?;
+        }
+        f.write_str(" }")
+      }
+      Literal::Array(ref a) => {
+        f.write_str("[")?;
+        for (i, expr) in a.iter().enumerate() {
+          if i > 0 { f.write_str(", ")?; }
+          write!(f, "{}", expr)?;
+        }
+        f.write_str("]")
+      }
     }
   }
 }
@@ -228,11 +471,56 @@ impl<'a> Display for Literal<'a> {
 pub struct ExprLiteral<'a> {
   literal: Literal<'a>,
   ty: GraphRef<'a, Type<'a>>,
+  span: TokenSpan,
+  value: Option<Constant>,
 }
 
 impl<'a> ExprLiteral<'a> {
   pub fn new(literal: Literal<'a>, ty: GraphRef<'a, Type<'a>>) -> Self {
-    ExprLiteral { literal, ty }
+    let span = match literal {
+      Literal::Option(ref o) => o.span().clone(),
+      Literal::Text(ref t) => t.span().clone(),
+      Literal::LocalizedText(ref t) => t.span().clone(),
+      Literal::Integer(ref i) => i.span().clone(),
+      Literal::Decimal(ref d) => d.span().clone(),
+      Literal::TimeSpan(ref ts) => ts[0].span().from_to(ts[ts.len() - 1].span()),
+      Literal::Object(_) | Literal::Array(_) => unreachable!("use with_span"),
+    };
+    ExprLiteral { literal, ty, span, value: None }
+  }
+
+  /// For literals whose span isn't covered by their parts,
+  /// like the brackets around arrays and objects.
+  pub fn with_span(literal: Literal<'a>, ty: GraphRef<'a, Type<'a>>, span: TokenSpan) -> Self {
+    ExprLiteral { literal, ty, span, value: None }
+  }
+
+  pub fn literal(&self) -> &Literal<'a> {
+    &self.literal
+  }
+
+  fn fold(&self) -> Option<Constant> {
+    Some(match self.literal {
+      Literal::Option(ref o) => Constant::Option(*o.value()),
+      Literal::Text(ref t) => Constant::Text(t.value().clone()),
+      Literal::LocalizedText(ref t) => Constant::LocalizedText(t.value().clone()),
+      Literal::Integer(ref i) => Constant::Integer(*i.value()),
+      Literal::Decimal(ref d) => Constant::Decimal(*d.value()),
+      Literal::TimeSpan(ref parts) => {
+        Constant::TimeSpan(parts.iter().map(TimeSpanPart::milliseconds).sum())
+      }
+      Literal::Object(_) => return None,
+      Literal::Array(ref a) => {
+        let mut values = Vec::with_capacity(a.len());
+        for expr in a {
+          match expr.constant() {
+            Some(value) => values.push(value.clone()),
+            None => return None,
+          }
+        }
+        Constant::Array(values)
+      }
+    })
   }
 }
 
@@ -244,17 +532,7 @@ impl<'a> Display for ExprLiteral<'a> {
 
 impl<'a> SourceItem for ExprLiteral<'a> {
   fn span(&self) -> &TokenSpan {
-    match self.literal {
-      Literal::Option(ref o) => o.span(),
-      Literal::Text(ref t) => t.span(),
-      Literal::LocalizedText(ref t) => t.span(),
-      Literal::Integer(ref i) => i.span(),
-      Literal::Decimal(ref d) => d.span(),
-      // FIXME!
-      Literal::TimeSpan(ref ts) => ts[0].span(),
-      Literal::Object(ref _o) => unimplemented!(),
-      Literal::Array(ref _a) => unimplemented!(),
-    }
+    &self.span
   }
 
   fn resolve(&mut self) -> Result<()> {
@@ -280,6 +558,7 @@ impl<'a> SourceItem for ExprLiteral<'a> {
         expr.typecheck()?;
       }
     }
+    self.value = self.fold();
     Ok(())
   }
 }
@@ -295,8 +574,99 @@ impl<'a> Expression<'a> for ExprLiteral<'a> {
 
   fn is_constant(&self) -> bool {
     match self.literal {
-      Literal::Object(_) | Literal::Array(_) => false,
+      Literal::Object(_) => false,
+      Literal::Array(ref a) => a.iter().all(|e| e.is_constant()),
       _ => true,
     }
   }
+
+  fn constant(&self) -> Option<&Constant> {
+    self.value.as_ref()
+  }
+
+  /// Arrays fit typed arrays whose element type each element is
+  /// assignable to, within the array's length, and objects fit object
+  /// types that have a property for each key. The primitive `array`
+  /// and `object` types take anything.
+  fn infer_type(&mut self, expected: GraphRef<'a, Type<'a>>) -> Result<()> {
+    let expected_ty = expected.awake();
+    match self.literal {
+      Literal::Array(ref mut a) => {
+        if expected_ty.as_primitive() == Some(PrimitiveType::Array) {
+          self.ty = expected;
+          return Ok(());
+        }
+        let array = match expected_ty.as_custom().and_then(Array::try_cast) {
+          Some(array) => array,
+          None => return Ok(()),
+        };
+        if let Some(max_length) = array.max_length() {
+          if a.len() > max_length as usize {
+            return Err(ErrorKind::ValueOutOfRange(
+              format!("{} elements", a.len()),
+              "the array type has a maximum length",
+              self.span.clone(),
+            ).into());
+          }
+        }
+        if let Some(element_type) = array.element_type() {
+          for expr in a {
+            expect_assignable(&mut **expr, element_type)?;
+          }
+        }
+      }
+      Literal::Object(ref mut o) => {
+        if expected_ty.as_primitive() == Some(PrimitiveType::Object) {
+          self.ty = expected;
+          return Ok(());
+        }
+        let object = match expected_ty.as_custom().and_then(Object::try_cast) {
+          Some(object) => object,
+          None => return Ok(()),
+        };
+        for (key, expr) in o.iter_mut() {
+          let var = match object.property(key.value()) {
+            Some(var) => var,
+            None => return Err(ErrorKind::NoProperty(
+              key.clone(),
+              object.name().value().clone(),
+            ).into()),
+          };
+          let var_ty = var.awake().ty();
+          expect_assignable(&mut **expr, var_ty)?;
+        }
+      }
+      _ => return Ok(()),
+    }
+    self.ty = expected;
+    Ok(())
+  }
+
+  /// Only arrays and objects with non-constant parts get here.
+  /// Object keys go in sorted order.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    match self.literal {
+      Literal::Array(ref a) => {
+        for expr in a {
+          code.expr(&**expr)?;
+        }
+        code.emit(Instr::MakeArray(a.len() as u32));
+      }
+      Literal::Object(ref o) => {
+        let mut keys = o.keys().collect::<Vec<_>>();
+        keys.sort_by(|a, b| a.value().cmp(b.value()));
+        for key in &keys {
+          let index = code.constant(Constant::Text(key.value().clone()));
+          code.emit(Instr::Const(index));
+          code.expr(&*o[*key])?;
+        }
+        code.emit(Instr::MakeObject(keys.len() as u32));
+      }
+      _ => {
+        let index = code.constant(self.fold().expect("scalar literals are constant"));
+        code.emit(Instr::Const(index));
+      }
+    }
+    Ok(())
+  }
 }
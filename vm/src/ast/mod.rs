@@ -8,10 +8,12 @@ use util::{SharedStrings, InsertGraphCell};
 use util::graph_cell::*;
 use util::later::Later;
 use compile::{TokenSpan, TokenValue};
+use strings::StringTable;
 
 pub mod ty;
 pub mod var;
 pub mod expr;
+pub mod stmt;
 
 use self::ty::*;
 use self::var::*;
@@ -203,9 +205,13 @@ pub struct Ast<'a> {
   #[serde(skip)]
   primitive_types: Later<PrimitiveTypeSet<'a>>,
   #[serde(skip)]
+  any_user: Later<GraphRef<'a, Type<'a>>>,
+  #[serde(skip)]
   array_names: FxHashMap<ArrayName, Arc<str>>,
   scope: GraphCell<Scope<'a>>,
   strings: SharedStrings,
+  #[serde(skip)]
+  localized_strings: StringTable,
   /// The path "(internal)" for things with no code location.
   #[serde(skip)]
   internal_path: Arc<PathBuf>,
@@ -216,12 +222,14 @@ impl<'a> Ast<'a> {
     let ast = box GraphCell::new(Ast {
       types: Default::default(),
       primitive_types: Later::new(),
+      any_user: Later::new(),
       array_names: Default::default(),
       scope: Scope::new(
         ScopeKind::GLOBAL,
         TokenSpan::new(Arc::new(Path::new("(global)").into())),
       ),
       strings: SharedStrings::new(),
+      localized_strings: StringTable::new(),
       internal_path: Arc::new(Path::new("(internal)").into()),
     });
     {
@@ -237,6 +245,16 @@ impl<'a> Ast<'a> {
       let primitive_types = PrimitiveTypeSet::new(&ast_ref.types);
       Later::set(&mut ast_ref.primitive_types, primitive_types);
     }
+    {
+      // "user" is a keyword, so no program can define a type with this name.
+      let name = ast.awake().shared_string("user");
+      let span = TokenSpan::new(ast.awake().internal_path());
+      UserGroup::new(TokenValue::new(name.clone(), span), ast.asleep_mut())
+        .expect("only primitive types are defined yet");
+      let mut ast_ref = ast.awake_mut();
+      let any_user = ast_ref.types[&name].asleep();
+      Later::set(&mut ast_ref.any_user, any_user);
+    }
     ast
   }
 
@@ -244,30 +262,75 @@ impl<'a> Ast<'a> {
     self.strings.get(s)
   }
 
+  /// Every `localized` literal in the program.
+  pub fn localized_strings(&self) -> &StringTable {
+    &self.localized_strings
+  }
+
+  /// Records a use of a localized string and returns its key.
+  pub fn insert_localized_string(&mut self, text: Arc<str>, span: TokenSpan) -> Arc<str> {
+    self.localized_strings.insert(text, span)
+  }
+
   pub fn internal_path(&self) -> Arc<PathBuf> {
     self.internal_path.clone()
   }
 
-  fn resolution_step<F>(&self, step: F) -> Result<()>
+  /// Every type in the program, including the primitive types.
+  pub fn types<'s>(&'s self) -> impl Iterator<Item = GraphRef<'a, Type<'a>>> + 's {
+    self.types.values().map(|t| t.asleep())
+  }
+
+  fn resolution_step<F>(&self, step: F, errors: &mut Vec<Error>)
   where F: Fn(&mut (SourceItem + 'a)) -> Result<()>
   {
     for ty in self.types.values() {
-      (step)(&mut *ty.awake_mut())?;
+      if let Err(e) = (step)(&mut *ty.awake_mut()) {
+        errors.push(e);
+      }
+    }
+    // Global variables can refer to each other, so the
+    // scope itself can't stay borrowed.
+    let vars = self.scope.awake().own_vars_mut();
+    for var in vars {
+      if let Err(e) = (step)(&mut *var.awake_mut()) {
+        errors.push(e);
+      }
     }
-    (step)(&mut *self.scope.awake_mut())
   }
 
-  pub fn typecheck(&self) -> Result<()> {
+  /// Returns the errors from every type that failed. Typechecking
+  /// is skipped if any references couldn't be resolved.
+  pub fn typecheck(&self) -> ::std::result::Result<(), Vec<Error>> {
+    let mut errors = Vec::new();
     trace!("Resolve references");
-    self.resolution_step(SourceItem::resolve)?;
-    trace!("Typecheck");
-    self.resolution_step(SourceItem::typecheck)
+    self.resolution_step(SourceItem::resolve, &mut errors);
+    if errors.is_empty() {
+      trace!("Typecheck");
+      self.resolution_step(SourceItem::typecheck, &mut errors);
+      // Either group can give a precedence, so it's
+      // checked once every group has been seen.
+      if let Err(e) = check_precedence(self.types()) {
+        errors.push(e);
+      }
+    }
+    if errors.is_empty() {
+      Ok(())
+    } else {
+      Err(errors)
+    }
   }
 
   pub fn primitive(&self) -> &PrimitiveTypeSet<'a> {
     &self.primitive_types
   }
 
+  /// The built-in user group that every user is in,
+  /// for values that can hold any type of user.
+  pub fn any_user(&self) -> GraphRef<'a, Type<'a>> {
+    *self.any_user
+  }
+
   pub fn insert_type<T>(this: GraphRefMut<'a, Ast<'a>>, ty: T)
     -> Result<GraphRefMut<'a, Type<'a>>>
   where T: CustomType<'a> + CastType<'a> + 'a
@@ -277,15 +340,22 @@ impl<'a> Ast<'a> {
     let gr = this
       .awake_mut()
       .types
-      .insert_graph_cell(name, Type::Custom(Box::new(ty)));
+      .insert_graph_cell(name.clone(), Type::Custom(Box::new(ty)));
     let type_ref = match gr {
       Ok(type_ref) => type_ref,
-      Err(ty) => return Err(
-        ErrorKind::DuplicateDefinition(
-          ty.name().clone(),
-          ty.item_name(),
-        ).into()
-      ),
+      Err(ty) => {
+        let first = this.awake().types
+          .get(&name)
+          .map(|t| t.awake().name().span().clone())
+          .unwrap_or_default();
+        return Err(
+          ErrorKind::DuplicateDefinition(
+            ty.name().clone(),
+            ty.item_name(),
+            first,
+          ).into()
+        );
+      }
     };
     let t_mut = type_ref.map(|r| T::cast_mut(r.as_custom_mut().unwrap()));
     let t_ref = t_mut.asleep_ref();
@@ -386,23 +456,6 @@ where
 
 impl_scoped!('a, Ast<'a>);
 
-/*
-pub struct Upgrade {
-  pub cost: GrpRef<Collectable, CollectableGroup>,
-  pub cost_amount: Expression,
-  pub self_amount_range: Range,
-}
-
-pub enum Redemption {
-  ForCurrency,
-  ForCollectable {
-    self_amount: u32,
-    cost: GrpRef<Collectable, CollectableGroup>,
-    cost_amount: u32,
-  }
-}
-*/
-
 mod errors {
   // ?????
   #![allow(unused_doc_comment)]
@@ -421,9 +474,15 @@ mod errors {
         display("{}: no definition for {} '{}'", name.span(), typ, name.value())
       }
 
-      DuplicateDefinition(name: TokenValue<Arc<str>>, typ: &'static str) {
+      DuplicateDefinition(name: TokenValue<Arc<str>>, typ: &'static str, first: TokenSpan) {
         description("item already defined")
-        display("{}: {} '{}' already defined", name.span(), typ, name.value())
+        display(
+          "{}: {} '{}' already defined (first defined at {})",
+          name.span(),
+          typ,
+          name.value(),
+          &first
+        )
       }
 
       TypeResolution(expected: Arc<str>, found: TokenValue<Arc<str>>) {
@@ -461,6 +520,102 @@ mod errors {
         description("value out of range")
         display("{}: value '{}' out of range: {}", &location, &value, reason)
       }
+
+      // The operands' values are their type names.
+      InvalidOperands(
+        operator: TokenValue<&'static str>,
+        left: TokenValue<Arc<str>>,
+        right: TokenValue<Arc<str>>
+      )
+      {
+        description("invalid operand types")
+        display(
+          "{}: operator '{}' can't be applied to types '{}' and '{}'",
+          operator.span(),
+          operator.value(),
+          left.value(),
+          right.value()
+        )
+      }
+
+      RangeOverlap(what: &'static str, first: TokenSpan, second: TokenSpan) {
+        description("overlapping ranges")
+        display("{}: {} range overlaps with the range at {}", &second, what, &first)
+      }
+
+      RangeGap(what: &'static str, missing: String, before: TokenSpan, after: TokenSpan) {
+        description("gap between ranges")
+        display(
+          "{}: {} ranges leave out {} (between here and {})",
+          &before,
+          what,
+          &missing,
+          &after
+        )
+      }
+
+      NotInGroup(item: TokenValue<Arc<str>>, group: Arc<str>) {
+        description("collectable not in group")
+        display("{}: '{}' is not in collectable group '{}'", item.span(), item.value(), &group)
+      }
+
+      InvalidStatement(reason: &'static str, location: TokenSpan) {
+        description("statement not allowed here")
+        display("{}: statement not allowed here: {}", &location, reason)
+      }
+
+      NotAssignable(expr: String, location: TokenSpan) {
+        description("expression is not assignable")
+        display("{}: can't assign to '{}'", &location, &expr)
+      }
+
+      IntegerOutOfRange(location: TokenSpan) {
+        description("integer out of range")
+        display("{}: integer out of range", &location)
+      }
+
+      DivisionByZero(location: TokenSpan) {
+        description("division by zero")
+        display("{}: division by zero", &location)
+      }
+
+      // Each group is higher than the one after it, and the last one is the
+      // first again. Names after the first are where that order was given.
+      PrecedenceCycle(chain: Vec<TokenValue<Arc<str>>>) {
+        description("cyclic user group precedence")
+        display(
+          "{}: precedence cycle: {}",
+          chain[0].span(),
+          chain.iter().map(|n| &**n.value()).collect::<Vec<_>>().join(" -> ")
+        )
+      }
+
+      MissingDefault(ty: TokenValue<Arc<str>>, property: TokenValue<Arc<str>>) {
+        description("inherited property has no value")
+        display(
+          "{}: '{}' needs a default for property '{}' (declared at {})",
+          ty.span(),
+          ty.value(),
+          property.value(),
+          property.span()
+        )
+      }
+
+      NoProperty(name: TokenValue<Arc<str>>, ty: Arc<str>) {
+        description("property not found")
+        display("{}: no property `{}` on type {}", name.span(), name.value(), &ty)
+      }
+
+      ArgumentCount(function: TokenValue<Arc<str>>, expected: usize, found: usize) {
+        description("wrong number of arguments")
+        display(
+          "{}: '{}' takes {} argument(s), but {} were given",
+          function.span(),
+          function.value(),
+          expected,
+          found
+        )
+      }
     }
   }
 }
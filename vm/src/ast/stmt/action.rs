@@ -0,0 +1,448 @@
+use std::fmt::{self, Display};
+use compile::{TokenSpan, TokenValue};
+use ast::{SourceItem, ItemRef};
+use ast::ty::{BaseCustomType, Collectable, PrimitiveType};
+use ast::expr::{BoxExpression, Constant, expect_primitive, expect_assignable};
+use ast::errors::*;
+use bc::{CodeBuilder, Instr};
+use super::{Statement, StatementKind, TypeOrExpr, expect_base_type};
+
+/// `assert <condition>;`
+#[derive(Debug, Serialize)]
+pub struct AssertStmt<'a> {
+  condition: BoxExpression<'a>,
+  span: TokenSpan,
+}
+
+impl<'a> AssertStmt<'a> {
+  pub fn new(condition: BoxExpression<'a>, span: TokenSpan) -> Self {
+    AssertStmt { condition, span }
+  }
+
+  pub fn condition(&self) -> &BoxExpression<'a> {
+    &self.condition
+  }
+}
+
+impl<'a> Display for AssertStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "assert {}", self.condition)
+  }
+}
+
+impl<'a> SourceItem for AssertStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.condition.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    self.condition.typecheck()?;
+    expect_primitive(&*self.condition, &[PrimitiveType::Option])?;
+    Ok(())
+  }
+}
+
+impl<'a> Statement<'a> for AssertStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Assert
+  }
+
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    code.expr(&*self.condition)?;
+    code.emit(Instr::Assert);
+    Ok(())
+  }
+}
+
+/// `authorize <user or user group>;`
+#[derive(Debug, Serialize)]
+pub struct AuthorizeStmt<'a> {
+  target: TypeOrExpr<'a>,
+  span: TokenSpan,
+}
+
+impl<'a> AuthorizeStmt<'a> {
+  pub fn new(target: TypeOrExpr<'a>, span: TokenSpan) -> Self {
+    AuthorizeStmt { target, span }
+  }
+
+  pub fn target(&self) -> &TypeOrExpr<'a> {
+    &self.target
+  }
+}
+
+impl<'a> Display for AuthorizeStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "authorize {}", self.target)
+  }
+}
+
+impl<'a> SourceItem for AuthorizeStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.target.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    self.target.typecheck()?;
+    self.target.expect_base_type(
+      &[BaseCustomType::User, BaseCustomType::UserGroup],
+      "user or user group",
+    )
+  }
+}
+
+impl<'a> Statement<'a> for AuthorizeStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Authorize
+  }
+
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    self.target.compile_authorize(code)
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub enum AwardSign {
+  Add,
+  Remove,
+}
+
+impl AwardSign {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      AwardSign::Add => "+",
+      AwardSign::Remove => "-",
+    }
+  }
+}
+
+/// `award [+|-]<item> [x <amount>] [to <user>];`
+#[derive(Debug, Serialize)]
+pub struct AwardStmt<'a> {
+  sign: TokenValue<AwardSign>,
+  item: TypeOrExpr<'a>,
+  amount: Option<BoxExpression<'a>>,
+  target: Option<BoxExpression<'a>>,
+  span: TokenSpan,
+}
+
+impl<'a> AwardStmt<'a> {
+  pub fn new(
+    sign: TokenValue<AwardSign>,
+    item: TypeOrExpr<'a>,
+    amount: Option<BoxExpression<'a>>,
+    target: Option<BoxExpression<'a>>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    AwardStmt { sign, item, amount, target, span }
+  }
+
+  pub fn sign(&self) -> AwardSign {
+    *self.sign.value()
+  }
+
+  pub fn item(&self) -> &TypeOrExpr<'a> {
+    &self.item
+  }
+
+  pub fn amount(&self) -> Option<&BoxExpression<'a>> {
+    self.amount.as_ref()
+  }
+
+  /// If there is no target, the award goes to the sender.
+  pub fn target(&self) -> Option<&BoxExpression<'a>> {
+    self.target.as_ref()
+  }
+}
+
+impl<'a> Display for AwardStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "award {}{}", self.sign.value().as_str(), self.item)?;
+    if let Some(ref amount) = self.amount {
+      write!(f, " x {}", amount)?;
+    }
+    if let Some(ref target) = self.target {
+      write!(f, " to {}", target)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'a> SourceItem for AwardStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.item.resolve()?;
+    if let Some(ref mut amount) = self.amount {
+      amount.resolve()?;
+    }
+    if let Some(ref mut target) = self.target {
+      target.resolve()?;
+    }
+    Ok(())
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    self.item.typecheck()?;
+    self.item.expect_base_type(
+      &[
+        BaseCustomType::Collectable,
+        BaseCustomType::CollectableGroup,
+        BaseCustomType::Distribution,
+      ],
+      "collectable or distribution",
+    )?;
+    if let Some(ref mut amount) = self.amount {
+      amount.typecheck()?;
+      expect_primitive(&**amount, &[PrimitiveType::Integer])?;
+    }
+    if let Some(ref mut target) = self.target {
+      target.typecheck()?;
+      expect_base_type(
+        &**target,
+        &[BaseCustomType::User, BaseCustomType::UserGroup],
+        "user or user group",
+      )?;
+    }
+    Ok(())
+  }
+}
+
+impl<'a> Statement<'a> for AwardStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Award
+  }
+
+  /// An instance, then the amount, then the target go on the stack.
+  /// The amount defaults to 1 and the target to the sender.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    if let TypeOrExpr::Expr(ref item) = self.item {
+      code.expr(&**item)?;
+    }
+    match self.amount {
+      Some(ref amount) => code.expr(&**amount)?,
+      None => {
+        let one = code.constant(Constant::Integer(1));
+        code.emit(Instr::Const(one));
+      }
+    }
+    match self.target {
+      Some(ref target) => code.expr(&**target)?,
+      None => {
+        code.emit(Instr::LoadSender);
+      }
+    }
+    let sign = *self.sign.value();
+    match self.item {
+      TypeOrExpr::Type(ref ty) => {
+        let name = code.name(ty.name().value());
+        code.emit(Instr::AwardType(sign, name));
+      }
+      TypeOrExpr::Expr(_) => {
+        code.emit(Instr::AwardInstance(sign));
+      }
+    }
+    Ok(())
+  }
+}
+
+/// `cost <collectable> x <amount>;`
+#[derive(Debug, Serialize)]
+pub struct CostStmt<'a> {
+  collectable: ItemRef<'a, Collectable<'a>>,
+  amount: BoxExpression<'a>,
+  span: TokenSpan,
+}
+
+impl<'a> CostStmt<'a> {
+  pub fn new(
+    collectable: ItemRef<'a, Collectable<'a>>,
+    amount: BoxExpression<'a>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    CostStmt { collectable, amount, span }
+  }
+
+  pub fn collectable(&self) -> &ItemRef<'a, Collectable<'a>> {
+    &self.collectable
+  }
+
+  pub fn amount(&self) -> &BoxExpression<'a> {
+    &self.amount
+  }
+}
+
+impl<'a> Display for CostStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "cost {} x {}", self.collectable, self.amount)
+  }
+}
+
+impl<'a> SourceItem for CostStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.collectable.resolve()?;
+    self.amount.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    self.amount.typecheck()?;
+    expect_primitive(&*self.amount, &[PrimitiveType::Integer])?;
+    Ok(())
+  }
+}
+
+impl<'a> Statement<'a> for CostStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Cost
+  }
+
+  /// The user has to agree to pay.
+  fn is_wait(&self) -> bool {
+    true
+  }
+
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    code.expr(&*self.amount)?;
+    let name = code.name(self.collectable.name().value());
+    code.emit(Instr::Cost(name));
+    Ok(())
+  }
+}
+
+/// `timer <time span>;`
+#[derive(Debug, Serialize)]
+pub struct TimerStmt<'a> {
+  duration: BoxExpression<'a>,
+  span: TokenSpan,
+}
+
+impl<'a> TimerStmt<'a> {
+  pub fn new(duration: BoxExpression<'a>, span: TokenSpan) -> Self {
+    TimerStmt { duration, span }
+  }
+
+  pub fn duration(&self) -> &BoxExpression<'a> {
+    &self.duration
+  }
+}
+
+impl<'a> Display for TimerStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "timer {}", self.duration)
+  }
+}
+
+impl<'a> SourceItem for TimerStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.duration.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    self.duration.typecheck()?;
+    expect_primitive(&*self.duration, &[PrimitiveType::TimeSpan])?;
+    Ok(())
+  }
+}
+
+impl<'a> Statement<'a> for TimerStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Timer
+  }
+
+  fn is_wait(&self) -> bool {
+    true
+  }
+
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    code.expr(&*self.duration)?;
+    code.emit(Instr::Timer);
+    Ok(())
+  }
+}
+
+/// `[set] <target> = <value>;`
+#[derive(Debug, Serialize)]
+pub struct SetStmt<'a> {
+  target: BoxExpression<'a>,
+  value: BoxExpression<'a>,
+  span: TokenSpan,
+}
+
+impl<'a> SetStmt<'a> {
+  pub fn new(
+    target: BoxExpression<'a>,
+    value: BoxExpression<'a>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    SetStmt { target, value, span }
+  }
+
+  pub fn target(&self) -> &BoxExpression<'a> {
+    &self.target
+  }
+
+  pub fn value(&self) -> &BoxExpression<'a> {
+    &self.value
+  }
+}
+
+impl<'a> Display for SetStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "set {} = {}", self.target, self.value)
+  }
+}
+
+impl<'a> SourceItem for SetStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    if !self.target.is_lvalue() {
+      return Err(ErrorKind::NotAssignable(
+        self.target.to_string(),
+        self.target.span().clone(),
+      ).into());
+    }
+    self.target.resolve()?;
+    self.value.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    self.target.typecheck()?;
+    self.value.typecheck()?;
+    let ty = self.target.ty();
+    expect_assignable(&mut *self.value, ty)
+  }
+}
+
+impl<'a> Statement<'a> for SetStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Set
+  }
+
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    self.target.compile_assign(code, &*self.value)
+  }
+}
@@ -0,0 +1,88 @@
+use std::fmt::{self, Display};
+use compile::TokenSpan;
+use ast::SourceItem;
+use ast::errors::*;
+use bc::{CodeBuilder, Instr};
+use super::{Statement, StatementKind, BoxStatement, fmt_block, resolve_block, typecheck_block};
+
+/// ```text
+/// option:
+///   <statements>
+/// or:
+///   <statements>
+/// end;
+/// ```
+///
+/// Runs whichever branch gets through its first wait (e.g. a timer
+/// or the user agreeing to a cost) first.
+#[derive(Debug, Serialize)]
+pub struct OptionStmt<'a> {
+  branches: Vec<Vec<BoxStatement<'a>>>,
+  span: TokenSpan,
+}
+
+impl<'a> OptionStmt<'a> {
+  pub fn new(branches: Vec<Vec<BoxStatement<'a>>>, span: TokenSpan) -> Self {
+    OptionStmt { branches, span }
+  }
+
+  pub fn branches(&self) -> &[Vec<BoxStatement<'a>>] {
+    &self.branches
+  }
+}
+
+impl<'a> Display for OptionStmt<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    for (i, branch) in self.branches.iter().enumerate() {
+      f.write_str(if i == 0 { "option:" } else { " or:" })?;
+      fmt_block(f, branch)?;
+    }
+    f.write_str(" end")
+  }
+}
+
+impl<'a> SourceItem for OptionStmt<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    for branch in &mut self.branches {
+      resolve_block(branch)?;
+    }
+    Ok(())
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    for branch in &mut self.branches {
+      typecheck_block(branch)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'a> Statement<'a> for OptionStmt<'a> {
+  fn kind(&self) -> StatementKind {
+    StatementKind::Option
+  }
+
+  fn is_wait(&self) -> bool {
+    true
+  }
+
+  /// Each branch ends by jumping past the option.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()> {
+    let table = code.branch_table();
+    code.emit(Instr::Option(table));
+    let mut ends = Vec::with_capacity(self.branches.len());
+    for branch in &self.branches {
+      code.start_branch(table);
+      code.block(branch)?;
+      ends.push(code.emit(Instr::EndBranch(0)));
+    }
+    for end in ends {
+      code.patch(end);
+    }
+    Ok(())
+  }
+}
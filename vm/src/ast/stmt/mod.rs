@@ -0,0 +1,186 @@
+use std::fmt::{self, Debug, Display};
+use serde::{Serialize, Serializer};
+use erased_serde::Serialize as ErasedSerialize;
+use util::cast::*;
+use compile::{TokenSpan, TokenValue};
+use ast::{SourceItem, ItemRef, Named};
+use ast::ty::{BaseCustomType, CustomType};
+use ast::expr::{Expression, BoxExpression};
+use ast::errors::*;
+use bc::{CodeBuilder, Instr};
+
+mod action;
+mod flow;
+
+pub use self::action::*;
+pub use self::flow::*;
+
+#[derive(Debug, Serialize, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StatementKind {
+  Assert,
+  Authorize,
+  Award,
+  Cost,
+  Timer,
+  Set,
+  Option,
+}
+
+pub trait Statement<'a>
+  : Debug
+  + Display
+  + ErasedSerialize
+  + Cast<ErasedSerialize + 'a>
+  + SourceItem
+  + 'a
+{
+  fn kind(&self) -> StatementKind;
+  /// Whether execution may have to wait at this statement
+  /// for something outside of the program to happen.
+  fn is_wait(&self) -> bool { false }
+  /// Appends the statement's code. Use `CodeBuilder::block`,
+  /// which keeps track of source positions.
+  fn compile(&self, code: &mut CodeBuilder) -> Result<()>;
+}
+
+pub type BoxStatement<'a> = Box<Statement<'a> + 'a>;
+
+impl<'a> Serialize for Statement<'a> {
+  fn serialize<S: Serializer>(&self, serializer: S)
+    -> ::std::result::Result<S::Ok, S::Error>
+  {
+    self.cast().serialize(serializer)
+  }
+}
+
+/// Statements like `award` and `authorize` take either a named
+/// type (`award +SmallChestRewards`) or an expression that
+/// evaluates to an instance (`award -chest`). Names that are
+/// variables in scope when the statement is parsed are expressions,
+/// everything else is looked up as a type.
+#[derive(Debug, Serialize)]
+pub enum TypeOrExpr<'a> {
+  Type(ItemRef<'a, CustomType<'a>>),
+  Expr(BoxExpression<'a>),
+}
+
+impl<'a> TypeOrExpr<'a> {
+  /// Only valid after the resolve phase has succeeded.
+  pub fn base_type(&self) -> Option<BaseCustomType> {
+    match *self {
+      TypeOrExpr::Type(ref t) => Some(t.unwrap().awake().base_type()),
+      TypeOrExpr::Expr(ref e) => {
+        let ty = e.ty();
+        let base_type = ty.awake().as_custom().map(|c| c.base_type());
+        base_type
+      }
+    }
+  }
+
+  /// Fails with a type error unless the type or the type of the
+  /// expression is one of `allowed`.
+  pub fn expect_base_type(&self, allowed: &[BaseCustomType], expected: &'static str)
+    -> Result<()>
+  {
+    match *self {
+      TypeOrExpr::Type(ref t) => {
+        let base_type = t.unwrap().awake().base_type();
+        if allowed.contains(&base_type) {
+          Ok(())
+        } else {
+          Err(ErrorKind::TypeResolution(expected.into(), t.name().clone()).into())
+        }
+      }
+      TypeOrExpr::Expr(ref e) => expect_base_type(&**e, allowed, expected),
+    }
+  }
+
+  /// Appends a check that the event's sender is the user or in the
+  /// group named by the type, or is the instance the expression gives.
+  pub fn compile_authorize(&self, code: &mut CodeBuilder) -> Result<()> {
+    match *self {
+      TypeOrExpr::Type(ref t) => {
+        let name = code.name(t.name().value());
+        code.emit(Instr::AuthorizeType(name));
+      }
+      TypeOrExpr::Expr(ref e) => {
+        code.expr(&**e)?;
+        code.emit(Instr::Authorize);
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Fails with a type error unless the expression is an instance
+/// of a custom type with one of the `allowed` base types.
+pub fn expect_base_type<'a>(
+  expr: &Expression<'a>,
+  allowed: &[BaseCustomType],
+  expected: &'static str,
+) -> Result<()>
+{
+  let ty = expr.ty();
+  let ty = ty.awake();
+  match ty.as_custom().map(|c| c.base_type()) {
+    Some(ref base_type) if allowed.contains(base_type) => Ok(()),
+    _ => Err(ErrorKind::TypeResolution(
+      expected.into(),
+      TokenValue::new(ty.name().value().clone(), expr.span().clone()),
+    ).into()),
+  }
+}
+
+impl<'a> Display for TypeOrExpr<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      TypeOrExpr::Type(ref t) => f.write_str(t.name().value()),
+      TypeOrExpr::Expr(ref e) => Display::fmt(e, f),
+    }
+  }
+}
+
+impl<'a> SourceItem for TypeOrExpr<'a> {
+  fn span(&self) -> &TokenSpan {
+    match *self {
+      TypeOrExpr::Type(ref t) => t.name().span(),
+      TypeOrExpr::Expr(ref e) => e.span(),
+    }
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    match *self {
+      TypeOrExpr::Type(ref mut t) => t.resolve(),
+      TypeOrExpr::Expr(ref mut e) => e.resolve(),
+    }
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    match *self {
+      TypeOrExpr::Type(_) => Ok(()),
+      TypeOrExpr::Expr(ref mut e) => e.typecheck(),
+    }
+  }
+}
+
+fn fmt_block<'a>(f: &mut fmt::Formatter, block: &[BoxStatement<'a>]) -> fmt::Result {
+  for stmt in block {
+    write!(f, " {};", stmt)?;
+  }
+  Ok(())
+}
+
+/// A sequence of statements, e.g. an event body or an option branch.
+pub fn resolve_block<'a>(block: &mut [BoxStatement<'a>]) -> Result<()> {
+  for stmt in block {
+    stmt.resolve()?;
+  }
+  Ok(())
+}
+
+pub fn typecheck_block<'a>(block: &mut [BoxStatement<'a>]) -> Result<()> {
+  for stmt in block {
+    stmt.typecheck()?;
+  }
+  Ok(())
+}
@@ -53,6 +53,15 @@ impl<'a> Array<'a> {
       scope: Scope::child(parent_scope, ScopeKind::TYPE, span)
     }
   }
+
+  /// Only valid after resolve phase has succeeded.
+  pub fn element_type(&self) -> Option<GraphRef<'a, Type<'a>>> {
+    self.ty.as_ref().map(|ty| ty.unwrap())
+  }
+
+  pub fn max_length(&self) -> Option<u32> {
+    self.max_length
+  }
 }
 
 type_macros!(
@@ -70,6 +79,9 @@ impl<'a> SourceItem for Array<'a> {
   }
 
   fn resolve(&mut self) -> Result<()> {
+    if let Some(ref mut ty) = self.ty {
+      ty.resolve()?;
+    }
     Ok(())
   }
 
@@ -1,10 +1,13 @@
 use std::sync::Arc;
-use fxhash::FxHashMap;
+use std::fmt::{self, Display};
+use std::i64;
+use fxhash::{FxHashMap, FxHashSet};
 use util::graph_cell::*;
 use util::later::Later;
 use util::{InsertUnique};
 use compile::{TokenSpan, TokenValue};
-use ast::var::Variable;
+use ast::var::{Variable, DefaultValue, PropertyKind};
+use ast::expr::{BoxExpression, expect_primitive};
 use super::*;
 
 /// When auto grouping is on, you can only own
@@ -34,8 +37,9 @@ pub struct CollectableGroup<'ast> {
   collectables: FxHashMap<Arc<str>, ItemRefMut<'ast, Collectable<'ast>>>,
   sub_groups: FxHashMap<Arc<str>, ItemRefMut<'ast, CollectableGroup<'ast>>>,
 
-  upgrades: Option<Vec<Upgrade>>,
-  redemptions: Option<Vec<Redemption>>,
+  defaults: Vec<DefaultValue<'ast>>,
+  upgrades: Option<Vec<Upgrade<'ast>>>,
+  redemptions: Option<Vec<Redemption<'ast>>>,
 }
 
 impl<'ast> CollectableGroup<'ast> {
@@ -51,14 +55,20 @@ impl<'ast> CollectableGroup<'ast> {
         self_ref: Later::new(),
         auto_grouping: AutoGrouping::Inherit,
         parent: None,
-        scope: Scope::child(parent_scope, ScopeKind::TYPE | ScopeKind::RECURSIVE, span),
+        scope: Scope::child(
+          parent_scope,
+          ScopeKind::TYPE | ScopeKind::RECURSIVE,
+          span.clone(),
+        ),
         collectables: Default::default(),
         sub_groups: Default::default(),
+        defaults: Vec::new(),
         upgrades: None,
         redemptions: None,
       }
     )?;
     Later::set(&mut cg.awake_mut().self_ref, cg.asleep_ref());
+    insert_implicit_properties(cg.awake().scope_mut(), ast.asleep_ref(), span)?;
     Ok(cg)
   }
 
@@ -71,32 +81,50 @@ impl<'ast> CollectableGroup<'ast> {
   }
 
   pub fn insert_collectable_ref(&mut self, r: ItemRefMut<'ast, Collectable<'ast>>) -> Result<()> {
-    self.collectables
+    let collectables = &mut self.collectables;
+    collectables
       .insert_unique(r.name().value().clone(), r)
-      .map_err(|(_, r)|
+      .map_err(|(name, r)|
         ErrorKind::DuplicateDefinition(
-          r.name().clone(), "collectable"
+          r.name().clone(), "collectable", collectables[&name].name().span().clone()
         ).into()
       )
   }
 
   pub fn insert_group_ref(&mut self, r: ItemRefMut<'ast, CollectableGroup<'ast>>) -> Result<()> {
-    self.sub_groups
+    let sub_groups = &mut self.sub_groups;
+    sub_groups
       .insert_unique(r.name().value().clone(), r)
-      .map_err(|(_, r)|
+      .map_err(|(name, r)|
         ErrorKind::DuplicateDefinition(
-          r.name().clone(), "collectable group"
+          r.name().clone(), "collectable group", sub_groups[&name].name().span().clone()
         ).into()
       )
   }
 
-  pub fn insert_upgrades(&mut self, upgrades: Vec<Upgrade>) {
+  pub fn insert_default(&mut self, default: DefaultValue<'ast>) -> Result<()> {
+    insert_default(&mut self.defaults, default)
+  }
+
+  pub fn defaults(&self) -> &[DefaultValue<'ast>] {
+    &self.defaults
+  }
+
+  pub fn insert_upgrades(&mut self, upgrades: Vec<Upgrade<'ast>>) {
     self.upgrades = Some(upgrades);
   }
 
-  pub fn insert_redemptions(&mut self, redemptions: Vec<Redemption>) {
+  pub fn upgrades(&self) -> Option<&[Upgrade<'ast>]> {
+    self.upgrades.as_ref().map(Vec::as_slice)
+  }
+
+  pub fn insert_redemptions(&mut self, redemptions: Vec<Redemption<'ast>>) {
     self.redemptions = Some(redemptions);
   }
+
+  pub fn redemptions(&self) -> Option<&[Redemption<'ast>]> {
+    self.redemptions.as_ref().map(Vec::as_slice)
+  }
 }
 
 type_macros!(
@@ -117,17 +145,46 @@ impl<'ast> SourceItem for CollectableGroup<'ast> {
     for g in self.sub_groups.values_mut() {
       g.resolve()?;
       let g = g.unwrap();
-      g.awake_mut().set_super_type(*self.self_ref)?;
+      let mut g = g.awake_mut();
+      g.set_super_type(*self.self_ref)?;
+      g.scope_mut().awake_mut().inherit(self.scope.asleep());
     }
     for c in self.collectables.values_mut() {
       c.resolve()?;
       let c = c.unwrap();
-      c.awake_mut().set_super_type(*self.self_ref)?;
+      let mut c = c.awake_mut();
+      c.set_super_type(*self.self_ref)?;
+      c.scope_mut().awake_mut().inherit(self.scope.asleep());
+    }
+    Scope::resolve_vars(&self.scope)?;
+    for default in &mut self.defaults {
+      default.resolve()?;
+    }
+    if let Some(ref mut upgrades) = self.upgrades {
+      for upgrade in upgrades {
+        upgrade.resolve()?;
+      }
+    }
+    if let Some(ref mut redemptions) = self.redemptions {
+      for redemption in redemptions {
+        redemption.resolve()?;
+      }
     }
     Ok(())
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    Scope::typecheck_vars(&self.scope)?;
+    typecheck_defaults(&mut self.defaults, self.parent)?;
+    let (scope, parent) = (&self.scope, self.parent);
+    if let Some(ref mut upgrades) = self.upgrades {
+      typecheck_upgrades(upgrades, &|name| find_property(scope, parent, name))?;
+    }
+    if let Some(ref mut redemptions) = self.redemptions {
+      for redemption in redemptions {
+        redemption.typecheck()?;
+      }
+    }
     Ok(())
   }
 }
@@ -146,7 +203,7 @@ impl<'ast> CustomType<'ast> for CollectableGroup<'ast> {
   }
 
   fn property(&self, name: &str) -> Option<GraphRef<'ast, Variable<'ast>>> {
-    self.scope.awake().find(name)
+    find_property(&self.scope, self.parent, name)
   }
 
   fn is_sub_type_of(&self, _ty: &CustomType<'ast>) -> bool {
@@ -170,8 +227,9 @@ pub struct Collectable<'ast> {
   parent: Option<GraphRef<'ast, CollectableGroup<'ast>>>,
   auto_grouping: AutoGrouping,
   scope: GraphCell<Scope<'ast>>,
-  upgrades: Option<Vec<Upgrade>>,
-  redemptions: Option<Vec<Redemption>>,
+  defaults: Vec<DefaultValue<'ast>>,
+  upgrades: Option<Vec<Upgrade<'ast>>>,
+  redemptions: Option<Vec<Redemption<'ast>>>,
 }
 
 impl<'ast> Collectable<'ast> {
@@ -183,14 +241,21 @@ impl<'ast> Collectable<'ast> {
   {
     let parent_scope = ast.awake().scope();
     let span = name.span().clone();
-    Ast::insert_cast_type(ast, Collectable {
+    let c = Ast::insert_cast_type(ast, Collectable {
       name,
       parent: None,
       auto_grouping: AutoGrouping::Inherit,
-      scope: Scope::child(parent_scope, ScopeKind::TYPE | ScopeKind::RECURSIVE, span),
+      scope: Scope::child(
+        parent_scope,
+        ScopeKind::TYPE | ScopeKind::RECURSIVE,
+        span.clone(),
+      ),
+      defaults: Vec::new(),
       upgrades: None,
       redemptions: None,
-    })
+    })?;
+    insert_implicit_properties(c.awake().scope_mut(), ast.asleep_ref(), span)?;
+    Ok(c)
   }
 
   pub fn auto_grouping(&self) -> AutoGrouping {
@@ -201,13 +266,62 @@ impl<'ast> Collectable<'ast> {
     self.auto_grouping = auto_grouping;
   }
 
-  pub fn insert_upgrades(&mut self, upgrades: Vec<Upgrade>) {
+  pub fn insert_default(&mut self, default: DefaultValue<'ast>) -> Result<()> {
+    insert_default(&mut self.defaults, default)
+  }
+
+  pub fn defaults(&self) -> &[DefaultValue<'ast>] {
+    &self.defaults
+  }
+
+  /// Every inherited property without an initial value needs a
+  /// default here or in a group between here and where it's declared.
+  fn check_inherited_values(&self) -> Result<()> {
+    let mut provided = self.defaults
+      .iter()
+      .map(|d| d.name().value().clone())
+      .collect::<FxHashSet<_>>();
+    provided.extend(self.scope.awake().own_vars().map(|v| v.awake().name().value().clone()));
+    let mut parent = self.parent;
+    while let Some(p) = parent {
+      let p = p.awake();
+      provided.extend(p.defaults.iter().map(|d| d.name().value().clone()));
+      for var in p.scope.awake().own_vars() {
+        let var = var.awake();
+        if provided.contains(var.name().value()) {
+          continue;
+        }
+        let has_value = var.initial().is_some() || match *var.kind() {
+          PropertyKind::Stored => false,
+          PropertyKind::Lookup(_) => true,
+        };
+        if !has_value {
+          return Err(ErrorKind::MissingDefault(
+            self.name.clone(),
+            var.name().clone(),
+          ).into());
+        }
+      }
+      parent = p.super_type();
+    }
+    Ok(())
+  }
+
+  pub fn insert_upgrades(&mut self, upgrades: Vec<Upgrade<'ast>>) {
     self.upgrades = Some(upgrades);
   }
 
-  pub fn insert_redemptions(&mut self, redemptions: Vec<Redemption>) {
+  pub fn upgrades(&self) -> Option<&[Upgrade<'ast>]> {
+    self.upgrades.as_ref().map(Vec::as_slice)
+  }
+
+  pub fn insert_redemptions(&mut self, redemptions: Vec<Redemption<'ast>>) {
     self.redemptions = Some(redemptions);
   }
+
+  pub fn redemptions(&self) -> Option<&[Redemption<'ast>]> {
+    self.redemptions.as_ref().map(Vec::as_slice)
+  }
 }
 
 type_macros!(
@@ -227,10 +341,36 @@ impl<'ast> SourceItem for Collectable<'ast> {
   fn resolve(&mut self) -> Result<()> {
     // TODO: This may not resolve super types, depending on order.
     // Need to change the way those are set, with a placeholder type.
-    self.scope.awake_mut().resolve()
+    Scope::resolve_vars(&self.scope)?;
+    for default in &mut self.defaults {
+      default.resolve()?;
+    }
+    if let Some(ref mut upgrades) = self.upgrades {
+      for upgrade in upgrades {
+        upgrade.resolve()?;
+      }
+    }
+    if let Some(ref mut redemptions) = self.redemptions {
+      for redemption in redemptions {
+        redemption.resolve()?;
+      }
+    }
+    Ok(())
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    Scope::typecheck_vars(&self.scope)?;
+    typecheck_defaults(&mut self.defaults, self.parent)?;
+    self.check_inherited_values()?;
+    let (scope, parent) = (&self.scope, self.parent);
+    if let Some(ref mut upgrades) = self.upgrades {
+      typecheck_upgrades(upgrades, &|name| find_property(scope, parent, name))?;
+    }
+    if let Some(ref mut redemptions) = self.redemptions {
+      for redemption in redemptions {
+        redemption.typecheck()?;
+      }
+    }
     Ok(())
   }
 }
@@ -252,10 +392,8 @@ impl<'ast> CustomType<'ast> for Collectable<'ast> {
     false
   }
 
-  fn property(&self, _name: &str) -> Option<GraphRef<'ast, Variable<'ast>>> {
-    //self.properties.get(name)
-    //  .or_else(|| self.parent.map(|p| p.properties.get(name)))
-    None
+  fn property(&self, name: &str) -> Option<GraphRef<'ast, Variable<'ast>>> {
+    find_property(&self.scope, self.parent, name)
   }
 }
 
@@ -269,24 +407,423 @@ impl<'ast> SubType<'ast, CollectableGroup<'ast>> for Collectable<'ast> {
   }
 }
 
+/// Looks for a property in the type's own scope, then in its parents.
+fn find_property<'ast>(
+  scope: &GraphCell<Scope<'ast>>,
+  parent: Option<GraphRef<'ast, CollectableGroup<'ast>>>,
+  name: &str,
+) -> Option<GraphRef<'ast, Variable<'ast>>>
+{
+  scope.awake().find(name).or_else(|| {
+    parent.and_then(|p| {
+      let property = p.awake().property(name);
+      property
+    })
+  })
+}
+
+fn insert_default<'ast>(
+  defaults: &mut Vec<DefaultValue<'ast>>,
+  default: DefaultValue<'ast>,
+) -> Result<()>
+{
+  if let Some(first) = defaults.iter().find(|d| d.name().value() == default.name().value()) {
+    return Err(ErrorKind::DuplicateDefinition(
+      default.name().clone(),
+      "default",
+      first.name().span().clone(),
+    ).into());
+  }
+  defaults.push(default);
+  Ok(())
+}
+
+/// Links each default to the property it overrides from a parent group.
+fn typecheck_defaults<'ast>(
+  defaults: &mut [DefaultValue<'ast>],
+  parent: Option<GraphRef<'ast, CollectableGroup<'ast>>>,
+) -> Result<()>
+{
+  for default in defaults {
+    let var = parent.and_then(|p| {
+      let property = p.awake().property(default.name().value());
+      property
+    });
+    default.link(var)?;
+    default.typecheck()?;
+  }
+  Ok(())
+}
+
+/// Every collectable and group has an implicit `amount` property,
+/// and an `owner` property with the user who has the instance.
+fn insert_implicit_properties<'ast>(
+  scope: GraphRefMut<'ast, Scope<'ast>>,
+  ast: GraphRef<'ast, Ast<'ast>>,
+  span: TokenSpan,
+) -> Result<()>
+{
+  let ast = ast.awake();
+  let implicit = [("amount", ast.primitive().integer()), ("owner", ast.any_user())];
+  for &(name, ty) in &implicit {
+    let name = TokenValue::new(ast.shared_string(name), span.clone());
+    let ty_name = TokenValue::new(ty.awake().name().value().clone(), span.clone());
+    let ty = ItemRef::with_item(ty_name, ty);
+    scope.awake_mut().insert(Variable::new(name, ty))?;
+  }
+  Ok(())
+}
+
+/// An inclusive range of integers where either end may be open,
+/// e.g. `3`, `min 5 max 8`, `max 4`, `range 1 to 10` or `range(2, 4)`.
+#[derive(Debug, Clone, Serialize)]
+pub struct AmountRange {
+  min: Option<TokenValue<i64>>,
+  max: Option<TokenValue<i64>>,
+  span: TokenSpan,
+}
+
+impl AmountRange {
+  pub fn new(
+    min: Option<TokenValue<i64>>,
+    max: Option<TokenValue<i64>>,
+    span: TokenSpan,
+  ) -> Result<Self>
+  {
+    let range = AmountRange { min, max, span };
+    if range.min_value() > range.max_value() {
+      return Err(ErrorKind::ValueOutOfRange(
+        range.to_string(),
+        "the minimum is greater than the maximum",
+        range.span,
+      ).into());
+    }
+    Ok(range)
+  }
+
+  pub fn exactly(value: TokenValue<i64>) -> Self {
+    let span = value.span().clone();
+    AmountRange { min: Some(value.clone()), max: Some(value), span }
+  }
+
+  pub fn min(&self) -> Option<i64> {
+    self.min.as_ref().map(|m| *m.value())
+  }
+
+  pub fn max(&self) -> Option<i64> {
+    self.max.as_ref().map(|m| *m.value())
+  }
+
+  /// The lowest value in the range, which may be `i64::MIN`.
+  pub fn min_value(&self) -> i64 {
+    self.min().unwrap_or(i64::MIN)
+  }
+
+  /// The highest value in the range, which may be `i64::MAX`.
+  pub fn max_value(&self) -> i64 {
+    self.max().unwrap_or(i64::MAX)
+  }
+
+  pub fn contains(&self, value: i64) -> bool {
+    value >= self.min_value() && value <= self.max_value()
+  }
+
+  pub fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  /// Checks that no two ranges overlap and there are
+  /// no values left out between the lowest and highest range.
+  pub fn check_coverage<'r, I>(ranges: I, what: &'static str) -> Result<()>
+  where I: IntoIterator<Item = &'r AmountRange>
+  {
+    Self::check_ranges(ranges, what, false)
+  }
+
+  /// Checks that no two ranges overlap, allowing gaps between them.
+  pub fn check_disjoint<'r, I>(ranges: I, what: &'static str) -> Result<()>
+  where I: IntoIterator<Item = &'r AmountRange>
+  {
+    Self::check_ranges(ranges, what, true)
+  }
+
+  fn check_ranges<'r, I>(ranges: I, what: &'static str, allow_gaps: bool) -> Result<()>
+  where I: IntoIterator<Item = &'r AmountRange>
+  {
+    let mut ranges = ranges.into_iter().collect::<Vec<_>>();
+    ranges.sort_by_key(|r| r.min_value());
+    for pair in ranges.windows(2) {
+      let (first, second) = (pair[0], pair[1]);
+      let first_max = first.max_value();
+      let second_min = second.min_value();
+      if first_max >= second_min {
+        return Err(ErrorKind::RangeOverlap(
+          what,
+          first.span.clone(),
+          second.span.clone(),
+        ).into());
+      } else if !allow_gaps && first_max + 1 < second_min {
+        let missing = if first_max + 1 == second_min - 1 {
+          (first_max + 1).to_string()
+        } else {
+          format!("{} to {}", first_max + 1, second_min - 1)
+        };
+        return Err(ErrorKind::RangeGap(
+          what,
+          missing,
+          first.span.clone(),
+          second.span.clone(),
+        ).into());
+      }
+    }
+    Ok(())
+  }
+}
+
+impl Display for AmountRange {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match (self.min(), self.max()) {
+      (Some(min), Some(max)) if min == max => write!(f, "{}", min),
+      (Some(min), Some(max)) => write!(f, "range {} to {}", min, max),
+      (Some(min), None) => write!(f, "min {}", min),
+      (None, Some(max)) => write!(f, "max {}", max),
+      (None, None) => f.write_str("any"),
+    }
+  }
+}
+
+/// One rule from a `has upgrades` block. Without a property,
+/// the upgrade raises the collectable's own amount:
+///
+/// ```text
+/// has upgrades with cost [Coin x 100 for amount max 4, ...];
+/// ```
+///
+/// With a property, the upgrade raises that property and
+/// can require an amount of the collectable:
+///
+/// ```text
+/// has upgrades for level:
+///   award +1 for .level = 1 and .amount = 5 and cost 10 x Coin;
+/// end;
+/// ```
 #[derive(Debug, Serialize)]
-pub struct Upgrade {
-  level: u32,
+pub struct Upgrade<'ast> {
+  property: Option<TokenValue<Arc<str>>>,
+  increment: TokenValue<i64>,
+  levels: AmountRange,
+  required_amount: Option<BoxExpression<'ast>>,
+  cost: ItemRef<'ast, Collectable<'ast>>,
+  cost_amount: BoxExpression<'ast>,
+  span: TokenSpan,
+}
+
+impl<'ast> Upgrade<'ast> {
+  pub fn new(
+    property: Option<TokenValue<Arc<str>>>,
+    increment: TokenValue<i64>,
+    levels: AmountRange,
+    required_amount: Option<BoxExpression<'ast>>,
+    cost: ItemRef<'ast, Collectable<'ast>>,
+    cost_amount: BoxExpression<'ast>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    Upgrade {
+      property,
+      increment,
+      levels,
+      required_amount,
+      cost,
+      cost_amount,
+      span,
+    }
+  }
+
+  /// The upgraded property, or `None` for the amount.
+  pub fn property(&self) -> Option<&TokenValue<Arc<str>>> {
+    self.property.as_ref()
+  }
+
+  pub fn increment(&self) -> i64 {
+    *self.increment.value()
+  }
+
+  /// The values of the property (or amount) this rule applies to.
+  pub fn levels(&self) -> &AmountRange {
+    &self.levels
+  }
+
+  pub fn required_amount(&self) -> Option<&BoxExpression<'ast>> {
+    self.required_amount.as_ref()
+  }
+
+  pub fn cost(&self) -> &ItemRef<'ast, Collectable<'ast>> {
+    &self.cost
+  }
+
+  pub fn cost_amount(&self) -> &BoxExpression<'ast> {
+    &self.cost_amount
+  }
+}
+
+impl<'ast> Display for Upgrade<'ast> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.property {
+      Some(ref p) => write!(f, "award +{} for .{} {}", self.increment.value(), p, self.levels)?,
+      None => write!(f, "award +{} for amount {}", self.increment.value(), self.levels)?,
+    }
+    if let Some(ref amount) = self.required_amount {
+      write!(f, " and .amount = {}", amount)?;
+    }
+    write!(f, " and cost {} x {}", self.cost_amount, self.cost)
+  }
+}
+
+impl<'ast> SourceItem for Upgrade<'ast> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.cost.resolve()?;
+    if let Some(ref mut amount) = self.required_amount {
+      amount.resolve()?;
+    }
+    self.cost_amount.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    if let Some(ref mut amount) = self.required_amount {
+      amount.typecheck()?;
+      expect_primitive(&**amount, &[PrimitiveType::Integer])?;
+    }
+    self.cost_amount.typecheck()?;
+    expect_primitive(&*self.cost_amount, &[PrimitiveType::Integer])?;
+    Ok(())
+  }
 }
 
-impl Upgrade {
-  pub fn new(level: u32) -> Self {
-    Upgrade { level }
+fn typecheck_upgrades<'ast>(
+  upgrades: &mut [Upgrade<'ast>],
+  property: &Fn(&str) -> Option<GraphRef<'ast, Variable<'ast>>>,
+) -> Result<()>
+{
+  for upgrade in upgrades.iter_mut() {
+    upgrade.typecheck()?;
+    if let Some(ref name) = upgrade.property {
+      let var = match property(name.value()) {
+        Some(var) => var,
+        None => return Err(ErrorKind::NotDefined(name.clone(), "property").into()),
+      };
+      let var = var.awake();
+      let ty = var.ty();
+      let ty = ty.awake();
+      if ty.as_primitive() != Some(PrimitiveType::Integer) {
+        return Err(ErrorKind::TypeResolution(
+          PrimitiveType::Integer.as_str().into(),
+          TokenValue::new(ty.name().value().clone(), name.span().clone()),
+        ).into());
+      }
+    }
   }
+  AmountRange::check_coverage(upgrades.iter().map(Upgrade::levels), "upgrade level")
 }
 
+/// One way to get a collectable from a `has redemptions` block.
 #[derive(Debug, Serialize)]
-pub struct Redemption {
-  amount: u32,
+pub enum Redemption<'ast> {
+  /// `has redemptions for currency;` - the price is looked up
+  /// by an adapter for the store the user is logged in to.
+  ForCurrency(TokenSpan),
+  /// `x 1 for Coin x 100` or `with amount 1 for cost Coin x 100`.
+  ForCollectable {
+    /// How much of the owning collectable is awarded.
+    amount: TokenValue<i64>,
+    cost: ItemRef<'ast, Collectable<'ast>>,
+    cost_amount: BoxExpression<'ast>,
+    span: TokenSpan,
+  },
+}
+
+impl<'ast> Redemption<'ast> {
+  pub fn for_collectable(
+    amount: TokenValue<i64>,
+    cost: ItemRef<'ast, Collectable<'ast>>,
+    cost_amount: BoxExpression<'ast>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    Redemption::ForCollectable { amount, cost, cost_amount, span }
+  }
+
+  /// The awarded amount, or `None` if it's decided by the store.
+  pub fn amount(&self) -> Option<i64> {
+    match *self {
+      Redemption::ForCurrency(_) => None,
+      Redemption::ForCollectable { ref amount, .. } => Some(*amount.value()),
+    }
+  }
+
+  pub fn cost(&self) -> Option<&ItemRef<'ast, Collectable<'ast>>> {
+    match *self {
+      Redemption::ForCurrency(_) => None,
+      Redemption::ForCollectable { ref cost, .. } => Some(cost),
+    }
+  }
+
+  pub fn cost_amount(&self) -> Option<&BoxExpression<'ast>> {
+    match *self {
+      Redemption::ForCurrency(_) => None,
+      Redemption::ForCollectable { ref cost_amount, .. } => Some(cost_amount),
+    }
+  }
+}
+
+impl<'ast> Display for Redemption<'ast> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Redemption::ForCurrency(_) => f.write_str("for currency"),
+      Redemption::ForCollectable { ref amount, ref cost, ref cost_amount, .. } => {
+        write!(f, "x {} for {} x {}", amount.value(), cost, cost_amount)
+      }
+    }
+  }
 }
 
-impl Redemption {
-  pub fn new(amount: u32) -> Self {
-    Redemption { amount }
+impl<'ast> SourceItem for Redemption<'ast> {
+  fn span(&self) -> &TokenSpan {
+    match *self {
+      Redemption::ForCurrency(ref span) => span,
+      Redemption::ForCollectable { ref span, .. } => span,
+    }
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    match *self {
+      Redemption::ForCurrency(_) => Ok(()),
+      Redemption::ForCollectable { ref mut cost, ref mut cost_amount, .. } => {
+        cost.resolve()?;
+        cost_amount.resolve()
+      }
+    }
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    match *self {
+      Redemption::ForCurrency(_) => Ok(()),
+      Redemption::ForCollectable { ref amount, ref mut cost_amount, .. } => {
+        if *amount.value() <= 0 {
+          return Err(ErrorKind::ValueOutOfRange(
+            amount.value().to_string(),
+            "redemptions must award a positive amount",
+            amount.span().clone(),
+          ).into());
+        }
+        cost_amount.typecheck()?;
+        expect_primitive(&**cost_amount, &[PrimitiveType::Integer])?;
+        Ok(())
+      }
+    }
   }
 }
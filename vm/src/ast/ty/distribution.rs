@@ -0,0 +1,239 @@
+use std::sync::Arc;
+use std::fmt::{self, Display};
+use util::graph_cell::*;
+use compile::{TokenSpan, TokenValue};
+use super::*;
+
+/// Allow for some rounding error when adding up weights.
+const WEIGHT_EPSILON: f64 = 1e-9;
+
+/// A random award picked from the collectables in a group.
+///
+/// ```text
+/// distribution of Card SmallChestRewards:
+///   amount range(10, 15);
+///   group x range(2, 4) of [
+///     CommonCard weighted 95%,
+///     RareCard x max 2
+///   ];
+/// end;
+/// ```
+#[derive(Debug, Serialize)]
+pub struct Distribution<'ast> {
+  name: TokenValue<Arc<str>>,
+  group: ItemRef<'ast, CollectableGroup<'ast>>,
+  /// The total amount awarded.
+  amount: Option<AmountRange>,
+  /// How many of the entries are picked.
+  picks: Option<AmountRange>,
+  entries: Vec<DistributionEntry<'ast>>,
+  scope: GraphCell<Scope<'ast>>,
+}
+
+impl<'ast> Distribution<'ast> {
+  pub fn new(
+    name: TokenValue<Arc<str>>,
+    group: ItemRef<'ast, CollectableGroup<'ast>>,
+    ast: GraphRefMut<'ast, Ast<'ast>>,
+  ) -> Result<GraphRefMut<'ast, Self>>
+  {
+    let parent_scope = ast.awake().scope();
+    let span = name.span().clone();
+    Ast::insert_cast_type(
+      ast,
+      Distribution {
+        name,
+        group,
+        amount: None,
+        picks: None,
+        entries: Vec::new(),
+        scope: Scope::child(parent_scope, ScopeKind::TYPE, span),
+      }
+    )
+  }
+
+  pub fn group(&self) -> &ItemRef<'ast, CollectableGroup<'ast>> {
+    &self.group
+  }
+
+  pub fn amount(&self) -> Option<&AmountRange> {
+    self.amount.as_ref()
+  }
+
+  pub fn set_amount(&mut self, amount: AmountRange) {
+    self.amount = Some(amount);
+  }
+
+  pub fn picks(&self) -> Option<&AmountRange> {
+    self.picks.as_ref()
+  }
+
+  pub fn entries(&self) -> &[DistributionEntry<'ast>] {
+    &self.entries
+  }
+
+  pub fn set_entries(&mut self, picks: AmountRange, entries: Vec<DistributionEntry<'ast>>) {
+    self.picks = Some(picks);
+    self.entries = entries;
+  }
+
+  fn check_entry(&self, entry: &DistributionEntry<'ast>) -> Result<()> {
+    let item = entry.item.unwrap();
+    let item = item.awake();
+    let mut parent = if let Some(c) = Collectable::try_cast(&*item) {
+      c.super_type()
+    } else if let Some(g) = CollectableGroup::try_cast(&*item) {
+      g.super_type()
+    } else {
+      return Err(ErrorKind::TypeResolution(
+        BaseCustomType::Collectable.as_str().into(),
+        entry.item.name().clone(),
+      ).into());
+    };
+
+    let group = self.group.name().value();
+    while let Some(p) = parent {
+      let p = p.awake();
+      if p.name().value() == group {
+        return Ok(());
+      }
+      parent = p.super_type();
+    }
+    Err(ErrorKind::NotInGroup(entry.item.name().clone(), group.clone()).into())
+  }
+}
+
+type_macros!(
+  Distribution<'ast>;
+
+  impl_named(type),
+  impl_name_traits,
+  named_display,
+  impl_scoped('ast,)
+);
+
+impl<'ast> SourceItem for Distribution<'ast> {
+  fn span(&self) -> &TokenSpan {
+    self.name.span()
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.group.resolve()?;
+    for entry in &mut self.entries {
+      entry.resolve()?;
+    }
+    Ok(())
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    let mut total_weight = 0.0;
+    for entry in &self.entries {
+      self.check_entry(entry)?;
+      if let Some(ref weight) = entry.weight {
+        if *weight.value() <= 0.0 || *weight.value() > 1.0 {
+          return Err(ErrorKind::ValueOutOfRange(
+            format!("{}%", weight.value() * 100.0),
+            "weights must be more than 0% and at most 100%",
+            weight.span().clone(),
+          ).into());
+        }
+        total_weight += *weight.value();
+        if total_weight > 1.0 + WEIGHT_EPSILON {
+          return Err(ErrorKind::ValueOutOfRange(
+            format!("{}%", total_weight * 100.0),
+            "weights in a distribution can't add up to more than 100%",
+            weight.span().clone(),
+          ).into());
+        }
+      }
+    }
+    if let Some(ref picks) = self.picks {
+      if picks.min_value() > self.entries.len() as i64 {
+        return Err(ErrorKind::ValueOutOfRange(
+          picks.to_string(),
+          "can't pick more groups than there are entries",
+          picks.span().clone(),
+        ).into());
+      }
+    }
+    Ok(())
+  }
+}
+
+impl<'ast> CastType<'ast> for Distribution<'ast> {
+  const BASE_TYPE: BaseCustomType = BaseCustomType::Distribution;
+}
+
+impl<'ast> CustomType<'ast> for Distribution<'ast> {
+  fn base_type(&self) -> BaseCustomType {
+    BaseCustomType::Distribution
+  }
+
+  fn capabilities(&self) -> TypeCapability {
+    Default::default()
+  }
+}
+
+/// `<collectable or group> [weighted <percent>] [x <amount range>]`
+///
+/// Entries without a weight share whatever is left over
+/// from the explicit weights.
+#[derive(Debug, Serialize)]
+pub struct DistributionEntry<'ast> {
+  item: ItemRef<'ast, CustomType<'ast>>,
+  weight: Option<TokenValue<f64>>,
+  amount: Option<AmountRange>,
+  span: TokenSpan,
+}
+
+impl<'ast> DistributionEntry<'ast> {
+  pub fn new(
+    item: ItemRef<'ast, CustomType<'ast>>,
+    weight: Option<TokenValue<f64>>,
+    amount: Option<AmountRange>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    DistributionEntry { item, weight, amount, span }
+  }
+
+  pub fn item(&self) -> &ItemRef<'ast, CustomType<'ast>> {
+    &self.item
+  }
+
+  /// A fraction between 0 and 1.
+  pub fn weight(&self) -> Option<f64> {
+    self.weight.as_ref().map(|w| *w.value())
+  }
+
+  pub fn amount(&self) -> Option<&AmountRange> {
+    self.amount.as_ref()
+  }
+}
+
+impl<'ast> Display for DistributionEntry<'ast> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.item.name().value())?;
+    if let Some(ref weight) = self.weight {
+      write!(f, " weighted {}%", weight.value() * 100.0)?;
+    }
+    if let Some(ref amount) = self.amount {
+      write!(f, " x {}", amount)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'ast> SourceItem for DistributionEntry<'ast> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.item.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    Ok(())
+  }
+}
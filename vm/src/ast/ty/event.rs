@@ -1,19 +1,58 @@
 use std::sync::Arc;
 use util::graph_cell::*;
 use ast::var::Variable;
+use ast::stmt::{BoxStatement, TypeOrExpr, resolve_block, typecheck_block};
 use compile::{TokenSpan, TokenValue};
 use super::*;
 
+/// ```text
+/// event Name:
+///   <statements>
+/// end;
+///
+/// Owner has event Name(instance) <- sender:
+///   <statements>
+/// end;
+/// ```
+///
+/// Events attached to a type are named `Owner.Name`.
 #[derive(Debug, Serialize)]
 pub struct Event<'ast> {
   name: TokenValue<Arc<str>>,
-  params: Vec<GraphCell<Variable<'ast>>>,
+  owner: Option<ItemRef<'ast, CustomType<'ast>>>,
+  /// In declaration order; the variables are in `scope`.
+  params: Vec<TokenValue<Arc<str>>>,
+  /// Who is allowed to send the event.
+  sender: Option<TypeOrExpr<'ast>>,
   scope: GraphCell<Scope<'ast>>,
+  body: Vec<BoxStatement<'ast>>,
 }
 
 impl<'ast> Event<'ast> {
   pub fn new(name: TokenValue<Arc<str>>, ast: GraphRefMut<'ast, Ast<'ast>>)
     -> Result<GraphRefMut<'ast, Self>>
+  {
+    Self::create(name, None, ast)
+  }
+
+  pub fn with_owner(
+    owner: ItemRef<'ast, CustomType<'ast>>,
+    name: TokenValue<Arc<str>>,
+    ast: GraphRefMut<'ast, Ast<'ast>>,
+  ) -> Result<GraphRefMut<'ast, Self>>
+  {
+    let full_name = ast.awake().shared_string(
+      &format!("{}.{}", owner.name().value(), name.value())
+    );
+    let name = TokenValue::new(full_name, name.span().clone());
+    Self::create(name, Some(owner), ast)
+  }
+
+  fn create(
+    name: TokenValue<Arc<str>>,
+    owner: Option<ItemRef<'ast, CustomType<'ast>>>,
+    ast: GraphRefMut<'ast, Ast<'ast>>,
+  ) -> Result<GraphRefMut<'ast, Self>>
   {
     let parent_scope = ast.awake().scope();
     let span = name.span().clone();
@@ -21,11 +60,45 @@ impl<'ast> Event<'ast> {
       ast,
       Event {
         name,
+        owner,
         params: Vec::new(),
+        sender: None,
         scope: Scope::child(parent_scope, ScopeKind::TYPE, span),
+        body: Vec::new(),
       }
     )
   }
+
+  pub fn owner(&self) -> Option<&ItemRef<'ast, CustomType<'ast>>> {
+    self.owner.as_ref()
+  }
+
+  pub fn insert_param(&mut self, param: Variable<'ast>) -> Result<()> {
+    let name = param.name().clone();
+    self.scope.awake_mut().insert(param)?;
+    self.params.push(name);
+    Ok(())
+  }
+
+  pub fn params(&self) -> &[TokenValue<Arc<str>>] {
+    &self.params
+  }
+
+  pub fn sender(&self) -> Option<&TypeOrExpr<'ast>> {
+    self.sender.as_ref()
+  }
+
+  pub fn set_sender(&mut self, sender: TypeOrExpr<'ast>) {
+    self.sender = Some(sender);
+  }
+
+  pub fn body(&self) -> &[BoxStatement<'ast>] {
+    &self.body
+  }
+
+  pub fn set_body(&mut self, body: Vec<BoxStatement<'ast>>) {
+    self.body = body;
+  }
 }
 
 type_macros!(
@@ -43,11 +116,40 @@ impl<'ast> SourceItem for Event<'ast> {
   }
 
   fn resolve(&mut self) -> Result<()> {
-    Ok(())
+    if let Some(ref mut owner) = self.owner {
+      owner.resolve()?;
+    }
+    Scope::resolve_vars(&self.scope)?;
+    if let Some(ref mut sender) = self.sender {
+      sender.resolve()?;
+    }
+    resolve_block(&mut self.body)
   }
 
   fn typecheck(&mut self) -> Result<()> {
-    Ok(())
+    if let Some(ref owner) = self.owner {
+      let base_type = owner.unwrap().awake().base_type();
+      match base_type {
+        | BaseCustomType::Collectable
+        | BaseCustomType::CollectableGroup
+        | BaseCustomType::Object
+        | BaseCustomType::User
+          => {}
+        _ => return Err(ErrorKind::TypeResolution(
+          "collectable, object or user".into(),
+          owner.name().clone(),
+        ).into()),
+      }
+    }
+    Scope::typecheck_vars(&self.scope)?;
+    if let Some(ref mut sender) = self.sender {
+      sender.typecheck()?;
+      sender.expect_base_type(
+        &[BaseCustomType::User, BaseCustomType::UserGroup],
+        "user or user group",
+      )?;
+    }
+    typecheck_block(&mut self.body)
   }
 }
 
@@ -1,11 +1,27 @@
 use std::sync::Arc;
 use util::graph_cell::*;
 use compile::{TokenSpan, TokenValue};
+use ast::var::Variable;
+use ast::expr::{BoxExpression, expect_assignable};
+use ast::stmt::{BoxStatement, resolve_block, typecheck_block};
 use super::*;
 
+/// ```text
+/// function CoinRewards(player Player) -> integer:
+///   <statements>
+///   -> 10 + 2 * player.Level.amount;
+/// end;
+/// ```
 #[derive(Debug, Serialize)]
 pub struct Function<'ast> {
   name: TokenValue<Arc<str>>,
+  /// In declaration order; the variables are in `param_scope`.
+  params: Vec<TokenValue<Arc<str>>>,
+  /// Missing for functions that don't return anything.
+  return_type: Option<ItemRef<'ast, Type<'ast>>>,
+  body: Vec<BoxStatement<'ast>>,
+  /// Only there if there's a return type.
+  result: Option<BoxExpression<'ast>>,
   param_scope: GraphCell<Scope<'ast>>,
   local_scope: Later<GraphCell<Scope<'ast>>>,
 }
@@ -20,18 +36,68 @@ impl<'ast> Function<'ast> {
       ast,
       Function {
         name,
+        params: Vec::new(),
+        return_type: None,
+        body: Vec::new(),
+        result: None,
         param_scope: Scope::child(parent_scope, ScopeKind::FN_PARAM, span.clone()),
         local_scope: Later::new(),
       }
     )?;
-    let mut fmut = f.awake_mut();
-    let param_scope = fmut.param_scope.asleep();
-    Later::set(
-      &mut fmut.local_scope,
-      Scope::child(param_scope, ScopeKind::FN_LOCAL, span)
-    );
+    {
+      let mut fmut = f.awake_mut();
+      let param_scope = fmut.param_scope.asleep();
+      Later::set(
+        &mut fmut.local_scope,
+        Scope::child(param_scope, ScopeKind::FN_LOCAL, span)
+      );
+    }
     Ok(f)
   }
+
+  pub fn param_scope(&self) -> GraphRef<'ast, Scope<'ast>> {
+    self.param_scope.asleep()
+  }
+
+  pub fn param_scope_mut(&self) -> GraphRefMut<'ast, Scope<'ast>> {
+    self.param_scope.asleep_mut()
+  }
+
+  pub fn insert_param(&mut self, param: Variable<'ast>) -> Result<()> {
+    let name = param.name().clone();
+    self.param_scope.awake_mut().insert(param)?;
+    self.params.push(name);
+    Ok(())
+  }
+
+  pub fn params(&self) -> &[TokenValue<Arc<str>>] {
+    &self.params
+  }
+
+  pub fn return_type(&self) -> Option<&ItemRef<'ast, Type<'ast>>> {
+    self.return_type.as_ref()
+  }
+
+  pub fn set_return_type(&mut self, ty: ItemRef<'ast, Type<'ast>>) {
+    self.return_type = Some(ty);
+  }
+
+  pub fn body(&self) -> &[BoxStatement<'ast>] {
+    &self.body
+  }
+
+  pub fn result(&self) -> Option<&BoxExpression<'ast>> {
+    self.result.as_ref()
+  }
+
+  pub fn set_body(
+    &mut self,
+    body: Vec<BoxStatement<'ast>>,
+    result: Option<BoxExpression<'ast>>,
+  ) {
+    self.body = body;
+    self.result = result;
+  }
 }
 
 type_macros!(
@@ -49,10 +115,36 @@ impl<'ast> SourceItem for Function<'ast> {
   }
 
   fn resolve(&mut self) -> Result<()> {
+    Scope::resolve_vars(&self.param_scope)?;
+    Scope::resolve_vars(&self.local_scope)?;
+    if let Some(ref mut return_type) = self.return_type {
+      return_type.resolve()?;
+    }
+    resolve_block(&mut self.body)?;
+    if let Some(ref mut result) = self.result {
+      result.resolve()?;
+    }
     Ok(())
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    Scope::typecheck_vars(&self.param_scope)?;
+    Scope::typecheck_vars(&self.local_scope)?;
+    typecheck_block(&mut self.body)?;
+    for stmt in &self.body {
+      if stmt.is_wait() {
+        return Err(ErrorKind::InvalidStatement(
+          "functions can't wait for anything",
+          stmt.span().clone(),
+        ).into());
+      }
+    }
+    if let Some(ref mut result) = self.result {
+      result.typecheck()?;
+      if let Some(ref return_type) = self.return_type {
+        expect_assignable(&mut **result, return_type.unwrap())?;
+      }
+    }
     Ok(())
   }
 }
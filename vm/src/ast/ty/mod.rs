@@ -11,6 +11,7 @@ use super::*;
 
 mod array;
 mod collectable;
+mod distribution;
 mod earlyref;
 mod event;
 mod function;
@@ -19,6 +20,7 @@ mod user;
 
 pub use self::array::*;
 pub use self::collectable::*;
+pub use self::distribution::*;
 pub use self::earlyref::*;
 pub use self::event::*;
 pub use self::function::*;
@@ -173,13 +175,14 @@ impl<'a> PrimitiveTypeSet<'a> {
 
 /// "Generic" types that form the base
 /// of user defined instances.
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum BaseCustomType {
   EarlyRef,
   Array,
   Object,
   Collectable,
   CollectableGroup,
+  Distribution,
   User,
   UserGroup,
   Event,
@@ -197,6 +200,7 @@ impl BaseCustomType {
       Object => "object",
       Collectable => "collectable",
       CollectableGroup => "collectable group",
+      Distribution => "distribution",
       User => "user",
       UserGroup => "user group",
       Event => "event",
@@ -227,6 +231,10 @@ impl BaseCustomType {
         => Collectable::new(name, ast).map(|_| ()),
       BaseCustomType::CollectableGroup
         => CollectableGroup::new(name, ast).map(|_| ()),
+      BaseCustomType::Distribution
+        => Err(ErrorKind::InvalidOperation(
+          "empty distribution type is not valid - it needs a collectable group"
+        ).into()),
       BaseCustomType::User
         => User::new(name, ast).map(|_| ()),
       BaseCustomType::UserGroup
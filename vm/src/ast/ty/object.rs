@@ -44,11 +44,11 @@ impl<'ast> SourceItem for Object<'ast> {
   }
 
   fn resolve(&mut self) -> Result<()> {
-    self.scope.awake_mut().resolve()
+    Scope::resolve_vars(&self.scope)
   }
 
   fn typecheck(&mut self) -> Result<()> {
-    Ok(())
+    Scope::typecheck_vars(&self.scope)
   }
 }
 
@@ -65,8 +65,13 @@ impl<'ast> CustomType<'ast> for Object<'ast> {
     TypeCapability::PROPERTIES | TypeCapability::OWNED | TypeCapability::INHERIT
   }
 
-  fn property(&self, _name: &str) -> Option<GraphRef<'ast, Variable<'ast>>> {
-    None
+  fn property(&self, name: &str) -> Option<GraphRef<'ast, Variable<'ast>>> {
+    self.scope.awake().find(name).or_else(|| {
+      self.super_type.and_then(|st| {
+        let property = st.awake().property(name);
+        property
+      })
+    })
   }
 
   fn is_sub_type_of(&self, _ty: &CustomType<'ast>) -> bool {
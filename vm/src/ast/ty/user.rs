@@ -1,4 +1,5 @@
 use std::sync::Arc;
+use std::fmt::{self, Display};
 use fxhash::FxHashMap;
 use util::graph_cell::*;
 use compile::{TokenSpan, TokenValue};
@@ -43,6 +44,25 @@ impl<'ast> Default for Precedence<'ast> {
   }
 }
 
+impl<'ast> Precedence<'ast> {
+  /// The group this precedence is relative to.
+  pub fn target(&self) -> Option<&ItemRef<'ast, UserGroup<'ast>>> {
+    match *self {
+      Precedence::Undefined => None,
+      Precedence::Higher(ref g) | Precedence::Equal(ref g) | Precedence::Lower(ref g) => Some(g),
+    }
+  }
+
+  fn target_mut(&mut self) -> Option<&mut ItemRef<'ast, UserGroup<'ast>>> {
+    match *self {
+      Precedence::Undefined => None,
+      Precedence::Higher(ref mut g)
+      | Precedence::Equal(ref mut g)
+      | Precedence::Lower(ref mut g) => Some(g),
+    }
+  }
+}
+
 /// This is a classification, and not a super type of User. A user
 /// can belong to multiple user groups, which mostly just serve to
 /// establish permissions.
@@ -88,6 +108,162 @@ impl<'ast> UserGroup<'ast> {
       }
     )
   }
+
+  pub fn membership_mode(&self) -> MembershipMode {
+    self.membership_mode
+  }
+
+  pub fn set_membership_mode(&mut self, mode: MembershipMode) {
+    self.membership_mode = mode;
+  }
+
+  pub fn except_members(&self) -> &[ItemRef<'ast, User<'ast>>] {
+    &self.except_members
+  }
+
+  pub fn insert_except_member(&mut self, user: ItemRef<'ast, User<'ast>>) -> Result<()> {
+    insert_unique_ref(&mut self.except_members, user, "user")
+  }
+
+  pub fn deny_with(&self) -> &[ItemRef<'ast, UserGroup<'ast>>] {
+    &self.deny_with
+  }
+
+  pub fn insert_deny_with(&mut self, group: ItemRef<'ast, UserGroup<'ast>>) -> Result<()> {
+    insert_unique_ref(&mut self.deny_with, group, "user group")
+  }
+
+  pub fn precedence(&self) -> &Precedence<'ast> {
+    &self.precedence
+  }
+
+  pub fn set_precedence(&mut self, precedence: Precedence<'ast>) {
+    self.precedence = precedence;
+  }
+}
+
+/// An ordering from the higher group to the lower one. The token is
+/// the lower group's name, at the place the ordering was given.
+type PrecedenceEdge = (usize, usize, TokenValue<Arc<str>>);
+
+/// Precedence can be given from either group, so `A higher than B` and
+/// `B lower than A` are the same ordering. Each one is turned into an
+/// edge from the higher group to the lower one, groups of equal
+/// precedence are merged, and any cycle that's left is an error.
+pub fn check_precedence<'ast, I>(types: I) -> Result<()>
+where I: IntoIterator<Item = GraphRef<'ast, Type<'ast>>>
+{
+  let groups = types
+    .into_iter()
+    .filter_map(|t| t.map_opt(|t| t.as_custom().and_then(UserGroup::try_cast)))
+    .collect::<Vec<_>>();
+  let index = groups
+    .iter()
+    .enumerate()
+    .map(|(i, g)| (g.awake().name.value().clone(), i))
+    .collect::<FxHashMap<_, _>>();
+  let mut same = (0..groups.len()).collect::<Vec<_>>();
+  let mut edges = Vec::new();
+  for (i, group) in groups.iter().enumerate() {
+    let group = group.awake();
+    let other = match group.precedence.target() {
+      Some(other) => other,
+      None => continue,
+    };
+    let j = index[other.name().value()];
+    match group.precedence {
+      Precedence::Higher(_) => edges.push((i, j, other.name().clone())),
+      Precedence::Lower(_) => edges.push((
+        j,
+        i,
+        TokenValue::new(group.name.value().clone(), other.name().span().clone()),
+      )),
+      Precedence::Equal(_) => {
+        let (a, b) = (equal_root(&same, i), equal_root(&same, j));
+        same[a] = b;
+      }
+      Precedence::Undefined => {}
+    }
+  }
+
+  let mut visited = vec![Visit::New; groups.len()];
+  let mut path = Vec::new();
+  for i in 0..groups.len() {
+    let root = equal_root(&same, i);
+    if visited[root] != Visit::New {
+      continue;
+    }
+    if let Some(cycle) = find_precedence_cycle(root, &same, &edges, &mut visited, &mut path) {
+      let mut chain = vec![groups[cycle[0].0].awake().name.clone()];
+      chain.extend(cycle.iter().map(|e| e.2.clone()));
+      return Err(ErrorKind::PrecedenceCycle(chain).into());
+    }
+  }
+  Ok(())
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Visit {
+  New,
+  OnPath,
+  Done,
+}
+
+/// The group standing in for every group of equal precedence with it.
+fn equal_root(same: &[usize], mut i: usize) -> usize {
+  while same[i] != i {
+    i = same[i];
+  }
+  i
+}
+
+/// A depth first search from `from` along the edges, which
+/// returns the edges of the first cycle found.
+fn find_precedence_cycle<'e>(
+  from: usize,
+  same: &[usize],
+  edges: &'e [PrecedenceEdge],
+  visited: &mut [Visit],
+  path: &mut Vec<&'e PrecedenceEdge>,
+) -> Option<Vec<&'e PrecedenceEdge>>
+{
+  visited[from] = Visit::OnPath;
+  for edge in edges.iter().filter(|e| equal_root(same, e.0) == from) {
+    let to = equal_root(same, edge.1);
+    path.push(edge);
+    match visited[to] {
+      Visit::OnPath => {
+        let start = path.iter().position(|e| equal_root(same, e.0) == to).unwrap();
+        return Some(path[start..].to_vec());
+      }
+      Visit::New => {
+        if let Some(cycle) = find_precedence_cycle(to, same, edges, visited, path) {
+          return Some(cycle);
+        }
+      }
+      Visit::Done => {}
+    }
+    path.pop();
+  }
+  visited[from] = Visit::Done;
+  None
+}
+
+fn insert_unique_ref<'ast, T: Named + ?Sized + 'ast>(
+  refs: &mut Vec<ItemRef<'ast, T>>,
+  item: ItemRef<'ast, T>,
+  typ: &'static str,
+) -> Result<()>
+{
+  if let Some(first) = refs.iter().find(|r| r.name().value() == item.name().value()) {
+    return Err(ErrorKind::DuplicateDefinition(
+      item.name().clone(),
+      typ,
+      first.name().span().clone(),
+    ).into());
+  }
+  refs.push(item);
+  Ok(())
 }
 
 impl<'ast> SourceItem for UserGroup<'ast> {
@@ -96,10 +272,26 @@ impl<'ast> SourceItem for UserGroup<'ast> {
   }
 
   fn resolve(&mut self) -> Result<()> {
+    for user in &mut self.except_members {
+      user.resolve()?;
+    }
+    for group in &mut self.deny_with {
+      group.resolve()?;
+    }
+    if let Some(group) = self.precedence.target_mut() {
+      group.resolve()?;
+    }
     Ok(())
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    if let Some(group) = self.deny_with.iter().find(|g| g.name().value() == self.name.value()) {
+      return Err(ErrorKind::ValueOutOfRange(
+        group.name().value().to_string(),
+        "a group can't deny itself",
+        group.name().span().clone(),
+      ).into());
+    }
     Ok(())
   }
 }
@@ -118,6 +310,95 @@ impl<'ast> CustomType<'ast> for UserGroup<'ast> {
   }
 }
 
+/// A `has collectable` clause on a user type, which lets users of that
+/// type own the collectable (or anything in the group), optionally
+/// limited to a range of amounts:
+///
+/// ```text
+/// has collectable Level with amount range 1 to 10;
+/// has collectable group Chest with amount max 4;
+/// ```
+#[derive(Debug, Serialize)]
+pub struct OwnedCollectable<'ast> {
+  item: ItemRef<'ast, CustomType<'ast>>,
+  is_group: bool,
+  amount: Option<AmountRange>,
+  span: TokenSpan,
+}
+
+impl<'ast> OwnedCollectable<'ast> {
+  pub fn new(
+    item: ItemRef<'ast, CustomType<'ast>>,
+    is_group: bool,
+    amount: Option<AmountRange>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    OwnedCollectable { item, is_group, amount, span }
+  }
+
+  pub fn item(&self) -> &ItemRef<'ast, CustomType<'ast>> {
+    &self.item
+  }
+
+  pub fn is_group(&self) -> bool {
+    self.is_group
+  }
+
+  /// The limits on how many of this item a user can hold.
+  pub fn amount(&self) -> Option<&AmountRange> {
+    self.amount.as_ref()
+  }
+}
+
+impl<'ast> Display for OwnedCollectable<'ast> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str("has collectable ")?;
+    if self.is_group {
+      f.write_str("group ")?;
+    }
+    f.write_str(self.item.name().value())?;
+    if let Some(ref amount) = self.amount {
+      write!(f, " with amount {}", amount)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'ast> SourceItem for OwnedCollectable<'ast> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.item.resolve()
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    let expected = if self.is_group {
+      BaseCustomType::CollectableGroup
+    } else {
+      BaseCustomType::Collectable
+    };
+    if self.item.unwrap().awake().base_type() != expected {
+      return Err(ErrorKind::TypeResolution(
+        expected.as_str().into(),
+        self.item.name().clone(),
+      ).into());
+    }
+    if let Some(ref amount) = self.amount {
+      if amount.min().map_or(false, |min| min < 0) {
+        return Err(ErrorKind::ValueOutOfRange(
+          amount.to_string(),
+          "users can't own a negative amount",
+          amount.span().clone(),
+        ).into());
+      }
+    }
+    Ok(())
+  }
+}
+
 /// This does not represent a single user. It is a user type that can belong
 /// to any number of user groups, have properties, and has automatic
 /// collectable ownership, notification target, and authentication functionality.
@@ -128,7 +409,7 @@ impl<'ast> CustomType<'ast> for UserGroup<'ast> {
 #[derive(Debug, Serialize)]
 pub struct User<'ast> {
   name: TokenValue<Arc<str>>,
-  properties: FxHashMap<Arc<str>, GraphCell<Variable<'ast>>>,
+  collectables: Vec<OwnedCollectable<'ast>>,
   scope: GraphCell<Scope<'ast>>,
 }
 
@@ -142,11 +423,60 @@ impl<'ast> User<'ast> {
       ast,
       User {
         name,
-        properties: Default::default(),
+        collectables: Vec::new(),
         scope: Scope::child(parent_scope, ScopeKind::TYPE, span),
       }
     )
   }
+
+  pub fn insert_collectable(&mut self, collectable: OwnedCollectable<'ast>) {
+    self.collectables.push(collectable);
+  }
+
+  pub fn collectables(&self) -> &[OwnedCollectable<'ast>] {
+    &self.collectables
+  }
+
+  /// A user has to be able to hold the minimum amount of a collectable
+  /// without going over the maximum of any owned group it belongs to.
+  fn check_group_limits(&self, collectable: &OwnedCollectable<'ast>) -> Result<()> {
+    let min = match collectable.amount.as_ref().and_then(AmountRange::min) {
+      Some(min) => min,
+      None => return Ok(()),
+    };
+    let item = collectable.item.unwrap();
+    let item = item.awake();
+    let mut parent = if let Some(c) = Collectable::try_cast(&*item) {
+      c.super_type()
+    } else if let Some(g) = CollectableGroup::try_cast(&*item) {
+      g.super_type()
+    } else {
+      None
+    };
+    while let Some(p) = parent {
+      let p = p.awake();
+      let limit = self
+        .owned_collectable(p.name().value())
+        .and_then(|g| g.amount.as_ref())
+        .and_then(AmountRange::max);
+      if let Some(max) = limit {
+        if min > max {
+          return Err(ErrorKind::ValueOutOfRange(
+            min.to_string(),
+            "the minimum is more than the group's maximum",
+            collectable.amount.as_ref().unwrap().span().clone(),
+          ).into());
+        }
+      }
+      parent = p.super_type();
+    }
+    Ok(())
+  }
+
+  /// The `has collectable` clause for this exact collectable or group.
+  pub fn owned_collectable(&self, name: &str) -> Option<&OwnedCollectable<'ast>> {
+    self.collectables.iter().find(|c| &**c.item.name().value() == name)
+  }
 }
 
 type_macros!(
@@ -164,10 +494,32 @@ impl<'ast> SourceItem for User<'ast> {
   }
 
   fn resolve(&mut self) -> Result<()> {
+    Scope::resolve_vars(&self.scope)?;
+    for collectable in &mut self.collectables {
+      collectable.resolve()?;
+    }
     Ok(())
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    Scope::typecheck_vars(&self.scope)?;
+    for collectable in &mut self.collectables {
+      collectable.typecheck()?;
+    }
+    for (i, collectable) in self.collectables.iter().enumerate() {
+      let name = collectable.item.name();
+      let first = self.collectables[..i]
+        .iter()
+        .find(|c| c.item.name().value() == name.value());
+      if let Some(first) = first {
+        return Err(ErrorKind::DuplicateDefinition(
+          name.clone(),
+          "owned collectable",
+          first.span.clone(),
+        ).into());
+      }
+      self.check_group_limits(collectable)?;
+    }
     Ok(())
   }
 }
@@ -186,6 +538,6 @@ impl<'ast> CustomType<'ast> for User<'ast> {
   }
 
   fn property(&self, name: &str) -> Option<GraphRef<'ast, Variable<'ast>>> {
-    self.properties.get(name).map(|p| p.asleep())
+    self.scope.awake().find(name)
   }
 }
@@ -5,7 +5,7 @@ use serde::ser::{Serialize, Serializer, SerializeStruct};
 use compile::{TokenSpan, TokenValue};
 use util::InsertGraphCell;
 use util::graph_cell::*;
-use ast::expr::BoxExpression;
+use ast::expr::{BoxExpression, expect_assignable};
 use super::*;
 use super::errors::*;
 use super::ty::*;
@@ -15,6 +15,7 @@ pub struct Variable<'a> {
   name: TokenValue<Arc<str>>,
   ty: ItemRef<'a, Type<'a>>,
   initial: Option<BoxExpression<'a>>,
+  kind: PropertyKind<'a>,
 }
 
 impl<'a> Variable<'a> {
@@ -23,7 +24,7 @@ impl<'a> Variable<'a> {
     ty: ItemRef<'a, Type<'a>>,
   ) -> Self
   {
-    Variable { name, ty, initial: None }
+    Variable { name, ty, initial: None, kind: PropertyKind::Stored }
   }
 
   /// Only valid after resolve phase has succeeded.
@@ -31,9 +32,23 @@ impl<'a> Variable<'a> {
     self.ty.item().unwrap()
   }
 
+  /// For lookup properties, this is the value
+  /// used when no entry in the table matches.
+  pub fn initial(&self) -> Option<&BoxExpression<'a>> {
+    self.initial.as_ref()
+  }
+
   pub fn set_initial(&mut self, initial: BoxExpression<'a>) {
     self.initial = Some(initial);
   }
+
+  pub fn kind(&self) -> &PropertyKind<'a> {
+    &self.kind
+  }
+
+  pub fn set_lookup(&mut self, table: LookupTable<'a>) {
+    self.kind = PropertyKind::Lookup(table);
+  }
 }
 
 impl_named!("variable", Variable<'a>);
@@ -45,43 +60,162 @@ impl<'a> SourceItem for Variable<'a> {
   }
 
   fn resolve(&mut self) -> Result<()> {
+    self.ty.resolve()?;
     if let Some(ref mut init) = self.initial {
       init.resolve()?;
     }
+    if let PropertyKind::Lookup(ref mut table) = self.kind {
+      table.resolve()?;
+    }
     Ok(())
   }
 
   fn typecheck(&mut self) -> Result<()> {
+    let ty = self.ty();
     if let Some(ref mut init) = self.initial {
       init.typecheck()?;
+      expect_assignable(&mut **init, ty)?;
+    }
+    if let PropertyKind::Lookup(ref mut table) = self.kind {
+      table.typecheck()?;
+      for entry in &mut table.entries {
+        expect_assignable(&mut *entry.value, ty)?;
+      }
+      if self.initial.is_none() {
+        AmountRange::check_coverage(
+          table.entries.iter().map(|e| &e.amount),
+          "lookup amount",
+        )?;
+      }
     }
     Ok(())
   }
 }
 
-/// Sets the initial value of an inherited variable.
+/// Where a property's value comes from.
+#[derive(Debug, Serialize)]
+pub enum PropertyKind<'a> {
+  /// The value is stored with the instance.
+  Stored,
+  /// The value is picked from a table by the amount of a collectable.
+  Lookup(LookupTable<'a>),
+}
+
+/// A property whose value depends on how many of a collectable
+/// the instance's owner has, falling back to the initial value:
+///
+/// ```text
+/// property greeting of text
+/// for collectable Level
+/// with [
+///   'Hello' for amount 1,
+///   'Goodbye' for amount 2,
+/// ]
+/// or = 'I don''t know';
+/// ```
+#[derive(Debug, Serialize)]
+pub struct LookupTable<'a> {
+  collectable: ItemRef<'a, Collectable<'a>>,
+  entries: Vec<LookupEntry<'a>>,
+  span: TokenSpan,
+}
+
+impl<'a> LookupTable<'a> {
+  pub fn new(
+    collectable: ItemRef<'a, Collectable<'a>>,
+    entries: Vec<LookupEntry<'a>>,
+    span: TokenSpan,
+  ) -> Self
+  {
+    LookupTable { collectable, entries, span }
+  }
+
+  pub fn collectable(&self) -> &ItemRef<'a, Collectable<'a>> {
+    &self.collectable
+  }
+
+  pub fn entries(&self) -> &[LookupEntry<'a>] {
+    &self.entries
+  }
+
+  /// The entry covering `amount`, if there is one.
+  pub fn lookup(&self, amount: i64) -> Option<&LookupEntry<'a>> {
+    self.entries.iter().find(|e| e.amount.contains(amount))
+  }
+}
+
+impl<'a> SourceItem for LookupTable<'a> {
+  fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  fn resolve(&mut self) -> Result<()> {
+    self.collectable.resolve()?;
+    for entry in &mut self.entries {
+      entry.value.resolve()?;
+    }
+    Ok(())
+  }
+
+  fn typecheck(&mut self) -> Result<()> {
+    for entry in &mut self.entries {
+      entry.value.typecheck()?;
+    }
+    AmountRange::check_disjoint(self.entries.iter().map(|e| &e.amount), "lookup amount")
+  }
+}
+
+/// `<value> for amount <range>`
+#[derive(Debug, Serialize)]
+pub struct LookupEntry<'a> {
+  value: BoxExpression<'a>,
+  amount: AmountRange,
+}
+
+impl<'a> LookupEntry<'a> {
+  pub fn new(value: BoxExpression<'a>, amount: AmountRange) -> Self {
+    LookupEntry { value, amount }
+  }
+
+  pub fn value(&self) -> &BoxExpression<'a> {
+    &self.value
+  }
+
+  pub fn amount(&self) -> &AmountRange {
+    &self.amount
+  }
+}
+
+/// Sets the initial value of an inherited variable:
+/// `default .timeToOpen = 3 hours;`
 #[derive(Debug, Serialize)]
 pub struct DefaultValue<'a> {
   name: TokenValue<Arc<str>>,
   value: BoxExpression<'a>,
   #[serde(skip)]
-  var: ResolveLater<'a, Variable<'a>, ScopeFilter<'a>>,
+  var: Option<GraphRef<'a, Variable<'a>>>,
 }
 
 impl<'a> DefaultValue<'a> {
-  pub fn new(
-    name: TokenValue<Arc<str>>,
-    scope: GraphRef<'a, Scope<'a>>,
-    value: BoxExpression<'a>,
-  ) -> Self
-  {
-    let mut scope_kind = scope.awake().kind();
-    scope_kind.remove(ScopeKind::RECURSIVE);
-    let filtered_scope = ScopeFilter::new(scope, scope_kind);
-    DefaultValue {
-      name: name.clone(),
-      value,
-      var: ResolveLater::Unresolved(filtered_scope),
+  pub fn new(name: TokenValue<Arc<str>>, value: BoxExpression<'a>) -> Self {
+    DefaultValue { name, value, var: None }
+  }
+
+  pub fn value(&self) -> &BoxExpression<'a> {
+    &self.value
+  }
+
+  /// The inherited property this sets. Only valid after typechecking.
+  pub fn var(&self) -> GraphRef<'a, Variable<'a>> {
+    self.var.expect("default value not linked to a property")
+  }
+
+  /// Super types are only known after every type has been resolved,
+  /// so the owner looks up the inherited property while typechecking.
+  pub fn link(&mut self, var: Option<GraphRef<'a, Variable<'a>>>) -> Result<()> {
+    match var {
+      Some(var) => Ok(self.var = Some(var)),
+      None => Err(ErrorKind::NotDefined(self.name.clone(), "inherited property").into()),
     }
   }
 }
@@ -95,12 +229,13 @@ impl<'a> SourceItem for DefaultValue<'a> {
   }
 
   fn resolve(&mut self) -> Result<()> {
-    self.var.resolve(Owner::find, &self.name)?;
     self.value.resolve()
   }
 
   fn typecheck(&mut self) -> Result<()> {
-    self.value.typecheck()
+    self.value.typecheck()?;
+    let ty = self.var().awake().ty();
+    expect_assignable(&mut *self.value, ty)
   }
 }
 
@@ -218,9 +353,11 @@ impl<'a> Scope<'a> {
     }
     let p = parent.awake();
     for (key, value) in &self.vars {
-      if p.has(key) {
+      if let Some(first) = p.find(key) {
         return Err(ErrorKind::DuplicateDefinition(
-          value.awake().name().clone(), "variable"
+          value.awake().name().clone(),
+          "variable",
+          first.awake().name().span().clone(),
         ).into());
       }
     }
@@ -265,23 +402,67 @@ impl<'a> Scope<'a> {
   }
 
   pub fn insert(&mut self, var: Variable<'a>) -> Result<GraphRefMut<'a, Variable<'a>>> {
-    let error: Error = ErrorKind::DuplicateDefinition(
-        var.name().clone(), "variable"
-    ).into();
+    let duplicate = |var: &Variable<'a>, first: GraphRef<'a, Variable<'a>>| -> Error {
+      ErrorKind::DuplicateDefinition(
+        var.name().clone(),
+        "variable",
+        first.awake().name().span().clone(),
+      ).into()
+    };
     if let Some(parent) = self.parent {
-      if parent.awake().has(&var.name()) {
-        return Err(error);
+      if let Some(first) = parent.awake().find(var.name().value()) {
+        return Err(duplicate(&var, first));
       }
     }
-    self.vars
-      .insert_graph_cell(var.name().value().clone(), var)
-      .map_err(move |_| error)
+    let name = var.name().value().clone();
+    let vars = &mut self.vars;
+    vars
+      .insert_graph_cell(name.clone(), var)
+      .map_err(|var| duplicate(&var, vars[&name].asleep()))
   }
 
   pub fn kind(&self) -> ScopeKind {
     self.kind
   }
 
+  /// The variables declared directly in this scope.
+  pub fn own_vars<'s>(&'s self) -> impl Iterator<Item = GraphRef<'a, Variable<'a>>> + 's {
+    self.vars.values().map(|v| v.asleep())
+  }
+
+  /// Collected so the scope doesn't have to stay borrowed
+  /// while the variables are modified.
+  pub fn own_vars_mut(&self) -> Vec<GraphRefMut<'a, Variable<'a>>> {
+    self.vars.values().map(|v| v.asleep_mut()).collect()
+  }
+
+  /// Resolves each variable without borrowing the scope, so
+  /// initial values can look up other variables in it.
+  pub fn resolve_vars(this: &GraphCell<Scope<'a>>) -> Result<()> {
+    let vars = this.awake().own_vars_mut();
+    for var in vars {
+      var.awake_mut().resolve()?;
+    }
+    Ok(())
+  }
+
+  pub fn typecheck_vars(this: &GraphCell<Scope<'a>>) -> Result<()> {
+    let vars = this.awake().own_vars_mut();
+    for var in vars {
+      var.awake_mut().typecheck()?;
+    }
+    Ok(())
+  }
+
+  /// Replaces the parent with a super type's scope once it's
+  /// known, so inherited properties can be found from here.
+  /// Unlike `set_parent`, variables declared here may hide the super
+  /// type's: every collectable and group has its own implicit `amount`
+  /// and `owner`, and sub types can override properties.
+  pub fn inherit(&mut self, super_scope: GraphRef<'a, Scope<'a>>) {
+    self.parent = Some(super_scope);
+  }
+
   pub fn level(&self) -> u32 {
     self.parent.map(|p| 1 + p.awake().level()).unwrap_or(0)
   }
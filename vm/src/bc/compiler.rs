@@ -0,0 +1,378 @@
+use std::sync::Arc;
+use std::mem;
+use fxhash::FxHashMap;
+use compile::{TokenSpan, TokenValue};
+use ast::{Ast, AstError, AstResult, Named, Owner, SourceItem};
+use ast::expr::{Constant, Expression};
+use ast::stmt::{BoxStatement, TypeOrExpr};
+use ast::ty::*;
+use ast::var::{Scope, Scoped, Variable};
+use query::Query;
+use util::graph_cell::GraphRef;
+use super::*;
+
+/// Compiles every event, function, property initializer and
+/// distribution. The AST has to have typechecked without errors.
+pub fn compile<'a>(ast: &Ast<'a>) -> ::std::result::Result<Program, Vec<AstError>> {
+  let mut compiler = Compiler::default();
+  let mut errors = Vec::new();
+  let mut types = ast.types().collect::<Vec<_>>();
+  types.sort_by(|a, b| a.awake().name().value().cmp(b.awake().name().value()));
+  for ty in types {
+    let ty = ty.awake();
+    let ty = match ty.as_custom() {
+      Some(ty) => ty,
+      None => continue,
+    };
+    if let Err(e) = compiler.custom_type(ty) {
+      errors.push(e);
+    }
+  }
+  if errors.is_empty() {
+    Ok(compiler.finish())
+  } else {
+    Err(errors)
+  }
+}
+
+#[derive(Default)]
+struct Compiler {
+  pool: Pool,
+  events: Vec<Code>,
+  functions: Vec<Code>,
+  initializers: Vec<Code>,
+  types: Vec<TypeInfo>,
+  distributions: Vec<DistributionTable>,
+}
+
+impl Compiler {
+  fn custom_type<'a>(&mut self, ty: &CustomType<'a>) -> AstResult<()> {
+    match ty.base_type() {
+      BaseCustomType::Event => self.event(Event::try_cast(ty).unwrap()),
+      BaseCustomType::Function => self.function(Function::try_cast(ty).unwrap()),
+      BaseCustomType::Distribution => {
+        let distribution = self.distribution(Distribution::try_cast(ty).unwrap());
+        self.distributions.push(distribution);
+        Ok(())
+      }
+      | BaseCustomType::Object
+      | BaseCustomType::Collectable
+      | BaseCustomType::CollectableGroup
+      | BaseCustomType::User
+      | BaseCustomType::UserGroup
+        => self.type_info(ty),
+      _ => Ok(()),
+    }
+  }
+
+  fn code(&mut self, name: &Arc<str>, kind: CodeKind, params: Vec<Arc<str>>, span: &TokenSpan)
+    -> CodeBuilder
+  {
+    let pool = mem::replace(&mut self.pool, Pool::default());
+    CodeBuilder::new(name.clone(), kind, params, span, pool)
+  }
+
+  fn finish_code(&mut self, code: CodeBuilder) -> Code {
+    let (code, pool) = code.finish();
+    self.pool = pool;
+    code
+  }
+
+  fn event<'a>(&mut self, event: &Event<'a>) -> AstResult<()> {
+    let params = event.params().iter().map(|p| p.value().clone()).collect();
+    let mut code = self.code(event.name().value(), CodeKind::Event, params, event.span());
+    let result = event_body(&mut code, event);
+    let mut code = self.finish_code(code);
+    result?;
+    code.param_types = param_types(&event.scope().awake(), event.params());
+    code.sender_type = match event.sender() {
+      Some(&TypeOrExpr::Type(ref ty)) => Some(ty.name().value().clone()),
+      _ => None,
+    };
+    self.events.push(code);
+    Ok(())
+  }
+
+  fn function<'a>(&mut self, function: &Function<'a>) -> AstResult<()> {
+    let params = function.params().iter().map(|p| p.value().clone()).collect();
+    let mut code = self.code(function.name().value(), CodeKind::Function, params, function.span());
+    let result = function_body(&mut code, function);
+    let mut code = self.finish_code(code);
+    result?;
+    code.param_types = param_types(&function.param_scope().awake(), function.params());
+    self.functions.push(code);
+    Ok(())
+  }
+
+  /// An initializer evaluates one value with the instance
+  /// it's for as `self`, for `.property` references.
+  fn initializer<'a>(&mut self, name: Arc<str>, value: &Expression<'a>) -> AstResult<u32> {
+    let mut code = self.code(&name, CodeKind::Initializer, Vec::new(), value.span());
+    let result = code.expr(value);
+    code.emit(Instr::Return);
+    let code = self.finish_code(code);
+    result?;
+    self.initializers.push(code);
+    Ok(self.initializers.len() as u32 - 1)
+  }
+
+  /// Lookup properties only get their fallback value here,
+  /// since the table depends on the owner's amount.
+  fn property<'a>(&mut self, ty: &Arc<str>, var: &Variable<'a>) -> AstResult<PropertyInfo> {
+    let initializer = match var.initial() {
+      Some(initial) => {
+        let name = format!("{}.{}", ty, var.name().value()).into();
+        Some(self.initializer(name, &**initial)?)
+      }
+      None => None,
+    };
+    Ok(PropertyInfo { name: var.name().value().clone(), initializer })
+  }
+
+  fn type_info<'a>(&mut self, ty: &CustomType<'a>) -> AstResult<()> {
+    let name = ty.name().value().clone();
+    let mut vars = ty.scope().awake().own_vars().collect::<Vec<_>>();
+    vars.sort_by(|a, b| a.awake().span().cmp(b.awake().span()));
+    let mut properties = Vec::with_capacity(vars.len());
+    for var in vars {
+      properties.push(self.property(&name, &var.awake())?);
+    }
+
+    let (super_type, defaults) = if let Some(c) = Collectable::try_cast(ty) {
+      (super_name(c.super_type()), c.defaults())
+    } else if let Some(g) = CollectableGroup::try_cast(ty) {
+      (super_name(g.super_type()), g.defaults())
+    } else if let Some(o) = Object::try_cast(ty) {
+      (super_name(o.super_type()), &[][..])
+    } else {
+      (None, &[][..])
+    };
+    let mut default_infos = Vec::with_capacity(defaults.len());
+    for default in defaults {
+      let property = default.var().awake().name().value().clone();
+      let init_name = format!("{}.{}", name, property).into();
+      let initializer = self.initializer(init_name, &**default.value())?;
+      default_infos.push(PropertyInfo { name: property, initializer: Some(initializer) });
+    }
+
+    self.types.push(TypeInfo {
+      name,
+      base_type: ty.base_type(),
+      super_type,
+      properties,
+      defaults: default_infos,
+    });
+    Ok(())
+  }
+
+  fn distribution<'a>(&mut self, distribution: &Distribution<'a>) -> DistributionTable {
+    DistributionTable {
+      name: distribution.name().value().clone(),
+      group: distribution.group().name().value().clone(),
+      amount: distribution.amount().map(bounds),
+      picks: distribution.picks().map(bounds),
+      entries: distribution.entries().iter().map(|entry| DistributionItem {
+        item: entry.item().name().value().clone(),
+        weight: entry.weight(),
+        amount: entry.amount().map(bounds),
+      }).collect(),
+    }
+  }
+
+  fn finish(self) -> Program {
+    Program {
+      version: FORMAT_VERSION,
+      constants: self.pool.constants,
+      names: self.pool.names,
+      queries: self.pool.queries,
+      events: self.events,
+      functions: self.functions,
+      initializers: self.initializers,
+      types: self.types,
+      distributions: self.distributions,
+    }
+  }
+}
+
+/// The sender check comes first, then the body.
+fn event_body<'a>(code: &mut CodeBuilder, event: &Event<'a>) -> AstResult<()> {
+  if let Some(sender) = event.sender() {
+    sender.compile_authorize(code)?;
+  }
+  code.block(event.body())?;
+  code.emit(Instr::End);
+  Ok(())
+}
+
+/// Functions without a return type end like events, returning void.
+fn function_body<'a>(code: &mut CodeBuilder, function: &Function<'a>) -> AstResult<()> {
+  code.block(function.body())?;
+  match function.result() {
+    Some(result) => {
+      code.expr(&**result)?;
+      code.emit(Instr::Return);
+    }
+    None => {
+      code.emit(Instr::End);
+    }
+  }
+  Ok(())
+}
+
+/// Parameters are in the innermost scope.
+fn param_types<'a>(scope: &Scope<'a>, params: &[TokenValue<Arc<str>>]) -> Vec<Arc<str>> {
+  params.iter().map(|param| {
+    let var = scope.find(param.value()).expect("parameter not in scope");
+    let ty = var.awake().ty();
+    let name = ty.awake().name().value().clone();
+    name
+  }).collect()
+}
+
+fn super_name<'a, T: Named + ?Sized + 'a>(super_type: Option<GraphRef<'a, T>>) -> Option<Arc<str>> {
+  super_type.map(|s| s.awake().name().value().clone())
+}
+
+fn bounds(range: &AmountRange) -> Bounds {
+  Bounds { min: range.min(), max: range.max() }
+}
+
+/// What's shared between code objects.
+#[derive(Default)]
+struct Pool {
+  constants: Vec<Constant>,
+  names: Vec<Arc<str>>,
+  name_indexes: FxHashMap<Arc<str>, u32>,
+  queries: Vec<Query<()>>,
+}
+
+/// Builds one code object. Expressions and statements
+/// add their own instructions through this.
+pub struct CodeBuilder {
+  code: Code,
+  pool: Pool,
+  /// The innermost item being compiled is last.
+  spans: Vec<TokenSpan>,
+}
+
+impl CodeBuilder {
+  fn new(name: Arc<str>, kind: CodeKind, params: Vec<Arc<str>>, span: &TokenSpan, pool: Pool)
+    -> Self
+  {
+    CodeBuilder {
+      code: Code {
+        name,
+        kind,
+        params,
+        param_types: Vec::new(),
+        sender_type: None,
+        instrs: Vec::new(),
+        branches: Vec::new(),
+        file: span.filename.to_string_lossy().into_owned().into(),
+        positions: Vec::new(),
+      },
+      pool,
+      spans: vec![span.clone()],
+    }
+  }
+
+  fn finish(self) -> (Code, Pool) {
+    (self.code, self.pool)
+  }
+
+  /// Appends an instruction and returns its index.
+  pub fn emit(&mut self, instr: Instr) -> u32 {
+    let pc = self.pc();
+    let span = self.spans.last().unwrap();
+    let pos = SourcePos { line: span.line as u32, column: span.start as u32 };
+    if self.code.positions.last().map(|&(_, p)| p) != Some(pos) {
+      self.code.positions.push((pc, pos));
+    }
+    self.code.instrs.push(instr);
+    pc
+  }
+
+  /// The index of the next instruction.
+  pub fn pc(&self) -> u32 {
+    self.code.instrs.len() as u32
+  }
+
+  /// Points the jump at `at` to the next instruction.
+  pub fn patch(&mut self, at: u32) {
+    let target = self.pc();
+    self.code.instrs[at as usize].set_jump_target(target);
+  }
+
+  pub fn constant(&mut self, value: Constant) -> u32 {
+    match self.pool.constants.iter().position(|c| c == &value) {
+      Some(index) => index as u32,
+      None => {
+        self.pool.constants.push(value);
+        self.pool.constants.len() as u32 - 1
+      }
+    }
+  }
+
+  pub fn name(&mut self, name: &Arc<str>) -> u32 {
+    if let Some(&index) = self.pool.name_indexes.get(name) {
+      return index;
+    }
+    let index = self.pool.names.len() as u32;
+    self.pool.names.push(name.clone());
+    self.pool.name_indexes.insert(name.clone(), index);
+    index
+  }
+
+  pub fn query(&mut self, query: Query<()>) -> u32 {
+    self.pool.queries.push(query);
+    self.pool.queries.len() as u32 - 1
+  }
+
+  /// Adds an empty branch table for `Instr::Option`.
+  pub fn branch_table(&mut self) -> u32 {
+    self.code.branches.push(Vec::new());
+    self.code.branches.len() as u32 - 1
+  }
+
+  /// Starts a branch at the next instruction.
+  pub fn start_branch(&mut self, table: u32) {
+    let pc = self.pc();
+    self.code.branches[table as usize].push(pc);
+  }
+
+  /// The slot for a parameter.
+  pub fn local(&self, name: &str) -> Option<u16> {
+    self.code.params.iter().position(|p| &**p == name).map(|i| i as u16)
+  }
+
+  /// Only initializers have an instance to refer to.
+  pub fn has_self(&self) -> bool {
+    self.code.kind == CodeKind::Initializer
+  }
+
+  /// Compiles an expression, leaving its value on the stack.
+  /// Constant expressions are just their folded value.
+  pub fn expr<'a>(&mut self, expr: &Expression<'a>) -> AstResult<()> {
+    if let Some(value) = expr.constant() {
+      let index = self.constant(value.clone());
+      self.spans.push(expr.span().clone());
+      self.emit(Instr::Const(index));
+      self.spans.pop();
+      return Ok(());
+    }
+    self.spans.push(expr.span().clone());
+    let result = expr.compile(self);
+    self.spans.pop();
+    result
+  }
+
+  pub fn block<'a>(&mut self, block: &[BoxStatement<'a>]) -> AstResult<()> {
+    for stmt in block {
+      self.spans.push(stmt.span().clone());
+      let result = stmt.compile(self);
+      self.spans.pop();
+      result?;
+    }
+    Ok(())
+  }
+}
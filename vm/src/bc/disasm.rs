@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+use query::{Query, UserFilter};
+use super::*;
+
+/// Writes a readable listing of the whole program.
+pub fn disassemble<W: Write>(program: &Program, out: &mut W) -> io::Result<()> {
+  write!(out, "; program format version {}\n", program.version)?;
+  if !program.constants.is_empty() {
+    out.write_all(b"\nconstants:\n")?;
+    for (i, constant) in program.constants.iter().enumerate() {
+      write!(out, "  {:>4}  {}\n", i, constant)?;
+    }
+  }
+  if !program.queries.is_empty() {
+    out.write_all(b"\nqueries:\n")?;
+    for (i, query) in program.queries.iter().enumerate() {
+      write!(out, "  {:>4}  {}\n", i, describe_query(query))?;
+    }
+  }
+  for ty in &program.types {
+    write!(out, "\n{} {}", ty.base_type, ty.name)?;
+    if let Some(ref super_type) = ty.super_type {
+      write!(out, " in {}", super_type)?;
+    }
+    out.write_all(b"\n")?;
+    for &(kind, props) in [("property", &ty.properties), ("default", &ty.defaults)].iter() {
+      for prop in props.iter() {
+        write!(out, "  {} {}", kind, prop.name)?;
+        if let Some(init) = prop.initializer {
+          write!(out, " = initializer {}", init)?;
+        }
+        out.write_all(b"\n")?;
+      }
+    }
+  }
+  for distribution in &program.distributions {
+    write!(out, "\ndistribution of {} {}\n", distribution.group, distribution.name)?;
+    if let Some(ref amount) = distribution.amount {
+      write!(out, "  amount {}\n", describe_bounds(amount))?;
+    }
+    if let Some(ref picks) = distribution.picks {
+      write!(out, "  picks {}\n", describe_bounds(picks))?;
+    }
+    for entry in &distribution.entries {
+      write!(out, "  {}", entry.item)?;
+      if let Some(weight) = entry.weight {
+        write!(out, " weighted {}%", weight * 100.0)?;
+      }
+      if let Some(ref amount) = entry.amount {
+        write!(out, " x {}", describe_bounds(amount))?;
+      }
+      out.write_all(b"\n")?;
+    }
+  }
+  for (i, code) in program.initializers.iter().enumerate() {
+    write!(out, "\ninitializer {} ", i)?;
+    disassemble_code(program, code, out)?;
+  }
+  for code in program.functions.iter().chain(program.events.iter()) {
+    out.write_all(b"\n")?;
+    disassemble_code(program, code, out)?;
+  }
+  Ok(())
+}
+
+/// Writes one code object, with each instruction's source
+/// position and what its operands refer to.
+pub fn disassemble_code<W: Write>(program: &Program, code: &Code, out: &mut W)
+  -> io::Result<()>
+{
+  let kind = match code.kind {
+    CodeKind::Event => "event",
+    CodeKind::Function => "function",
+    CodeKind::Initializer => "for",
+  };
+  let params = code.params
+    .iter()
+    .zip(code.param_types.iter())
+    .map(|(name, ty)| format!("{} {}", name, ty))
+    .collect::<Vec<_>>();
+  write!(out, "{} {}({})", kind, code.name, params.join(", "))?;
+  if let Some(ref sender) = code.sender_type {
+    write!(out, " <- {}", sender)?;
+  }
+  write!(out, " in {}\n", code.file)?;
+  let mut positions = code.positions.iter().peekable();
+  for (pc, instr) in code.instrs.iter().enumerate() {
+    let pc = pc as u32;
+    let mut pos = String::new();
+    while positions.peek().map(|&&(start, _)| start <= pc).unwrap_or(false) {
+      let &(_, p) = positions.next().unwrap();
+      pos = format!("{}:{}", p.line, p.column);
+    }
+    write!(out, "  {:>4}  {:<8}  {:<24}", pc, pos, instr.to_string())?;
+    if let Some(comment) = comment(program, code, instr) {
+      write!(out, "; {}", comment)?;
+    }
+    out.write_all(b"\n")?;
+  }
+  for (i, branches) in code.branches.iter().enumerate() {
+    let starts = branches.iter().map(|b| b.to_string()).collect::<Vec<_>>();
+    write!(out, "  branches {}: {}\n", i, starts.join(", "))?;
+  }
+  Ok(())
+}
+
+fn comment(program: &Program, code: &Code, instr: &Instr) -> Option<String> {
+  Some(match *instr {
+    Instr::Const(n) => program.constant(n).to_string(),
+    | Instr::LoadLocal(n)
+    | Instr::StoreLocal(n)
+      => code.params[n as usize].to_string(),
+    | Instr::LoadGlobal(n)
+    | Instr::StoreGlobal(n)
+    | Instr::GetProperty(n)
+    | Instr::SetProperty(n)
+    | Instr::AuthorizeType(n)
+    | Instr::AwardType(_, n)
+    | Instr::Cost(n)
+    | Instr::Call(n, _)
+      => program.name(n).to_string(),
+    Instr::Find(n) => describe_query(program.query(n)),
+    _ => return None,
+  })
+}
+
+fn describe_query(query: &Query<()>) -> String {
+  match *query {
+    Query::EventInstance { ref event, .. } => format!("event {} for _", event),
+    Query::User { ref user_type, ref filter } => {
+      let filter = match *filter {
+        Some(UserFilter::Property { ref name, .. }) => format!(" with .{} = _", name),
+        Some(UserFilter::Amount { ref collectable, min, max }) => format!(
+          " with amount {} of collectable {}",
+          describe_bounds(&Bounds { min, max }),
+          collectable,
+        ),
+        Some(UserFilter::SimilarAmount { ref collectable }) => {
+          format!(" with similar amount of collectable {}", collectable)
+        }
+        None => String::new(),
+      };
+      format!("user {}{}", user_type, filter)
+    }
+    Query::GameServer { ref switch, .. } => format!("gameserver with switch {} = _", switch),
+  }
+}
+
+fn describe_bounds(bounds: &Bounds) -> String {
+  match (bounds.min, bounds.max) {
+    (Some(min), Some(max)) if min == max => min.to_string(),
+    (Some(min), Some(max)) => format!("range({}, {})", min, max),
+    (Some(min), None) => format!("min {}", min),
+    (None, Some(max)) => format!("max {}", max),
+    (None, None) => "any".to_owned(),
+  }
+}
@@ -0,0 +1,169 @@
+use std::fmt::{self, Display};
+use ast::expr::BinaryOperator;
+use ast::stmt::AwardSign;
+
+/// One stack machine instruction. Operands that name something are
+/// indexes into the program's pools, and jump targets are
+/// instruction indexes in the same code object.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Instr {
+  /// Pushes a constant from the constant pool.
+  Const(u32),
+  Pop,
+  Dup,
+  /// Parameters are the only locals, in declaration order.
+  LoadLocal(u16),
+  StoreLocal(u16),
+  LoadGlobal(u32),
+  StoreGlobal(u32),
+  /// The instance whose property is being initialized.
+  LoadSelf,
+  /// The user who sent the event.
+  LoadSender,
+  /// Replaces the instance on the stack with its property.
+  GetProperty(u32),
+  /// Pops the value, then the instance.
+  SetProperty(u32),
+  Not,
+  Neg,
+  /// Any operator but `.`, `and` and `or`, which are compiled
+  /// to property access and jumps.
+  Binary(BinaryOperator),
+  /// Pops the index, then the array.
+  Index,
+  /// Pops that many values, the last one on top.
+  MakeArray(u32),
+  /// Pops that many key and value pairs. The keys are text.
+  MakeObject(u32),
+  /// Runs a query from the query pool, popping its operands.
+  Find(u32),
+  /// Pops that many arguments, the last one on top, and
+  /// pushes the result of the named function.
+  Call(u32, u16),
+  Jump(u32),
+  /// Pops an option and jumps if it's `yes`.
+  JumpIf(u32),
+  /// Pops an option and jumps if it's `no`.
+  JumpUnless(u32),
+  /// Pops an option and fails the invocation if it's `no`.
+  Assert,
+  /// Pops a user and fails unless it sent the event.
+  Authorize,
+  /// Fails unless the sender is of the named user type or in the group.
+  AuthorizeType(u32),
+  /// Pops the target user and the amount of the named
+  /// collectable, group or distribution.
+  AwardType(AwardSign, u32),
+  /// Pops the target user, the amount and the instance.
+  AwardInstance(AwardSign),
+  /// Pops the amount of the named collectable the sender has to pay.
+  Cost(u32),
+  /// Pops a time span to wait for.
+  Timer,
+  /// Starts every branch in the branch table. The first one
+  /// to get through its first wait continues, the rest are cancelled.
+  Option(u32),
+  /// The end of an option branch, jumping past the option.
+  EndBranch(u32),
+  /// Pops a function's result.
+  Return,
+  /// The end of an event or initializer.
+  End,
+}
+
+impl Instr {
+  pub fn mnemonic(&self) -> &'static str {
+    match *self {
+      Instr::Const(_) => "const",
+      Instr::Pop => "pop",
+      Instr::Dup => "dup",
+      Instr::LoadLocal(_) => "load_local",
+      Instr::StoreLocal(_) => "store_local",
+      Instr::LoadGlobal(_) => "load_global",
+      Instr::StoreGlobal(_) => "store_global",
+      Instr::LoadSelf => "load_self",
+      Instr::LoadSender => "load_sender",
+      Instr::GetProperty(_) => "get_property",
+      Instr::SetProperty(_) => "set_property",
+      Instr::Not => "not",
+      Instr::Neg => "neg",
+      Instr::Binary(_) => "binary",
+      Instr::Index => "index",
+      Instr::MakeArray(_) => "make_array",
+      Instr::MakeObject(_) => "make_object",
+      Instr::Find(_) => "find",
+      Instr::Call(..) => "call",
+      Instr::Jump(_) => "jump",
+      Instr::JumpIf(_) => "jump_if",
+      Instr::JumpUnless(_) => "jump_unless",
+      Instr::Assert => "assert",
+      Instr::Authorize => "authorize",
+      Instr::AuthorizeType(_) => "authorize_type",
+      Instr::AwardType(..) => "award_type",
+      Instr::AwardInstance(_) => "award_instance",
+      Instr::Cost(_) => "cost",
+      Instr::Timer => "timer",
+      Instr::Option(_) => "option",
+      Instr::EndBranch(_) => "end_branch",
+      Instr::Return => "return",
+      Instr::End => "end",
+    }
+  }
+
+  /// The instruction this one may jump to.
+  pub fn jump_target(&self) -> Option<u32> {
+    match *self {
+      | Instr::Jump(target)
+      | Instr::JumpIf(target)
+      | Instr::JumpUnless(target)
+      | Instr::EndBranch(target)
+        => Some(target),
+      _ => None,
+    }
+  }
+
+  /// Points a jump at `target`. Other instructions are unchanged.
+  pub fn set_jump_target(&mut self, target: u32) {
+    match *self {
+      | Instr::Jump(ref mut t)
+      | Instr::JumpIf(ref mut t)
+      | Instr::JumpUnless(ref mut t)
+      | Instr::EndBranch(ref mut t)
+        => *t = target,
+      _ => {}
+    }
+  }
+}
+
+/// Shows raw pool indexes. The disassembler shows what they refer to.
+impl Display for Instr {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.mnemonic())?;
+    match *self {
+      | Instr::Const(n)
+      | Instr::LoadGlobal(n)
+      | Instr::StoreGlobal(n)
+      | Instr::GetProperty(n)
+      | Instr::SetProperty(n)
+      | Instr::MakeArray(n)
+      | Instr::MakeObject(n)
+      | Instr::Find(n)
+      | Instr::Jump(n)
+      | Instr::JumpIf(n)
+      | Instr::JumpUnless(n)
+      | Instr::AuthorizeType(n)
+      | Instr::Cost(n)
+      | Instr::Option(n)
+      | Instr::EndBranch(n)
+        => write!(f, " {}", n),
+      | Instr::LoadLocal(n)
+      | Instr::StoreLocal(n)
+        => write!(f, " {}", n),
+      Instr::Call(n, args) => write!(f, " {} {}", n, args),
+      Instr::Binary(op) => write!(f, " {}", op),
+      Instr::AwardType(sign, n) => write!(f, " {}{}", sign.as_str(), n),
+      Instr::AwardInstance(sign) => write!(f, " {}", sign.as_str()),
+      _ => Ok(()),
+    }
+  }
+}
@@ -0,0 +1,15 @@
+//! Bytecode for a typechecked program. Each event, function and
+//! property initializer is a code object for a small stack machine,
+//! sharing the program's constant, name and query pools. A compiled
+//! `Program` doesn't refer to the AST, so it can be saved and loaded
+//! without the source.
+
+mod compiler;
+mod disasm;
+mod instr;
+mod program;
+
+pub use self::compiler::*;
+pub use self::disasm::*;
+pub use self::instr::*;
+pub use self::program::*;
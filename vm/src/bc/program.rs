@@ -0,0 +1,196 @@
+use std::sync::Arc;
+use std::io::{Read, Write};
+use serde::de::Error as DeError;
+use serde_json;
+use ast::expr::Constant;
+use ast::ty::BaseCustomType;
+use query::Query;
+use super::Instr;
+
+/// Bumped whenever a change to the instructions or the
+/// program layout would make older programs load wrong.
+pub const FORMAT_VERSION: u32 = 2;
+
+/// A whole compiled program. Everything is looked up by name,
+/// so it doesn't need the AST it was compiled from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Program {
+  pub version: u32,
+  pub constants: Vec<Constant>,
+  /// Property, type and global names.
+  pub names: Vec<Arc<str>>,
+  /// `find` expressions with their operands left on the stack.
+  pub queries: Vec<Query<()>>,
+  pub events: Vec<Code>,
+  pub functions: Vec<Code>,
+  /// Initial and default property values.
+  pub initializers: Vec<Code>,
+  pub types: Vec<TypeInfo>,
+  pub distributions: Vec<DistributionTable>,
+}
+
+impl Program {
+  pub fn constant(&self, index: u32) -> &Constant {
+    &self.constants[index as usize]
+  }
+
+  pub fn name(&self, index: u32) -> &Arc<str> {
+    &self.names[index as usize]
+  }
+
+  pub fn query(&self, index: u32) -> &Query<()> {
+    &self.queries[index as usize]
+  }
+
+  pub fn event(&self, name: &str) -> Option<&Code> {
+    self.events.iter().find(|c| &*c.name == name)
+  }
+
+  pub fn function(&self, name: &str) -> Option<&Code> {
+    self.functions.iter().find(|c| &*c.name == name)
+  }
+
+  pub fn type_info(&self, name: &str) -> Option<&TypeInfo> {
+    self.types.iter().find(|t| &*t.name == name)
+  }
+
+  pub fn distribution(&self, name: &str) -> Option<&DistributionTable> {
+    self.distributions.iter().find(|d| &*d.name == name)
+  }
+
+  /// The initializer for a property on an instance of `ty`, which is
+  /// the closest default or initial value going up the super types.
+  pub fn initializer(&self, ty: &str, property: &str) -> Option<&Code> {
+    let mut next = self.type_info(ty);
+    while let Some(info) = next {
+      let init = info.defaults
+        .iter()
+        .chain(info.properties.iter())
+        .find(|p| &*p.name == property)
+        .and_then(|p| p.initializer);
+      if let Some(init) = init {
+        return Some(&self.initializers[init as usize]);
+      }
+      next = info.super_type.as_ref().and_then(|s| self.type_info(s));
+    }
+    None
+  }
+
+  /// Whether `ty` is `other` or one of its sub types.
+  pub fn is_a(&self, ty: &str, other: &str) -> bool {
+    let mut next = Some(ty);
+    while let Some(name) = next {
+      if name == other {
+        return true;
+      }
+      next = self.type_info(name).and_then(|t| t.super_type.as_ref()).map(|s| &**s);
+    }
+    false
+  }
+
+  pub fn write_json<W: Write>(&self, writer: W) -> serde_json::Result<()> {
+    serde_json::to_writer(writer, self)
+  }
+
+  /// Fails if the program was written by a different format version.
+  pub fn read_json<R: Read>(reader: R) -> serde_json::Result<Self> {
+    let program: Program = serde_json::from_reader(reader)?;
+    if program.version != FORMAT_VERSION {
+      return Err(serde_json::Error::custom(format!(
+        "program format version {} isn't supported (expected {})",
+        program.version,
+        FORMAT_VERSION,
+      )));
+    }
+    Ok(program)
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CodeKind {
+  Event,
+  Function,
+  Initializer,
+}
+
+/// The code for one event, function or property initializer.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Code {
+  pub name: Arc<str>,
+  pub kind: CodeKind,
+  /// The names of the locals, in order.
+  pub params: Vec<Arc<str>>,
+  /// The type of each parameter.
+  pub param_types: Vec<Arc<str>>,
+  /// The user type or group allowed to send an event, when it's named.
+  pub sender_type: Option<Arc<str>>,
+  pub instrs: Vec<Instr>,
+  /// The start of each branch for `Instr::Option`.
+  pub branches: Vec<Vec<u32>>,
+  pub file: Arc<str>,
+  /// Where the code starting at each instruction
+  /// came from, in instruction order.
+  pub positions: Vec<(u32, SourcePos)>,
+}
+
+impl Code {
+  /// Where the instruction at `pc` came from.
+  pub fn position(&self, pc: u32) -> Option<&SourcePos> {
+    let index = match self.positions.binary_search_by_key(&pc, |&(p, _)| p) {
+      Ok(index) => index,
+      Err(0) => return None,
+      Err(index) => index - 1,
+    };
+    Some(&self.positions[index].1)
+  }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourcePos {
+  pub line: u32,
+  pub column: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TypeInfo {
+  pub name: Arc<str>,
+  pub base_type: BaseCustomType,
+  pub super_type: Option<Arc<str>>,
+  pub properties: Vec<PropertyInfo>,
+  /// Values for inherited properties.
+  pub defaults: Vec<PropertyInfo>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PropertyInfo {
+  pub name: Arc<str>,
+  /// An index into `Program::initializers`.
+  pub initializer: Option<u32>,
+}
+
+/// An amount range; either end may be open.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Bounds {
+  pub min: Option<i64>,
+  pub max: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistributionTable {
+  pub name: Arc<str>,
+  pub group: Arc<str>,
+  /// The total amount awarded.
+  pub amount: Option<Bounds>,
+  /// How many of the entries are picked.
+  pub picks: Option<Bounds>,
+  pub entries: Vec<DistributionItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DistributionItem {
+  pub item: Arc<str>,
+  /// A fraction between 0 and 1. Items without one share
+  /// whatever the explicit weights leave over.
+  pub weight: Option<f64>,
+  pub amount: Option<Bounds>,
+}
@@ -0,0 +1,384 @@
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::cell::RefCell;
+use fxhash::FxHashMap;
+use util::termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
+use ast::{AstError, AstErrorKind};
+use super::{ParseError, ParseErrorKind, TokenSpan};
+
+/// A message about some source code, pointing at
+/// the main location and anything related to it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+  message: String,
+  primary: Option<Label>,
+  secondary: Vec<Label>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+  span: TokenSpan,
+  message: String,
+}
+
+impl Label {
+  pub fn new<S: Into<String>>(span: TokenSpan, message: S) -> Self {
+    Label { span, message: message.into() }
+  }
+
+  pub fn span(&self) -> &TokenSpan {
+    &self.span
+  }
+
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+}
+
+impl Diagnostic {
+  /// A message that isn't about any particular place in the source.
+  pub fn new<S: Into<String>>(message: S) -> Self {
+    Diagnostic { message: message.into(), primary: None, secondary: Vec::new() }
+  }
+
+  pub fn at<S: Into<String>>(message: S, span: TokenSpan) -> Self {
+    Self::new(message).with_primary(span, "")
+  }
+
+  pub fn with_primary<S: Into<String>>(mut self, span: TokenSpan, message: S) -> Self {
+    self.primary = Some(Label::new(span, message));
+    self
+  }
+
+  pub fn with_secondary<S: Into<String>>(mut self, span: TokenSpan, message: S) -> Self {
+    self.secondary.push(Label::new(span, message));
+    self
+  }
+
+  pub fn message(&self) -> &str {
+    &self.message
+  }
+
+  pub fn primary(&self) -> Option<&Label> {
+    self.primary.as_ref()
+  }
+
+  pub fn secondary(&self) -> &[Label] {
+    &self.secondary
+  }
+}
+
+pub trait ToDiagnostic {
+  fn to_diagnostic(&self) -> Diagnostic;
+}
+
+impl ToDiagnostic for ParseError {
+  fn to_diagnostic(&self) -> Diagnostic {
+    match *self.kind() {
+      ParseErrorKind::Nom(ref span) => Diagnostic::at("invalid token", span.clone()),
+      ParseErrorKind::UnclosedString(ref span) => match span.as_ref() {
+        Some(span) => Diagnostic::new("unclosed string")
+          .with_primary(span.clone(), "string starts after this"),
+        None => Diagnostic::new("unclosed string"),
+      },
+      ParseErrorKind::Unexpected(ref token) => Diagnostic::at(
+        format!("unexpected token '{}'", token.value()),
+        token.span().clone(),
+      ),
+      ParseErrorKind::Expected(ref expected, ref found) => Diagnostic::new(
+        format!("expected '{}', found '{}'", expected, found.value())
+      ).with_primary(found.span().clone(), format!("expected '{}'", expected)),
+      ParseErrorKind::Syntax(ref message, ref location) => Diagnostic::at(
+        format!("syntax error: {}", message),
+        location.clone(),
+      ),
+      ParseErrorKind::InvalidOperation(operation, ref location) => Diagnostic::at(
+        format!("invalid operation: {}", operation),
+        location.clone(),
+      ),
+      ParseErrorKind::IntegerOutOfRange(ref integer, reason) => Diagnostic::new(
+        format!("integer '{}' out of range", integer.value())
+      ).with_primary(integer.span().clone(), reason),
+      ParseErrorKind::IncludeNotFound(ref path) => Diagnostic::new(
+        format!("can't find '{}' in the include path", path.value())
+      ).with_primary(path.span().clone(), "included here"),
+      ParseErrorKind::IncludeCycle(ref chain, ref sites) => {
+        let mut diagnostic = Diagnostic::new(format!(
+          "include cycle: {}",
+          chain
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+        ));
+        if let Some((last, rest)) = sites.split_last() {
+          diagnostic = diagnostic.with_primary(last.clone(), "cycle closes here");
+          for site in rest {
+            diagnostic = diagnostic.with_secondary(site.clone(), "included from here");
+          }
+        }
+        diagnostic
+      }
+      ParseErrorKind::Ast(ref e) => e.to_diagnostic(),
+      _ => Diagnostic::new(self.to_string()),
+    }
+  }
+}
+
+impl ToDiagnostic for AstError {
+  fn to_diagnostic(&self) -> Diagnostic {
+    match *self.kind() {
+      AstErrorKind::NotDefined(ref name, typ) => Diagnostic::new(
+        format!("no definition for {} '{}'", typ, name.value())
+      ).with_primary(name.span().clone(), "not found"),
+      AstErrorKind::DuplicateDefinition(ref name, typ, ref first) => Diagnostic::new(
+        format!("{} '{}' already defined", typ, name.value())
+      )
+        .with_primary(name.span().clone(), "defined again here")
+        .with_secondary(first.clone(), "first defined here"),
+      AstErrorKind::TypeResolution(ref expected, ref found) => Diagnostic::new(
+        format!("expected type '{}', found '{}' instead", expected, found.value())
+      ).with_primary(found.span().clone(), format!("expected '{}'", expected)),
+      AstErrorKind::ConflictingSuperType(ref ty, ref parent, ref conflicting) => {
+        Diagnostic::new(format!(
+          "can't set super type of '{}' to '{}' because it already has super type '{}'",
+          ty,
+          conflicting.value(),
+          parent
+        )).with_primary(conflicting.span().clone(), "second super type")
+      }
+      AstErrorKind::InvalidExpression(ref expr, ref span) => Diagnostic::at(
+        format!("invalid expression '{}'", expr),
+        span.clone(),
+      ),
+      AstErrorKind::ValueOutOfRange(ref value, reason, ref location) => Diagnostic::new(
+        format!("value '{}' out of range", value)
+      ).with_primary(location.clone(), reason),
+      AstErrorKind::InvalidOperands(ref operator, ref left, ref right) => {
+        Diagnostic::new(format!(
+          "operator '{}' can't be applied to types '{}' and '{}'",
+          operator.value(),
+          left.value(),
+          right.value()
+        ))
+          .with_primary(operator.span().clone(), "invalid operands")
+          .with_secondary(left.span().clone(), format!("this is '{}'", left.value()))
+          .with_secondary(right.span().clone(), format!("this is '{}'", right.value()))
+      }
+      AstErrorKind::RangeOverlap(what, ref first, ref second) => Diagnostic::new(
+        format!("overlapping {} ranges", what)
+      )
+        .with_primary(second.clone(), "this range overlaps")
+        .with_secondary(first.clone(), "with this one"),
+      AstErrorKind::RangeGap(what, ref missing, ref before, ref after) => Diagnostic::new(
+        format!("{} ranges leave out {}", what, missing)
+      )
+        .with_primary(before.clone(), "this range ends")
+        .with_secondary(after.clone(), "before this one starts"),
+      AstErrorKind::NotInGroup(ref item, ref group) => Diagnostic::new(
+        format!("'{}' is not in collectable group '{}'", item.value(), group)
+      ).with_primary(item.span().clone(), ""),
+      AstErrorKind::InvalidStatement(reason, ref location) => Diagnostic::new(
+        "statement not allowed here"
+      ).with_primary(location.clone(), reason),
+      AstErrorKind::NotAssignable(ref expr, ref location) => Diagnostic::at(
+        format!("can't assign to '{}'", expr),
+        location.clone(),
+      ),
+      AstErrorKind::IntegerOutOfRange(ref location) => Diagnostic::new(
+        "integer out of range"
+      ).with_primary(location.clone(), "this doesn't fit in a 64 bit integer"),
+      AstErrorKind::DivisionByZero(ref location) => Diagnostic::new(
+        "division by zero"
+      ).with_primary(location.clone(), "the right side is always zero"),
+      AstErrorKind::PrecedenceCycle(ref chain) => {
+        let mut diagnostic = Diagnostic::new(format!(
+          "user group precedence cycle: {}",
+          chain.iter().map(|n| &**n.value()).collect::<Vec<_>>().join(" -> ")
+        ));
+        if let Some((first, rest)) = chain[1..].split_first() {
+          diagnostic = diagnostic.with_primary(
+            first.span().clone(),
+            format!("puts '{}' above '{}'", chain[0].value(), first.value()),
+          );
+          for (from, to) in chain[1..].iter().zip(rest) {
+            diagnostic = diagnostic.with_secondary(
+              to.span().clone(),
+              format!("puts '{}' above '{}'", from.value(), to.value()),
+            );
+          }
+        }
+        diagnostic
+      }
+      AstErrorKind::MissingDefault(ref ty, ref property) => Diagnostic::new(
+        format!("'{}' has no value for property '{}'", ty.value(), property.value())
+      )
+        .with_primary(ty.span().clone(), format!("needs `default .{} = ...`", property.value()))
+        .with_secondary(property.span().clone(), "declared here without a value"),
+      AstErrorKind::NoProperty(ref name, ref ty) => Diagnostic::new(
+        format!("no property `{}` on type {}", name.value(), ty)
+      ).with_primary(name.span().clone(), "unknown property"),
+      AstErrorKind::ArgumentCount(ref function, expected, found) => Diagnostic::new(
+        format!("'{}' takes {} argument(s), but {} were given", function.value(), expected, found)
+      ).with_primary(function.span().clone(), "called here"),
+      _ => Diagnostic::new(self.to_string()),
+    }
+  }
+}
+
+/// Prints diagnostics along with the source lines they point to:
+///
+/// ```text
+/// error: collectable 'Coin' already defined
+///   --> test/cards.scifi:12:13
+///    |
+/// 12 | collectable Coin;
+///    |             ^^^^ defined again here
+///    |
+///  3 | collectable Coin;
+///    |             ---- first defined here
+/// ```
+pub struct Renderer {
+  color: ColorChoice,
+  sources: RefCell<FxHashMap<Arc<PathBuf>, Option<Vec<String>>>>,
+}
+
+impl Renderer {
+  pub fn new(color: ColorChoice) -> Self {
+    Renderer { color, sources: Default::default() }
+  }
+
+  /// For programs that weren't loaded from a file.
+  pub fn add_source(&self, filename: Arc<PathBuf>, source: &str) {
+    self.sources.borrow_mut().insert(filename, Some(split_lines(source)));
+  }
+
+  /// Writes to stderr. If this fails there's nowhere left to report it.
+  pub fn emit_all<'a, I>(&self, diagnostics: I)
+  where I: IntoIterator<Item = &'a Diagnostic>
+  {
+    let mut stderr = StandardStream::stderr(self.color);
+    for diagnostic in diagnostics {
+      let _ = self.emit(&mut stderr, diagnostic);
+    }
+  }
+
+  pub fn emit<W: WriteColor>(&self, out: &mut W, diagnostic: &Diagnostic) -> io::Result<()> {
+    let mut spec = ColorSpec::new();
+    out.set_color(spec.set_fg(Some(Color::Red)).set_bold(true))?;
+    write!(out, "error")?;
+    out.set_color(ColorSpec::new().set_bold(true))?;
+    writeln!(out, ": {}", diagnostic.message)?;
+    out.reset()?;
+
+    let primary = match diagnostic.primary {
+      Some(ref primary) => primary,
+      None => return writeln!(out, ""),
+    };
+    let gutter = diagnostic.secondary
+      .iter()
+      .chain(Some(primary))
+      .map(|label| label.span.line.to_string().len())
+      .max()
+      .unwrap_or(1);
+
+    self.write_gutter(out, gutter, "-->")?;
+    writeln!(
+      out,
+      " {}:{}:{}",
+      primary.span.filename.display(),
+      primary.span.line,
+      primary.span.start,
+    )?;
+    self.write_label(out, gutter, primary, '^', Color::Red)?;
+    for label in &diagnostic.secondary {
+      if label.span.filename != primary.span.filename {
+        self.write_gutter(out, gutter, "::")?;
+        writeln!(
+          out,
+          " {}:{}:{}",
+          label.span.filename.display(),
+          label.span.line,
+          label.span.start,
+        )?;
+      }
+      self.write_label(out, gutter, label, '-', Color::Blue)?;
+    }
+    writeln!(out, "")
+  }
+
+  fn write_gutter<W: WriteColor>(&self, out: &mut W, width: usize, text: &str)
+    -> io::Result<()>
+  {
+    out.set_color(ColorSpec::new().set_fg(Some(Color::Blue)).set_bold(true))?;
+    write!(out, "{:>width$} {}", "", text, width = width)?;
+    out.reset()
+  }
+
+  fn write_label<W: WriteColor>(
+    &self,
+    out: &mut W,
+    gutter: usize,
+    label: &Label,
+    underline: char,
+    color: Color,
+  ) -> io::Result<()>
+  {
+    let span = &label.span;
+    let line = match self.source_line(&span.filename, span.line) {
+      Some(line) => line,
+      None => return Ok(()),
+    };
+    self.write_gutter(out, gutter, "|\n")?;
+    out.set_color(ColorSpec::new().set_fg(Some(Color::Blue)).set_bold(true))?;
+    write!(out, "{:>width$} | ", span.line, width = gutter)?;
+    out.reset()?;
+    writeln!(out, "{}", line)?;
+
+    self.write_gutter(out, gutter, "| ")?;
+    // Keep tabs so the underline lines up with the source.
+    let start = span.start.saturating_sub(1);
+    let indent: String = line
+      .chars()
+      .take(start)
+      .map(|c| if c == '\t' { '\t' } else { ' ' })
+      .collect();
+    let end = if span.end_line > span.line {
+      line.len() + 1
+    } else {
+      span.end
+    };
+    let length = if end > span.start { end - span.start } else { 1 };
+    let marks: String = (0..length).map(|_| underline).collect();
+    write!(out, "{}", indent)?;
+    out.set_color(ColorSpec::new().set_fg(Some(color)).set_bold(true))?;
+    if label.message.is_empty() {
+      writeln!(out, "{}", marks)?;
+    } else {
+      writeln!(out, "{} {}", marks, label.message)?;
+    }
+    out.reset()
+  }
+
+  fn source_line(&self, filename: &Arc<PathBuf>, line: usize) -> Option<String> {
+    let mut sources = self.sources.borrow_mut();
+    let lines = sources
+      .entry(filename.clone())
+      .or_insert_with(|| {
+        let mut source = String::new();
+        File::open(filename.as_ref())
+          .and_then(|mut f| f.read_to_string(&mut source))
+          .ok()
+          .map(|_| split_lines(&source))
+      });
+    lines
+      .as_ref()
+      .and_then(|lines| lines.get(line.wrapping_sub(1)).cloned())
+  }
+}
+
+fn split_lines(source: &str) -> Vec<String> {
+  source.lines().map(String::from).collect()
+}
@@ -53,6 +53,12 @@ lexfn!(op_lsquarebracket -> TokenKind<'a>,
 lexfn!(op_rsquarebracket -> TokenKind<'a>,
   do_parse!(tag!("]") >> (TokenKind::RSquareBracket))
 );
+lexfn!(op_lbrace -> TokenKind<'a>,
+  do_parse!(tag!("{") >> (TokenKind::LBrace))
+);
+lexfn!(op_rbrace -> TokenKind<'a>,
+  do_parse!(tag!("}") >> (TokenKind::RBrace))
+);
 lexfn!(op_minus -> TokenKind<'a>,
   do_parse!(tag!("-") >> (TokenKind::Minus))
 );
@@ -84,7 +90,7 @@ lexfn!(op_greater -> TokenKind<'a>,
   do_parse!(tag!(">") >> (TokenKind::Greater))
 );
 lexfn!(op_rightarrow -> TokenKind<'a>,
-  do_parse!(tag!("<-") >> (TokenKind::RightArrow))
+  do_parse!(tag!("->") >> (TokenKind::RightArrow))
 );
 lexfn!(op_lessequal -> TokenKind<'a>,
   do_parse!(tag!("<=") >> (TokenKind::LessEqual))
@@ -109,6 +115,11 @@ lexfn!(operator -> TokenKind<'a>,
     | op_rparen
     | op_lsquarebracket
     | op_rsquarebracket
+    | op_lbrace
+    | op_rbrace
+    // Two character operators have to come before
+    // the one character operators they start with.
+    | op_rightarrow
     | op_minus
     | op_plus
     | op_multiply
@@ -116,12 +127,11 @@ lexfn!(operator -> TokenKind<'a>,
     | op_caret
     | op_equal
     | op_notequal
-    | op_less
     | op_leftarrow
-    | op_greater
-    | op_rightarrow
     | op_lessequal
+    | op_less
     | op_greaterequal
+    | op_greater
     | op_percentsign
     | op_exclamation
   )
@@ -1,11 +1,13 @@
+mod diagnostic;
 mod lexer;
 mod parser_rd;
 mod token;
 
+pub use self::diagnostic::{Diagnostic, Label, Renderer, ToDiagnostic};
 pub use self::token::{TokenSpan, TokenValue};
 
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use ast::Ast;
 use util::graph_cell::{GraphCell, GraphRefMut};
 
@@ -55,6 +57,7 @@ mod parse_errors {
   #![allow(unused_doc_comment)]
 
   use std::sync::Arc;
+  use std::path::PathBuf;
   use nom;
   use ast;
   use super::Placeholder;
@@ -96,6 +99,27 @@ mod parse_errors {
         description("integer out of range")
         display("{}: integer '{}' out of range: {}", integer.span(), integer.value(), reason)
       }
+
+      IncludeNotFound(path: TokenValue<Arc<str>>) {
+        description("include file not found")
+        display("{}: can't find '{}' in the include path", path.span(), path.value())
+      }
+
+      // `chain` is the files in the cycle, starting and ending with
+      // the same one. `sites` are the includes that lead from each
+      // file to the next.
+      IncludeCycle(chain: Vec<Arc<PathBuf>>, sites: Vec<TokenSpan>) {
+        description("include cycle")
+        display(
+          "{}: include cycle: {}",
+          sites.last().map(ToString::to_string).unwrap_or_default(),
+          chain
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(" -> ")
+        )
+      }
     }
 
     foreign_links {
@@ -133,15 +157,41 @@ pub use self::parse_errors::{
   ResultExt as ParseResultExt,
 };
 
-pub fn compile_file<'a>(filename: &Path) -> ParseResult<Box<GraphCell<Ast<'a>>>> {
-  parser_rd::Parser::parse(filename)
+/// A compiled program with every error found while compiling it.
+/// If there were errors, the AST only has the parts that parsed
+/// and should just be used for inspection.
+#[derive(Debug)]
+pub struct Compiled<'a> {
+  pub ast: Box<GraphCell<Ast<'a>>>,
+  pub errors: Vec<ParseError>,
+}
+
+impl<'a> Compiled<'a> {
+  pub fn is_ok(&self) -> bool {
+    self.errors.is_empty()
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompileOptions {
+  /// Searched in order for included files that
+  /// aren't in the same directory as the includer.
+  pub include_dirs: Vec<PathBuf>,
+}
+
+pub fn compile_file<'a>(filename: &Path, options: &CompileOptions) -> Compiled<'a> {
+  let (ast, errors) = parser_rd::Parser::parse(filename, options);
+  Compiled { ast, errors }
 }
 
+/// Compiles `program` into an existing AST,
+/// returning every error that was found.
 pub fn compile_string<'a>(
   filename: &Path,
   program: &str,
-  ast: GraphRefMut<'a, Ast<'a>>
-) -> ParseResult<()>
+  ast: GraphRefMut<'a, Ast<'a>>,
+  options: &CompileOptions,
+) -> Vec<ParseError>
 {
-  parser_rd::Parser::parse_str(filename, program, ast)
+  parser_rd::Parser::parse_str(filename, program, ast, options)
 }
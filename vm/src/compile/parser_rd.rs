@@ -6,16 +6,18 @@ use std::env;
 use std::convert::{TryFrom, TryInto};
 use std::result::Result as StdResult;
 use nom::IResult;
-use fxhash::FxHashSet;
+use fxhash::{FxHashMap, FxHashSet};
 use util::split_vec::SplitVec;
 use util::graph_cell::*;
 use ast::*;
 use ast::ty::*;
 use ast::var::*;
 use ast::expr::*;
+use ast::stmt::*;
 use super::lexer;
 use super::parse_errors::*;
 use super::token::*;
+use super::CompileOptions;
 
 /// Get the value from inside the TokenKind.
 macro_rules! extract {
@@ -120,12 +122,88 @@ impl<'a> TryFrom<TokenKind<'a>> for PostfixListOperator {
   }
 }
 
+/// Singular units only follow a number, as in `1 hour`,
+/// so they aren't keywords and can still be used as names.
+const SINGULAR_UNITS: [(Word, TimeSpanUnit); 8] = [
+  (Word("millisecond"), TimeSpanUnit::Milliseconds),
+  (Word("second"), TimeSpanUnit::Seconds),
+  (Word("minute"), TimeSpanUnit::Minutes),
+  (Word("hour"), TimeSpanUnit::Hours),
+  (Word("day"), TimeSpanUnit::Days),
+  (Word("week"), TimeSpanUnit::Weeks),
+  (Word("month"), TimeSpanUnit::Months),
+  (Word("year"), TimeSpanUnit::Years),
+];
+
+fn time_span_unit(token: &Token) -> Option<TimeSpanUnit> {
+  if let Some(&(_, unit)) = SINGULAR_UNITS.iter().find(|&&(word, _)| word == *token) {
+    return Some(unit);
+  }
+  let keyword = match token.kind {
+    TokenKind::Keyword(keyword) => keyword,
+    _ => return None,
+  };
+  Some(match keyword {
+    Keyword::Milliseconds => TimeSpanUnit::Milliseconds,
+    Keyword::Seconds => TimeSpanUnit::Seconds,
+    Keyword::Minutes => TimeSpanUnit::Minutes,
+    Keyword::Hours => TimeSpanUnit::Hours,
+    Keyword::Days => TimeSpanUnit::Days,
+    Keyword::Weeks => TimeSpanUnit::Weeks,
+    Keyword::Months => TimeSpanUnit::Months,
+    Keyword::Years => TimeSpanUnit::Years,
+    _ => return None,
+  })
+}
+
+/// State shared by the parsers for a program and all its includes.
+struct ParseState {
+  include_dirs: Vec<PathBuf>,
+  /// Every file is only parsed once, no matter how many times
+  /// it's included.
+  included_paths: FxHashSet<Arc<PathBuf>>,
+  /// The files currently being parsed, outermost first.
+  include_stack: Vec<Arc<PathBuf>>,
+  /// The include statements that lead from each file
+  /// in `include_stack` to the next.
+  include_sites: Vec<TokenSpan>,
+  errors: Vec<Error>,
+}
+
+impl ParseState {
+  fn new(options: &CompileOptions) -> Self {
+    ParseState {
+      include_dirs: options.include_dirs.clone(),
+      included_paths: Default::default(),
+      include_stack: Vec::new(),
+      include_sites: Vec::new(),
+      errors: Vec::new(),
+    }
+  }
+
+  /// Resolves and typechecks whatever was parsed,
+  /// adding any errors to the ones from parsing.
+  fn check<'ast>(&mut self, ast: &Ast<'ast>) {
+    if let Err(errors) = ast.typecheck() {
+      self.errors.extend(errors.into_iter().map(Error::from));
+    }
+  }
+}
+
 pub struct Parser<'p, 'ast: 'p> {
   filename: Arc<PathBuf>,
   token: Token<'p>,
-  included_paths: &'p mut FxHashSet<Arc<PathBuf>>,
+  state: &'p mut ParseState,
   inp: &'p [u8],
   ast: GraphRefMut<'ast, Ast<'ast>>,
+  /// Number of `:` blocks that haven't been closed
+  /// with `end` yet, used to recover from errors.
+  block_depth: u32,
+  /// `or:` continues the block that `option:` opened.
+  after_or: bool,
+  /// Number of open `{` and `[`. The `:`s in object
+  /// literals inside them don't open blocks.
+  bracket_depth: u32,
 }
 
 // TODO: Remove when this is finished.
@@ -133,7 +211,7 @@ pub struct Parser<'p, 'ast: 'p> {
 impl<'p, 'ast: 'p> Parser<'p, 'ast> {
   fn new(
     filename: Arc<PathBuf>,
-    included_paths: &'p mut FxHashSet<Arc<PathBuf>>,
+    state: &'p mut ParseState,
     inp: &'p [u8],
     ast: GraphRefMut<'ast, Ast<'ast>>,
   )
@@ -144,125 +222,190 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     Parser {
       filename,
       token,
-      included_paths,
+      state,
       inp,
       ast,
+      block_depth: 0,
+      after_or: false,
+      bracket_depth: 0,
     }
   }
 
-  fn include(
-    &mut self,
-    filename: &str,
-  ) -> Result<()>
-  {
-    let filename = Arc::new(
-      match self.filename.parent() {
-        Some(parent) if !parent.as_os_str().is_empty()
-          => parent.join(filename),
-        _ => env::current_dir().expect("Unknown current dir").join(filename),
+  /// Looks next to the current file first, then in each include directory.
+  fn find_include(&self, path: &TokenValue<Arc<str>>) -> Result<Arc<PathBuf>> {
+    let local_dir = match self.filename.parent() {
+      Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+      _ => env::current_dir().expect("Unknown current dir"),
+    };
+    let dirs = Some(&local_dir).into_iter().chain(self.state.include_dirs.iter());
+    for dir in dirs {
+      let candidate = dir.join(&**path.value());
+      if candidate.is_file() {
+        return Ok(Arc::new(candidate.canonicalize()?));
       }
-      .canonicalize()?
-    );
+    }
+    Err(ErrorKind::IncludeNotFound(path.clone()).into())
+  }
+
+  fn include(&mut self, path: TokenValue<Arc<str>>) -> Result<()> {
+    let filename = self.find_include(&path)?;
 
-    if !self.included_paths.insert(filename.clone()) {
+    let cycle_start = self.state.include_stack.iter().position(|f| *f == filename);
+    if let Some(start) = cycle_start {
+      let mut chain = self.state.include_stack[start..].to_vec();
+      chain.push(filename);
+      let mut sites = self.state.include_sites[start..].to_vec();
+      sites.push(path.span().clone());
+      return Err(ErrorKind::IncludeCycle(chain, sites).into());
+    }
+    if !self.state.included_paths.insert(filename.clone()) {
       trace!("Skipping already included file '{}'", filename.to_string_lossy());
       return Ok(());
     }
-    Self::parse_file(filename, &mut self.included_paths, self.ast.clone())
+
+    self.state.include_sites.push(path.span().clone());
+    let result = Parser::parse_file(filename, &mut *self.state, self.ast.clone());
+    self.state.include_sites.pop();
+    result
   }
 
-  pub fn parse(filename: &Path) -> Result<Box<GraphCell<Ast<'ast>>>> {
-    let mut includes: FxHashSet<_> = Default::default();
-    let filename = Arc::new(filename.canonicalize()?);
-    includes.insert(filename.clone());
+  /// Parses and typechecks a program, returning the AST along with
+  /// every error found. The AST is only usable if there are no errors.
+  pub fn parse(filename: &Path, options: &CompileOptions)
+    -> (Box<GraphCell<Ast<'ast>>>, Vec<Error>)
+  {
+    let mut state = ParseState::new(options);
     let ast = Ast::new();
-    Self::parse_file(filename, &mut includes, ast.asleep_mut())?;
-    ast.awake().typecheck()?;
-    Ok(ast)
+    match filename.canonicalize() {
+      Ok(filename) => {
+        let filename = Arc::new(filename);
+        state.included_paths.insert(filename.clone());
+        if let Err(e) = Parser::parse_file(filename, &mut state, ast.asleep_mut()) {
+          state.errors.push(e);
+        }
+        state.check(&ast.awake());
+      }
+      Err(e) => state.errors.push(e.into()),
+    }
+    (ast, state.errors)
   }
 
   fn parse_file(
     filename: Arc<PathBuf>,
-    includes: &'p mut FxHashSet<Arc<PathBuf>>,
+    state: &mut ParseState,
     ast: GraphRefMut<'ast, Ast<'ast>>,
   ) -> Result<()>
   {
     let mut program = String::new();
     File::open(filename.as_ref())?.read_to_string(&mut program)?;
     trace!("Loading {}", filename.to_string_lossy());
-    let mut parser = Self::new(filename, includes, program.as_bytes(), ast);
-    parser.parse_program()
+    state.include_stack.push(filename.clone());
+    Parser::new(filename, &mut *state, program.as_bytes(), ast).parse_program();
+    state.include_stack.pop();
+    Ok(())
   }
 
   pub fn parse_str(
     filename: &Path,
-    program: &'p str,
+    program: &str,
     ast: GraphRefMut<'ast, Ast<'ast>>,
-  ) -> Result<()>
+    options: &CompileOptions,
+  ) -> Vec<Error>
   {
-    let mut includes = Default::default();
-    let mut parser = Self::new(
-      Arc::new(filename.into()),
-      &mut includes,
+    let mut state = ParseState::new(options);
+    let filename = Arc::new(PathBuf::from(filename));
+    state.include_stack.push(filename.clone());
+    Parser::new(
+      filename,
+      &mut state,
       program.as_bytes(),
       ast,
-    );
-    parser.parse_program()?;
-    ast.awake().typecheck()?;
-    Ok(())
+    ).parse_program();
+    state.check(&ast.awake());
+    state.errors
   }
 
   // <>Program
 
   /// Top level = Include | Def block
   /// Def block = ident <def keyword> (';' | ':' body 'end;')
-  fn parse_program(&mut self) -> Result<()> {
-    self.advance()?;
-    loop {
-      if self.token == TokenKind::Eof {
-        return Ok(())
-      } else if self.token == Keyword::Include {
-        self.parse_include()?
-      } else {
-        let base_type = self.parse_base_custom_type()?;
+  ///
+  /// Errors are recorded and parsing continues
+  /// with the next top level item.
+  fn parse_program(&mut self) {
+    if let Err(e) = self.advance() {
+      self.recover(e, 0);
+    }
+    while self.token != TokenKind::Eof {
+      if let Err(e) = self.parse_top_level() {
+        self.recover(e, 0);
+      }
+    }
+  }
+
+  fn parse_top_level(&mut self) -> Result<()> {
+    if self.token == Keyword::Include {
+      self.parse_include()?
+    } else if self.token == TokenMatch::Identifier && self.peek()? == Keyword::Has {
+      self.parse_owned_event()?
+    } else {
+      let base_type = self.parse_base_custom_type()?;
+      // distribution of <group> <label>
+      let distribution_group = if base_type == BaseCustomType::Distribution {
+        self.consume(Keyword::Of)?;
         self.expect(TokenMatch::Identifier)?;
-        let label = self.string_token_value();
+        let group = ItemRef::new(self.string_token_value(), self.ast.asleep_ref());
         self.advance()?;
-        // An item (type) definition
-        if self.opt_consume(TokenKind::Semicolon)? {
-          // Empty item
-          base_type.insert_empty_type(self.ast, label)?;
-        } else {
-          self.consume(TokenKind::Colon)?;
-          // FIXME: These give out pointers to their scope, so they must
-          // be created in place and not moved!
-          match base_type {
-            | BaseCustomType::EarlyRef => unreachable!(),
-            | BaseCustomType::Collectable
-              => self.parse_collectable(label),
-            | BaseCustomType::CollectableGroup
-              => self.parse_collectable_group(label),
-            | BaseCustomType::User
-              => self.parse_user(label),
-            | BaseCustomType::UserGroup
-              => self.parse_user_group(label),
-            | BaseCustomType::Event
-              => self.parse_event(label),
-            | BaseCustomType::RemoteEvent
-              => self.parse_remote_event(label),
-            | BaseCustomType::Function
-              => self.parse_function(label),
-            | BaseCustomType::RemoteFunction
-              => self.parse_remote_function(label),
-            | BaseCustomType::Object
-              => self.parse_object_type(label),
-            | BaseCustomType::Array
-              => return self.e_syntax("custom array types are defined inline"),
-          }?;
-          self.parse_end()?;
+        Some(group)
+      } else {
+        None
+      };
+      self.expect(TokenMatch::Identifier)?;
+      let label = self.string_token_value();
+      self.advance()?;
+      // Functions have a signature before the body.
+      if base_type == BaseCustomType::Function {
+        return self.parse_function(label);
+      }
+      // An item (type) definition
+      if self.opt_consume(TokenKind::Semicolon)? {
+        // Empty item
+        if base_type == BaseCustomType::Distribution {
+          return self.e_syntax("a distribution needs at least one entry");
         }
+        base_type.insert_empty_type(self.ast, label)?;
+      } else {
+        self.consume(TokenKind::Colon)?;
+        // FIXME: These give out pointers to their scope, so they must
+        // be created in place and not moved!
+        match base_type {
+          | BaseCustomType::EarlyRef => unreachable!(),
+          | BaseCustomType::Collectable
+            => self.parse_collectable(label),
+          | BaseCustomType::CollectableGroup
+            => self.parse_collectable_group(label),
+          | BaseCustomType::Distribution
+            => self.parse_distribution(label, distribution_group.unwrap()),
+          | BaseCustomType::User
+            => self.parse_user(label),
+          | BaseCustomType::UserGroup
+            => self.parse_user_group(label),
+          | BaseCustomType::Event
+            => self.parse_event(label),
+          | BaseCustomType::RemoteEvent
+            => self.parse_remote_event(label),
+          | BaseCustomType::Function => unreachable!(),
+          | BaseCustomType::RemoteFunction
+            => self.parse_remote_function(label),
+          | BaseCustomType::Object
+            => self.parse_object_type(label),
+          | BaseCustomType::Array
+            => return self.e_syntax("custom array types are defined inline"),
+        }?;
+        self.parse_end()?;
       }
     }
+    Ok(())
   }
 
   // <>Types
@@ -305,6 +448,10 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
         self.advance()?;
         BaseCustomType::Object
       }
+      Keyword::Distribution => {
+        self.advance()?;
+        BaseCustomType::Distribution
+      }
       Keyword::Event => {
         self.advance()?;
         BaseCustomType::Event
@@ -385,23 +532,161 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
 
   fn parse_include(&mut self) -> Result<()> {
     self.consume(Keyword::Include)?;
-    let path_token = self.take(TokenMatch::String)?;
+    self.expect(TokenMatch::String)?;
+    let path = self.string_token_value();
+    self.advance()?;
     self.consume(TokenKind::Semicolon)?;
-    let path = extract!(self, String in path_token)?;
-    self.include(path)?;
+    // The include statement itself is fine, so
+    // keep going from here instead of recovering.
+    if let Err(e) = self.include(path) {
+      self.state.errors.push(e);
+    }
     Ok(())
   }
 
   // <>User
 
+  /// user body = (owned collectable | 'property' property ';')*
   fn parse_user(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
-    User::new(label, self.ast)?;
+    let _user = User::new(label, self.ast)?;
+    let mut user = _user.awake_mut();
+    loop {
+      if self.token == Keyword::Has {
+        self.recoverable(|this| {
+          let owned = this.parse_owned_collectable()?;
+          user.insert_collectable(owned);
+          this.consume(TokenKind::Semicolon)
+        });
+      } else if self.token == Keyword::Property {
+        let scope = user.scope_mut();
+        self.recoverable(|this| {
+          this.advance()?;
+          let prop = this.parse_property(scope)?;
+          scope.awake_mut().insert(prop)?;
+          this.consume(TokenKind::Semicolon)
+        });
+      } else {
+        break;
+      }
+    }
     Ok(())
   }
 
+  /// owned collectable =
+  ///   'has' 'collectable' 'group'? identifier ('with' 'amount' range)? ';'
+  fn parse_owned_collectable(&mut self) -> Result<OwnedCollectable<'ast>> {
+    let start = self.token.span.clone();
+    self.consume(Keyword::Has)?;
+    self.consume(Keyword::Collectable)?;
+    let is_group = self.opt_consume(Keyword::Group)?;
+    self.expect(TokenMatch::Identifier)?;
+    let name = self.string_token_value();
+    let item: ItemRef<CustomType> = ItemRef::new(name.clone(), self.ast.asleep_ref());
+    self.advance()?;
+    let mut end = name.span().clone();
+    let amount = if self.opt_consume(Keyword::With)? {
+      self.consume(Keyword::Amount)?;
+      let amount = self.parse_amount_range()?;
+      end = amount.span().clone();
+      Some(amount)
+    } else {
+      None
+    };
+    Ok(OwnedCollectable::new(item, is_group, amount, start.from_to(&end)))
+  }
+
+  /// user group body = (
+  ///   | 'membership' ('allow' | 'deny') ('except' names)? ';'
+  ///   | 'deny' 'with' names ';'
+  ///   | 'precedence' ('higher' 'than' | 'lower' 'than' | 'equal' 'to') identifier ';'
+  /// )*
   fn parse_user_group(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
-    UserGroup::new(label, self.ast)?;
-    Ok(())
+    let _group = UserGroup::new(label, self.ast)?;
+    let mut group = _group.awake_mut();
+    let mut has_membership = false;
+    let mut has_precedence = false;
+    loop {
+      if self.token == Word("membership") {
+        self.recoverable(|this| {
+          if has_membership {
+            return this.e_syntax("only one `membership` allowed");
+          }
+          has_membership = true;
+          this.advance()?;
+          if this.opt_consume(Word("allow"))? {
+            group.set_membership_mode(MembershipMode::Allow);
+          } else if this.opt_consume(Word("deny"))? {
+            group.set_membership_mode(MembershipMode::Deny);
+          } else {
+            return this.e_expected("allow or deny");
+          }
+          if this.opt_consume(Word("except"))? {
+            this.parse_names(|this, name| {
+              group.insert_except_member(ItemRef::new(name, this.ast.asleep_ref()))
+            })?;
+          }
+          this.consume(TokenKind::Semicolon)
+        });
+      } else if self.token == Word("deny") {
+        self.recoverable(|this| {
+          this.advance()?;
+          this.consume(Keyword::With)?;
+          this.parse_names(|this, name| {
+            group.insert_deny_with(ItemRef::new(name, this.ast.asleep_ref()))
+          })?;
+          this.consume(TokenKind::Semicolon)
+        });
+      } else if self.token == Word("precedence") {
+        self.recoverable(|this| {
+          if has_precedence {
+            return this.e_syntax("only one `precedence` allowed");
+          }
+          has_precedence = true;
+          this.advance()?;
+          let relation = if this.opt_consume(Word("higher"))? {
+            this.consume(Word("than"))?;
+            Precedence::Higher
+          } else if this.opt_consume(Word("lower"))? {
+            this.consume(Word("than"))?;
+            Precedence::Lower
+          } else if this.opt_consume(Word("equal"))? {
+            this.consume(Keyword::To)?;
+            Precedence::Equal
+          } else {
+            return this.e_expected("higher, lower or equal");
+          };
+          this.expect(TokenMatch::Identifier)?;
+          let other = ItemRef::new(this.string_token_value(), this.ast.asleep_ref());
+          this.advance()?;
+          group.set_precedence(relation(other));
+          this.consume(TokenKind::Semicolon)
+        });
+      } else {
+        return Ok(());
+      }
+    }
+  }
+
+  /// names = identifier | '[' identifier,* ']'
+  fn parse_names<F>(&mut self, mut add: F) -> Result<()>
+  where F: FnMut(&mut Self, TokenValue<Arc<str>>) -> Result<()>
+  {
+    let mut add_item = |this: &mut Self| -> Result<()> {
+      this.expect(TokenMatch::Identifier)?;
+      let name = this.string_token_value();
+      this.advance()?;
+      add(this, name)
+    };
+    if self.token == TokenKind::LSquareBracket {
+      self.parse_delimited_list_unit(
+        TokenKind::LSquareBracket,
+        TokenKind::Comma,
+        TokenKind::RSquareBracket,
+        add_item,
+      )
+    } else {
+      add_item(self)
+    }
   }
 
   // <>Collectable
@@ -426,23 +711,37 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
       Self::parse_has_collectable,
       Self::parse_has_collectable_group,
       |this: &mut Self, ref mut grp| -> Result<()> {
-        Ok(grp.insert_redemptions(this.parse_redemptions()?))
+        let scope = grp.scope_mut();
+        Ok(grp.insert_redemptions(this.parse_redemptions(scope)?))
       },
       |this: &mut Self, ref mut grp| -> Result<()> {
-        Ok(grp.insert_upgrades(this.parse_upgrades()?))
+        let scope = grp.scope_mut();
+        Ok(grp.insert_upgrades(this.parse_upgrades(scope)?))
       }
     ]);
     loop {
-      if self.opt_consume(Keyword::Property)? {
+      if self.token == Keyword::Property {
         let scope = group.scope_mut();
-        let prop = self.parse_property(scope)?;
-        scope.awake_mut().insert(prop)?;
-        self.consume(TokenKind::Semicolon)?;
+        self.recoverable(|this| {
+          this.advance()?;
+          let prop = this.parse_property(scope)?;
+          scope.awake_mut().insert(prop)?;
+          this.consume(TokenKind::Semicolon)
+        });
+      } else if self.token == Keyword::Default {
+        let scope = group.scope_mut();
+        self.recoverable(|this| {
+          let default = this.parse_default(scope)?;
+          group.insert_default(default)?;
+          this.consume(TokenKind::Semicolon)
+        });
       } else if self.token == Keyword::Has {
         if !Self::all_done(&vec) {
-          self.advance()?;
-          self.all_next(&mut vec, &mut *group)?;
-          self.consume(TokenKind::Semicolon)?;
+          self.recoverable(|this| {
+            this.advance()?;
+            this.all_next(&mut vec, &mut *group)?;
+            this.consume(TokenKind::Semicolon)
+          });
         } else {
           return self.e_syntax("only one of each has * block allowed");
         }
@@ -460,23 +759,37 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     collectable.set_auto_grouping(self.parse_auto_grouping()?);
     let mut vec = Self::all_init(&[
       |this: &mut Self, coll: &mut Collectable<'ast>| -> Result<()> {
-        Ok(coll.insert_redemptions(this.parse_redemptions()?))
+        let scope = coll.scope_mut();
+        Ok(coll.insert_redemptions(this.parse_redemptions(scope)?))
       },
       |this: &mut Self, coll: &mut Collectable<'ast>| -> Result<()> {
-        Ok(coll.insert_upgrades(this.parse_upgrades()?))
+        let scope = coll.scope_mut();
+        Ok(coll.insert_upgrades(this.parse_upgrades(scope)?))
       }
     ]);
     loop {
-      if self.opt_consume(Keyword::Property)? {
+      if self.token == Keyword::Property {
         let scope = collectable.scope_mut();
-        let prop = self.parse_property(scope)?;
-        scope.awake_mut().insert(prop)?;
-        self.consume(TokenKind::Semicolon)?;
+        self.recoverable(|this| {
+          this.advance()?;
+          let prop = this.parse_property(scope)?;
+          scope.awake_mut().insert(prop)?;
+          this.consume(TokenKind::Semicolon)
+        });
+      } else if self.token == Keyword::Default {
+        let scope = collectable.scope_mut();
+        self.recoverable(|this| {
+          let default = this.parse_default(scope)?;
+          collectable.insert_default(default)?;
+          this.consume(TokenKind::Semicolon)
+        });
       } else if self.token == Keyword::Has {
         if !Self::all_done(&vec) {
-          self.advance()?;
-          self.all_next(&mut vec, &mut *collectable)?;
-          self.consume(TokenKind::Semicolon)?;
+          self.recoverable(|this| {
+            this.advance()?;
+            this.all_next(&mut vec, &mut *collectable)?;
+            this.consume(TokenKind::Semicolon)
+          });
         } else {
           return self.e_syntax("only one of each `has *` block allowed");
         }
@@ -486,28 +799,9 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     }
   }
 
-  fn parse_inline_collectable(&mut self) -> Result<EarlyRefType<'ast>> {
-    self.expect(TokenMatch::Identifier)?;
-    let ty = EarlyRefType::new(
-      self.string_token_value(),
-      BaseCustomType::Collectable
-    );
-    self.advance()?;
-    Ok(ty)
-  }
-
-  fn parse_inline_collectable_group(&mut self) -> Result<EarlyRefType<'ast>> {
-    self.expect(TokenMatch::Identifier)?;
-    let ty = EarlyRefType::new(
-      self.string_token_value(),
-      BaseCustomType::CollectableGroup
-    );
-    self.advance()?;
-    Ok(ty)
-  }
-
   fn parse_has_collectable_or_group(
     &mut self,
+    group: &mut CollectableGroup<'ast>,
     is_inline_group: bool,
   ) -> Result<()>
   {
@@ -519,17 +813,21 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     } else if !is_inline_group && peek == Keyword::Group {
       return self.e_unexpected();
     }
-    let inline_item = if is_inline_group {
+    if is_inline_group {
       self.advance_x(2)?;
-      Self::parse_inline_collectable_group
     } else {
       self.advance()?;
-      Self::parse_inline_collectable
-    };
+    }
 
-    let add_item = move |this: &mut Self| -> Result<()> {
-      let item = inline_item(this)?;
-      Ast::insert_type(this.ast, item)?;
+    let mut add_item = |this: &mut Self| -> Result<()> {
+      this.expect(TokenMatch::Identifier)?;
+      let name = this.string_token_value();
+      this.advance()?;
+      if is_inline_group {
+        group.insert_group_ref(ItemRefMut::new(name, this.ast.asleep_ref()))?;
+      } else {
+        group.insert_collectable_ref(ItemRefMut::new(name, this.ast.asleep_ref()))?;
+      }
       Ok(())
     };
 
@@ -545,51 +843,603 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     }
   }
 
-  fn parse_has_collectable(&mut self, _: &mut CollectableGroup<'ast>)
+  fn parse_has_collectable(&mut self, group: &mut CollectableGroup<'ast>)
     -> Result<()>
   {
-    self.parse_has_collectable_or_group(false)
+    self.parse_has_collectable_or_group(group, false)
   }
 
-  fn parse_has_collectable_group(&mut self, _: &mut CollectableGroup<'ast>)
+  fn parse_has_collectable_group(&mut self, group: &mut CollectableGroup<'ast>)
     -> Result<()>
   {
-    self.parse_has_collectable_or_group(true)
+    self.parse_has_collectable_or_group(group, true)
   }
 
+  /// upgrades = 'upgrades'
+  ///   ( 'with' 'cost' '[' (cost 'for' 'amount' range),* ']'
+  ///   | 'for' ident ':' property upgrade* 'end'
+  ///   )?
+  ///
+  /// The caller consumes the final ';'.
   fn parse_upgrades(
     &mut self,
-  ) -> Result<Vec<Upgrade>>
+    scope: GraphRefMut<'ast, Scope<'ast>>,
+  ) -> Result<Vec<Upgrade<'ast>>>
   {
     self.consume(Keyword::Upgrades)?;
-    Ok(Vec::new())
+    if self.opt_consume(Keyword::With)? {
+      self.consume(Keyword::Cost)?;
+      self.parse_delimited_list(
+        TokenKind::LSquareBracket,
+        TokenKind::Comma,
+        TokenKind::RSquareBracket,
+        |this| this.parse_amount_upgrade(scope),
+        Vec::new(),
+        Vec::push,
+      )
+    } else if self.opt_consume(Keyword::For)? {
+      self.expect(TokenMatch::Identifier)?;
+      let property = self.string_token_value();
+      self.advance()?;
+      self.consume(TokenKind::Colon)?;
+      let mut upgrades = Vec::new();
+      while self.token != Keyword::End {
+        upgrades.push(self.parse_property_upgrade(&property, scope)?);
+      }
+      self.consume(Keyword::End)?;
+      Ok(upgrades)
+    } else {
+      Ok(Vec::new())
+    }
   }
 
+  /// <collectable> 'x' expr 'for' 'amount' range
+  fn parse_amount_upgrade(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<Upgrade<'ast>>
+  {
+    let start = self.token.span.clone();
+    self.expect(TokenMatch::Identifier)?;
+    let cost: ItemRef<Collectable> =
+      ItemRef::new(self.string_token_value(), self.ast.asleep_ref());
+    self.advance()?;
+    self.consume(Keyword::X)?;
+    let cost_amount = self.parse_expression(scope)?;
+    self.consume(Keyword::For)?;
+    self.consume(Keyword::Amount)?;
+    let levels = self.parse_amount_range()?;
+    let span = start.from_to(levels.span());
+    Ok(Upgrade::new(
+      None,
+      TokenValue::new(1, start),
+      levels,
+      None,
+      cost,
+      cost_amount,
+      span,
+    ))
+  }
+
+  /// 'award' '+' integer 'for' '.' <property> '=' integer
+  ///   ('and' '.' 'amount' '=' expr)? 'and' 'cost' expr 'x' <collectable> ';'
+  fn parse_property_upgrade(
+    &mut self,
+    property: &TokenValue<Arc<str>>,
+    scope: GraphRefMut<'ast, Scope<'ast>>,
+  ) -> Result<Upgrade<'ast>>
+  {
+    let start = self.token.span.clone();
+    self.consume(Keyword::Award)?;
+    self.consume(TokenKind::Plus)?;
+    let increment = self.take_int()?;
+    self.consume(Keyword::For)?;
+    self.consume(TokenKind::Dot)?;
+    self.expect(TokenMatch::Identifier)?;
+    if self.string_token_value().value() != property.value() {
+      return self.e_expected(format!("'{}'", property.value()));
+    }
+    self.advance()?;
+    self.consume(TokenKind::Equal)?;
+    let levels = AmountRange::exactly(self.take_int()?);
+    self.consume(Keyword::And)?;
+    let required_amount = if self.token == TokenKind::Dot {
+      self.advance()?;
+      self.consume(Keyword::Amount)?;
+      self.consume(TokenKind::Equal)?;
+      // Stop before `and`.
+      let amount = self.parse_precedence_expr(
+        BinaryOperator::And.precedence() + 1,
+        scope,
+      )?;
+      self.consume(Keyword::And)?;
+      Some(amount)
+    } else {
+      None
+    };
+    self.consume(Keyword::Cost)?;
+    let cost_amount = self.parse_expression(scope)?;
+    self.consume(Keyword::X)?;
+    self.expect(TokenMatch::Identifier)?;
+    let cost: ItemRef<Collectable> =
+      ItemRef::new(self.string_token_value(), self.ast.asleep_ref());
+    let span = start.from_to(&self.token.span);
+    self.advance()?;
+    self.consume(TokenKind::Semicolon)?;
+    Ok(Upgrade::new(
+      Some(property.clone()),
+      increment,
+      levels,
+      required_amount,
+      cost,
+      cost_amount,
+      span,
+    ))
+  }
+
+  /// redemptions = 'redemptions'
+  ///   ( ':' ('x' integer 'for' redemption cost ';')* 'end'
+  ///   | 'with' 'amount' '[' (integer 'for' 'cost' redemption cost),* ']'
+  ///   | 'with' 'amount' integer 'for' 'cost' redemption cost
+  ///   | 'for' 'currency'
+  ///   )
+  /// redemption cost = <collectable> 'x' expr
+  ///
+  /// The caller consumes the final ';'.
   fn parse_redemptions(
     &mut self,
-  ) -> Result<Vec<Redemption>>
+    scope: GraphRefMut<'ast, Scope<'ast>>,
+  ) -> Result<Vec<Redemption<'ast>>>
   {
     self.consume(Keyword::Redemptions)?;
-    Ok(Vec::new())
+    if self.opt_consume(TokenKind::Colon)? {
+      let mut redemptions = Vec::new();
+      while self.token != Keyword::End {
+        let start = self.token.span.clone();
+        self.consume(Keyword::X)?;
+        let amount = self.take_int()?;
+        self.consume(Keyword::For)?;
+        redemptions.push(self.parse_redemption_cost(start, amount, scope)?);
+        self.consume(TokenKind::Semicolon)?;
+      }
+      self.consume(Keyword::End)?;
+      Ok(redemptions)
+    } else if self.opt_consume(Keyword::With)? {
+      self.consume(Keyword::Amount)?;
+      let parse_item = |this: &mut Self| -> Result<Redemption<'ast>> {
+        let start = this.token.span.clone();
+        let amount = this.take_int()?;
+        this.consume(Keyword::For)?;
+        this.consume(Keyword::Cost)?;
+        this.parse_redemption_cost(start, amount, scope)
+      };
+      if self.token == TokenKind::LSquareBracket {
+        self.parse_delimited_list(
+          TokenKind::LSquareBracket,
+          TokenKind::Comma,
+          TokenKind::RSquareBracket,
+          parse_item,
+          Vec::new(),
+          Vec::push,
+        )
+      } else {
+        Ok(vec![parse_item(self)?])
+      }
+    } else {
+      let start = self.token.span.clone();
+      self.consume(Keyword::For)?;
+      let end = self.take(Keyword::Currency)?.span;
+      Ok(vec![Redemption::ForCurrency(start.from_to(&end))])
+    }
+  }
+
+  fn parse_redemption_cost(
+    &mut self,
+    start: TokenSpan,
+    amount: TokenValue<i64>,
+    scope: GraphRefMut<'ast, Scope<'ast>>,
+  ) -> Result<Redemption<'ast>>
+  {
+    self.expect(TokenMatch::Identifier)?;
+    let cost: ItemRef<Collectable> =
+      ItemRef::new(self.string_token_value(), self.ast.asleep_ref());
+    self.advance()?;
+    self.consume(Keyword::X)?;
+    let cost_amount = self.parse_expression(scope)?;
+    let span = start.from_to(cost_amount.span());
+    Ok(Redemption::for_collectable(amount, cost, cost_amount, span))
+  }
+
+  // <>Amount
+
+  /// range = integer
+  ///   | 'min' integer ('max' integer)?
+  ///   | 'max' integer
+  ///   | 'range' integer 'to' integer
+  ///   | 'range' '(' integer ',' integer ')'
+  fn parse_amount_range(&mut self) -> Result<AmountRange> {
+    let start = self.token.span.clone();
+    let (min, max, end) = if self.opt_consume(Keyword::Range)? {
+      if self.opt_consume(TokenKind::LParen)? {
+        let min = self.take_int()?;
+        self.consume(TokenKind::Comma)?;
+        let max = self.take_int()?;
+        let end = self.take(TokenKind::RParen)?.span;
+        (Some(min), Some(max), end)
+      } else {
+        let min = self.take_int()?;
+        self.consume(Keyword::To)?;
+        let max = self.take_int()?;
+        let end = max.span().clone();
+        (Some(min), Some(max), end)
+      }
+    } else if self.opt_consume(Keyword::Min)? {
+      let min = self.take_int()?;
+      if self.opt_consume(Keyword::Max)? {
+        let max = self.take_int()?;
+        let end = max.span().clone();
+        (Some(min), Some(max), end)
+      } else {
+        let end = min.span().clone();
+        (Some(min), None, end)
+      }
+    } else if self.opt_consume(Keyword::Max)? {
+      let max = self.take_int()?;
+      let end = max.span().clone();
+      (None, Some(max), end)
+    } else {
+      return Ok(AmountRange::exactly(self.take_int()?));
+    };
+    Ok(AmountRange::new(min, max, start.from_to(&end))?)
+  }
+
+  // <>Distribution
+
+  /// distribution body =
+  ///   ('amount' range ';')?
+  ///   ('group' 'x' range 'of' '[' entry,* ']' ';')?
+  fn parse_distribution(
+    &mut self,
+    label: TokenValue<Arc<str>>,
+    group: ItemRef<'ast, CollectableGroup<'ast>>,
+  ) -> Result<()>
+  {
+    let _distribution = Distribution::new(label, group, self.ast)?;
+    let mut distribution = _distribution.awake_mut();
+    loop {
+      if self.token == Keyword::Amount {
+        self.recoverable(|this| {
+          if distribution.amount().is_some() {
+            return this.e_syntax("only one `amount` allowed");
+          }
+          this.advance()?;
+          distribution.set_amount(this.parse_amount_range()?);
+          this.consume(TokenKind::Semicolon)
+        });
+      } else if self.token == Keyword::Group {
+        self.recoverable(|this| {
+          if distribution.picks().is_some() {
+            return this.e_syntax("only one `group` list allowed");
+          }
+          this.advance()?;
+          this.consume(Keyword::X)?;
+          let picks = this.parse_amount_range()?;
+          this.consume(Keyword::Of)?;
+          let entries = this.parse_delimited_list(
+            TokenKind::LSquareBracket,
+            TokenKind::Comma,
+            TokenKind::RSquareBracket,
+            Self::parse_distribution_entry,
+            Vec::new(),
+            Vec::push,
+          )?;
+          distribution.set_entries(picks, entries);
+          this.consume(TokenKind::Semicolon)
+        });
+      } else {
+        return Ok(());
+      }
+    }
+  }
+
+  /// entry = <collectable or group> ('weighted' percent)? ('x' range)?
+  fn parse_distribution_entry(&mut self) -> Result<DistributionEntry<'ast>> {
+    self.expect(TokenMatch::Identifier)?;
+    let name = self.string_token_value();
+    let item: ItemRef<CustomType> = ItemRef::new(name.clone(), self.ast.asleep_ref());
+    self.advance()?;
+    let mut end = name.span().clone();
+    let weight = if self.opt_consume(Keyword::Weighted)? {
+      let weight = match self.float_token_value() {
+        Some(weight) => weight,
+        None => return self.e_expected("weight"),
+      };
+      self.advance()?;
+      end = weight.span().clone();
+      Some(weight)
+    } else {
+      None
+    };
+    let amount = if self.opt_consume(Keyword::X)? {
+      let amount = self.parse_amount_range()?;
+      end = amount.span().clone();
+      Some(amount)
+    } else {
+      None
+    };
+    Ok(DistributionEntry::new(item, weight, amount, name.span().from_to(&end)))
   }
 
   // <>Event
 
   fn parse_event(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
-    Event::new(label, self.ast)?;
+    let _event = Event::new(label, self.ast)?;
+    let mut event = _event.awake_mut();
+    let body = self.parse_statement_block(event.scope_mut())?;
+    event.set_body(body);
     Ok(())
   }
 
+  /// owned event = owner 'has' 'event' name
+  ///   '(' [instance] (',' name type)* ')' ['<-' sender] ':'
+  ///   statement*
+  /// 'end' ';'
+  ///
+  /// The instance parameter's type is the owner.
+  fn parse_owned_event(&mut self) -> Result<()> {
+    self.expect(TokenMatch::Identifier)?;
+    let owner = self.string_token_value();
+    self.advance()?;
+    self.consume(Keyword::Has)?;
+    self.consume(Keyword::Event)?;
+    self.expect(TokenMatch::Identifier)?;
+    let name = self.string_token_value();
+    self.advance()?;
+
+    let owner_ref = ItemRef::new(owner.clone(), self.ast.asleep_ref());
+    let _event = Event::with_owner(owner_ref, name, self.ast)?;
+    let mut event = _event.awake_mut();
+    let mut first = true;
+    self.parse_delimited_list_unit(
+      TokenKind::LParen,
+      TokenKind::Comma,
+      TokenKind::RParen,
+      |this: &mut Self| -> Result<()> {
+        this.expect(TokenMatch::Identifier)?;
+        let name = this.string_token_value();
+        this.advance()?;
+        let implicit = this.token == TokenKind::Comma || this.token == TokenKind::RParen;
+        let ty = if first && implicit {
+          ItemRef::new(owner.clone(), this.ast.asleep_ref())
+        } else {
+          this.parse_type()?
+        };
+        first = false;
+        event.insert_param(Variable::new(name, ty))
+      },
+    )?;
+    if self.opt_consume(TokenKind::LeftArrow)? {
+      let sender = self.parse_type_or_expr(event.scope_mut())?;
+      event.set_sender(sender);
+    }
+    self.consume(TokenKind::Colon)?;
+    let body = self.parse_statement_block(event.scope_mut())?;
+    event.set_body(body);
+    self.parse_end()
+  }
+
   fn parse_remote_event(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
     RemoteEvent::new(label, self.ast)?;
     Ok(())
   }
 
+  // <>Statement
+
+  /// Statements up to, but not including, `end`, `or` or
+  /// the `->` that returns from a function.
+  fn parse_statement_block(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<Vec<BoxStatement<'ast>>>
+  {
+    let mut block = Vec::new();
+    while self.token != Keyword::End
+      && self.token != Keyword::Or
+      && self.token != TokenKind::RightArrow
+      && self.token != TokenKind::Eof
+    {
+      if let Some(stmt) = self.recoverable(|this| this.parse_statement(scope)) {
+        block.push(stmt);
+      }
+    }
+    Ok(block)
+  }
+
+  /// statement = keyword ... ';' | option | lvalue '=' expr ';'
+  fn parse_statement(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxStatement<'ast>>
+  {
+    let start = self.token.span.clone();
+    let keyword = if self.token == TokenMatch::Keyword {
+      Some(extract!(self, Keyword).unwrap())
+    } else {
+      None
+    };
+    let stmt: BoxStatement<'ast> = match keyword {
+      Some(Keyword::Assert) => {
+        self.advance()?;
+        let condition = self.parse_expression(scope)?;
+        let span = start.from_to(condition.span());
+        box AssertStmt::new(condition, span)
+      }
+      Some(Keyword::Authorize) => {
+        self.advance()?;
+        let target = self.parse_type_or_expr(scope)?;
+        let span = start.from_to(target.span());
+        box AuthorizeStmt::new(target, span)
+      }
+      Some(Keyword::Award) => {
+        self.advance()?;
+        self.parse_award(start, scope)?
+      }
+      Some(Keyword::Cost) => {
+        self.advance()?;
+        self.expect(TokenMatch::Identifier)?;
+        let collectable: ItemRef<Collectable> =
+          ItemRef::new(self.string_token_value(), self.ast.asleep_ref());
+        self.advance()?;
+        self.consume(Keyword::X)?;
+        let amount = self.parse_expression(scope)?;
+        let span = start.from_to(amount.span());
+        box CostStmt::new(collectable, amount, span)
+      }
+      Some(Keyword::Timer) => {
+        self.advance()?;
+        let duration = self.parse_expression(scope)?;
+        let span = start.from_to(duration.span());
+        box TimerStmt::new(duration, span)
+      }
+      Some(Keyword::Set) => {
+        self.advance()?;
+        self.parse_set(start, scope)?
+      }
+      // Consumes its own `end;`.
+      Some(Keyword::Option) => return self.parse_option(scope),
+      _ => self.parse_set(start, scope)?,
+    };
+    self.consume(TokenKind::Semicolon)?;
+    Ok(stmt)
+  }
+
+  /// See `TypeOrExpr`.
+  fn parse_type_or_expr(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<TypeOrExpr<'ast>>
+  {
+    if self.token == TokenMatch::Identifier {
+      let name = self.string_token_value();
+      if !scope.awake().has(name.value()) {
+        self.advance()?;
+        return Ok(TypeOrExpr::Type(ItemRef::new(name, self.ast.asleep_ref())));
+      }
+    }
+    Ok(TypeOrExpr::Expr(self.parse_expression(scope)?))
+  }
+
+  /// award = 'award' ['+' | '-'] item ['x' expr] ['to' expr]
+  fn parse_award(&mut self, start: TokenSpan, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxStatement<'ast>>
+  {
+    let sign_span = self.token.span.clone();
+    let sign = if self.opt_consume(TokenKind::Minus)? {
+      AwardSign::Remove
+    } else {
+      self.opt_consume(TokenKind::Plus)?;
+      AwardSign::Add
+    };
+    let item = self.parse_type_or_expr(scope)?;
+    let mut end = item.span().clone();
+    let amount = if self.opt_consume(Keyword::X)? {
+      let amount = self.parse_expression(scope)?;
+      end = amount.span().clone();
+      Some(amount)
+    } else {
+      None
+    };
+    let target = if self.opt_consume(Keyword::To)? {
+      let target = self.parse_expression(scope)?;
+      end = target.span().clone();
+      Some(target)
+    } else {
+      None
+    };
+    Ok(box AwardStmt::new(
+      TokenValue::new(sign, sign_span),
+      item,
+      amount,
+      target,
+      start.from_to(&end),
+    ))
+  }
+
+  /// set = ['set'] lvalue '=' expr
+  fn parse_set(&mut self, start: TokenSpan, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxStatement<'ast>>
+  {
+    // Stop before `=` so it isn't parsed as a comparison.
+    let target = self.parse_precedence_expr(
+      BinaryOperator::Eq.precedence() + 1,
+      scope,
+    )?;
+    self.consume(TokenKind::Equal)?;
+    let value = self.parse_expression(scope)?;
+    let span = start.from_to(value.span());
+    Ok(box SetStmt::new(target, value, span))
+  }
+
+  /// option = 'option' ':' block ('or' ':' block)+ 'end' ';'
+  fn parse_option(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxStatement<'ast>>
+  {
+    let start = self.token.span.clone();
+    self.consume(Keyword::Option)?;
+    self.consume(TokenKind::Colon)?;
+    let mut branches = vec![self.parse_statement_block(scope)?];
+    while self.opt_consume(Keyword::Or)? {
+      self.consume(TokenKind::Colon)?;
+      branches.push(self.parse_statement_block(scope)?);
+    }
+    let span = start.from_to(&self.token.span);
+    self.parse_end()?;
+    if branches.len() < 2 {
+      return Err(ErrorKind::Syntax(
+        "option needs at least one `or:` branch".into(),
+        span,
+      ).into());
+    }
+    if branches.iter().any(Vec::is_empty) {
+      return Err(ErrorKind::Syntax(
+        "option branches can't be empty".into(),
+        span,
+      ).into());
+    }
+    Ok(box OptionStmt::new(branches, span))
+  }
+
   // <>Function
 
+  /// function = label '(' (name type),* ')' ('->' type)? ':'
+  ///   statement* ('->' expr ';')?
+  /// 'end' ';'
+  ///
+  /// The result is there if and only if the return type is.
   fn parse_function(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
-    Function::new(label, self.ast)?;
-    Ok(())
+    let _function = Function::new(label, self.ast)?;
+    let mut function = _function.awake_mut();
+    self.parse_delimited_list_unit(
+      TokenKind::LParen,
+      TokenKind::Comma,
+      TokenKind::RParen,
+      |this: &mut Self| -> Result<()> {
+        this.expect(TokenMatch::Identifier)?;
+        let name = this.string_token_value();
+        this.advance()?;
+        let ty = this.parse_type()?;
+        function.insert_param(Variable::new(name, ty))
+      },
+    )?;
+    if self.opt_consume(TokenKind::RightArrow)? {
+      function.set_return_type(self.parse_type()?);
+    }
+    self.consume(TokenKind::Colon)?;
+
+    let scope = function.scope_mut();
+    let body = self.parse_statement_block(scope)?;
+    let result = if function.return_type().is_some() {
+      self.consume(TokenKind::RightArrow)?;
+      let result = self.parse_expression(scope)?;
+      self.consume(TokenKind::Semicolon)?;
+      Some(result)
+    } else {
+      None
+    };
+    function.set_body(body, result);
+    self.parse_end()
   }
 
   fn parse_remote_function(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
@@ -599,20 +1449,37 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
 
   // <>Object
 
+  /// object body = ('property' property ';')*
   fn parse_object_type(&mut self, label: TokenValue<Arc<str>>) -> Result<()> {
-    Object::new(label, self.ast)?;
+    let _object = Object::new(label, self.ast)?;
+    let mut object = _object.awake_mut();
+    while self.token == Keyword::Property {
+      let scope = object.scope_mut();
+      self.recoverable(|this| {
+        this.advance()?;
+        let prop = this.parse_property(scope)?;
+        scope.awake_mut().insert(prop)?;
+        this.consume(TokenKind::Semicolon)
+      });
+    }
     Ok(())
   }
 
   // <>Variable
 
-  /// property <name> <type>
+  /// property =
+  ///   | <name> <type> ('=' expr)?
+  ///   | <name> 'of' <type> 'for' 'collectable' <name>
+  ///     'with' '[' (expr 'for' 'amount' range),* ']' ('or' '=' expr)?
   fn parse_property(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
     -> Result<Variable<'ast>>
   {
     self.expect(TokenMatch::Identifier)?;
     let name = self.string_token_value();
     self.advance()?;
+    if self.opt_consume(Keyword::Of)? {
+      return self.parse_lookup_property(name, scope);
+    }
     let ty = self.parse_type()?;
     let mut var = Variable::new(name, ty);
     if self.token == TokenKind::Equal {
@@ -622,6 +1489,56 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     Ok(var)
   }
 
+  fn parse_lookup_property(
+    &mut self,
+    name: TokenValue<Arc<str>>,
+    scope: GraphRefMut<'ast, Scope<'ast>>,
+  ) -> Result<Variable<'ast>>
+  {
+    let ty = self.parse_type()?;
+    let start = self.token.span.clone();
+    self.consume(Keyword::For)?;
+    self.consume(Keyword::Collectable)?;
+    self.expect(TokenMatch::Identifier)?;
+    let end = self.token.span.clone();
+    let collectable = ItemRef::new(self.string_token_value(), self.ast.asleep_ref());
+    self.advance()?;
+    self.consume(Keyword::With)?;
+    let entries = self.parse_delimited_list(
+      TokenKind::LSquareBracket,
+      TokenKind::Comma,
+      TokenKind::RSquareBracket,
+      |this| {
+        let value = this.parse_expression(scope)?;
+        this.consume(Keyword::For)?;
+        this.consume(Keyword::Amount)?;
+        Ok(LookupEntry::new(value, this.parse_amount_range()?))
+      },
+      Vec::new(),
+      Vec::push,
+    )?;
+    let mut var = Variable::new(name, ty);
+    var.set_lookup(LookupTable::new(collectable, entries, start.from_to(&end)));
+    if self.opt_consume(Keyword::Or)? {
+      self.consume(TokenKind::Equal)?;
+      var.set_initial(self.parse_expression(scope)?);
+    }
+    Ok(var)
+  }
+
+  /// default = 'default' '.' <name> '=' expr
+  fn parse_default(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<DefaultValue<'ast>>
+  {
+    self.consume(Keyword::Default)?;
+    self.consume(TokenKind::Dot)?;
+    self.expect(TokenMatch::Identifier)?;
+    let name = self.string_token_value();
+    self.advance()?;
+    self.consume(TokenKind::Equal)?;
+    Ok(DefaultValue::new(name, self.parse_expression(scope)?))
+  }
+
   // <>Expression
 
   fn parse_expression(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
@@ -645,7 +1562,8 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
       let precedence = prefix.value().precedence();
       expr = box PrefixExpr::new(
         prefix,
-        self.parse_precedence_expr(precedence, scope)?
+        self.parse_precedence_expr(precedence, scope)?,
+        self.ast.asleep_ref(),
       );
       if is_paren {
         self.consume(TokenKind::RParen)?;
@@ -687,7 +1605,8 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
         expr = box BinaryExpr::new(
           binary,
           expr,
-          self.parse_precedence_expr(next_precedence, scope)?
+          self.parse_precedence_expr(next_precedence, scope)?,
+          self.ast.asleep_ref(),
         );
       } else {
         break;
@@ -696,14 +1615,27 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     Ok(expr)
   }
 
-  /// primary = ident | amount | literal
+  /// primary = ident | call | amount | literal | array | object | find
   fn parse_primary_expr(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
     -> Result<BoxExpression<'ast>>
   {
     if self.token == TokenMatch::Identifier {
       let tv = self.string_token_value();
       self.advance()?;
-      Ok(box ExprVar::new(tv, scope.asleep_ref().into()))
+      if self.token != TokenKind::LParen {
+        return Ok(box ExprVar::new(tv, scope.asleep_ref().into()));
+      }
+      self.advance()?;
+      let args = self.parse_list(
+        TokenKind::Comma,
+        |this| this.parse_expression(scope),
+        Vec::new(),
+        Vec::push,
+      )?;
+      let span = tv.span().from_to(&self.token.span);
+      self.consume(TokenKind::RParen)?;
+      let function: ItemRef<Function> = ItemRef::new(tv, self.ast.asleep_ref());
+      Ok(box ExprCall::new(function, args, span, self.ast.asleep_ref()))
     } else if self.token == Keyword::Amount {
       let tv = self.string_token_value();
       self.advance()?;
@@ -718,7 +1650,7 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     } else if self.token == TokenMatch::Integer {
       let tv = self.int_token_value().unwrap();
       self.advance()?;
-      Ok(match self.parse_time_span(&tv) {
+      Ok(match self.parse_time_span(&tv)? {
         Some(ts) => box ExprLiteral::new(
           Literal::TimeSpan(ts),
           self.ast.awake().primitive().time_span()
@@ -742,6 +1674,7 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
       let s = extract!(self, String in tok).unwrap();
       let s = self.ast.awake().shared_string(s);
       let tv = TokenValue::new(s, loc_span.from_to(&tok.span));
+      self.ast.awake_mut().insert_localized_string(tv.value().clone(), tv.span().clone());
       Ok(box ExprLiteral::new(
         Literal::LocalizedText(tv),
         self.ast.awake().primitive().localized_text()
@@ -760,13 +1693,226 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
         Literal::Option(tv),
         self.ast.awake().primitive().option()
       ))
+    } else if self.token == Keyword::Find {
+      self.parse_find(scope)
+    } else if self.token == TokenKind::LSquareBracket {
+      self.parse_array_literal(scope)
+    } else if self.token == TokenKind::LBrace {
+      self.parse_object_literal(scope)
     } else {
       self.e_unexpected()
     }
   }
 
-  fn parse_time_span(&mut self, _: &TokenValue<i64>) -> Option<Vec<TimeSpanPart>> {
-    None
+  /// array = '[' (expr (',' expr)*)? ']'
+  ///
+  /// An array of literals that all have the same type is an
+  /// `array of` that type, anything else is just an `array`.
+  /// Either way it can be assigned to a typed array its elements fit.
+  fn parse_array_literal(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxExpression<'ast>>
+  {
+    let start = self.take(TokenKind::LSquareBracket)?.span;
+    let elements = self.parse_list(
+      TokenKind::Comma,
+      |this| this.parse_expression(scope),
+      Vec::new(),
+      Vec::push,
+    )?;
+    let end = self.take(TokenKind::RSquareBracket)?.span;
+    let span = start.from_to(&end);
+
+    let mut element_type: Option<TokenValue<Arc<str>>> = None;
+    for expr in &elements {
+      if expr.kind() != ExpressionKind::Literal {
+        element_type = None;
+        break;
+      }
+      let ty = expr.ty();
+      let ty = ty.awake();
+      let name = ty.name().value();
+      match element_type {
+        Some(ref t) if t.value() != name => {
+          element_type = None;
+          break;
+        }
+        Some(_) => {}
+        None => element_type = Some(TokenValue::new(name.clone(), span.clone())),
+      }
+    }
+    let ty = match element_type {
+      Some(name) => Ast::get_array(self.ast, ArrayName::new(None, Some(name))),
+      None => self.ast.awake().primitive().array(),
+    };
+    Ok(box ExprLiteral::with_span(Literal::Array(elements), ty, span))
+  }
+
+  /// object = '{' (identifier ':' expr (',' identifier ':' expr)*)? '}'
+  ///
+  /// Its type is `object` until it's assigned to an object type.
+  fn parse_object_literal(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxExpression<'ast>>
+  {
+    let start = self.take(TokenKind::LBrace)?.span;
+    let pairs = self.parse_list(
+      TokenKind::Comma,
+      |this| {
+        this.expect(TokenMatch::Identifier)?;
+        let key = this.string_token_value();
+        this.advance()?;
+        this.consume(TokenKind::Colon)?;
+        Ok((key, this.parse_expression(scope)?))
+      },
+      Vec::new(),
+      Vec::push,
+    )?;
+    let end = self.take(TokenKind::RBrace)?.span;
+
+    let mut object: FxHashMap<TokenValue<Arc<str>>, BoxExpression<'ast>> = FxHashMap::default();
+    for (key, value) in pairs {
+      // Keys compare equal only with the same span.
+      if let Some(first) = object.keys().find(|k| k.value() == key.value()) {
+        return Err(AstError::from(AstErrorKind::DuplicateDefinition(
+          key.clone(),
+          "object key",
+          first.span().clone(),
+        )).into());
+      }
+      object.insert(key, value);
+    }
+    Ok(box ExprLiteral::with_span(
+      Literal::Object(object),
+      self.ast.awake().primitive().object(),
+      start.from_to(&end),
+    ))
+  }
+
+  /// find = 'find' (
+  ///   | 'event' identifier ('.' identifier)? 'for' expr
+  ///   | 'user' identifier ('with' user filter)?
+  ///   | 'gameserver' 'with' 'switch' identifier '=' expr
+  /// )
+  ///
+  /// user filter =
+  ///   | 'similar' 'amount' 'of' 'collectable' identifier
+  ///   | 'amount' range 'of' 'collectable' identifier
+  ///   | '.' identifier '=' expr
+  ///
+  /// Operands after `for` stop before any binary operator except `.`,
+  /// and values after `=` stop before comparisons, so `find` can be
+  /// used inside a larger condition.
+  fn parse_find(&mut self, scope: GraphRefMut<'ast, Scope<'ast>>)
+    -> Result<BoxExpression<'ast>>
+  {
+    let start = self.token.span.clone();
+    self.consume(Keyword::Find)?;
+    let value_precedence = BinaryOperator::Eq.precedence() + 1;
+    let (target, end) = if self.opt_consume(Keyword::Event)? {
+      self.expect(TokenMatch::Identifier)?;
+      let mut name = self.string_token_value();
+      self.advance()?;
+      if self.opt_consume(TokenKind::Dot)? {
+        self.expect(TokenMatch::Identifier)?;
+        let event = self.string_token_value();
+        self.advance()?;
+        let full_name = self.ast.awake().shared_string(
+          &format!("{}.{}", name.value(), event.value())
+        );
+        name = TokenValue::new(full_name, name.span().from_to(event.span()));
+      }
+      self.consume(Keyword::For)?;
+      let owner = self.parse_precedence_expr(BinaryOperator::Dot.precedence(), scope)?;
+      let end = owner.span().clone();
+      let event: ItemRef<Event> = ItemRef::new(name, self.ast.asleep_ref());
+      (FindTarget::Event { event, owner }, end)
+    } else if self.opt_consume(Keyword::User)? {
+      self.expect(TokenMatch::Identifier)?;
+      let name = self.string_token_value();
+      self.advance()?;
+      let mut end = name.span().clone();
+      let user_type: ItemRef<Type> = ItemRef::new(name, self.ast.asleep_ref());
+      let filter = if self.opt_consume(Keyword::With)? {
+        if self.opt_consume(TokenKind::Dot)? {
+          self.expect(TokenMatch::Identifier)?;
+          let name = self.string_token_value();
+          self.advance()?;
+          self.consume(TokenKind::Equal)?;
+          let value = self.parse_precedence_expr(value_precedence, scope)?;
+          end = value.span().clone();
+          Some(FindUserFilter::Property { name, value })
+        } else {
+          let similar = self.opt_consume(Keyword::Similar)?;
+          self.consume(Keyword::Amount)?;
+          let amount = if similar {
+            None
+          } else {
+            Some(self.parse_amount_range()?)
+          };
+          self.consume(Keyword::Of)?;
+          self.consume(Keyword::Collectable)?;
+          self.expect(TokenMatch::Identifier)?;
+          let name = self.string_token_value();
+          self.advance()?;
+          end = name.span().clone();
+          let collectable: ItemRef<Collectable> = ItemRef::new(name, self.ast.asleep_ref());
+          Some(match amount {
+            Some(amount) => FindUserFilter::Amount { collectable, amount },
+            None => FindUserFilter::SimilarAmount { collectable },
+          })
+        }
+      } else {
+        None
+      };
+      (FindTarget::User { user_type, filter }, end)
+    } else if self.opt_consume(Word("gameserver"))? {
+      self.consume(Keyword::With)?;
+      self.consume(Word("switch"))?;
+      self.expect(TokenMatch::Identifier)?;
+      let switch = self.string_token_value();
+      self.advance()?;
+      self.consume(TokenKind::Equal)?;
+      let value = self.parse_precedence_expr(value_precedence, scope)?;
+      let end = value.span().clone();
+      (FindTarget::GameServer { switch, value }, end)
+    } else {
+      return self.e_expected("event, user or gameserver");
+    };
+    Ok(box FindExpr::new(target, start.from_to(&end), self.ast.asleep_ref()))
+  }
+
+  /// time span = (integer unit)+
+  ///
+  /// The first integer has already been consumed. Units must go
+  /// from largest to smallest, e.g. `1 hour 30 minutes`.
+  fn parse_time_span(&mut self, first: &TokenValue<i64>)
+    -> Result<Option<Vec<TimeSpanPart>>>
+  {
+    let mut unit = match time_span_unit(&self.token) {
+      Some(unit) => TokenValue::new(unit, self.token.span.clone()),
+      None => return Ok(None),
+    };
+    self.advance()?;
+    let mut parts = vec![TimeSpanPart::new(first.clone(), unit.clone())?];
+    while self.token == TokenMatch::Integer {
+      let next_unit = match time_span_unit(&self.peek()?) {
+        Some(next_unit) => next_unit,
+        None => break,
+      };
+      let amount = self.int_token_value().unwrap();
+      self.advance()?;
+      if next_unit >= *unit.value() {
+        return self.e_syntax(format!(
+          "time span units must go from largest to smallest, \
+          but '{}' comes after '{}'",
+          next_unit,
+          unit.value(),
+        ));
+      }
+      unit = TokenValue::new(next_unit, self.token.span.clone());
+      self.advance()?;
+      parts.push(TimeSpanPart::new(amount, unit.clone())?);
+    }
+    Ok(Some(parts))
   }
 
 /*
@@ -964,11 +2110,105 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
 
   pub fn advance(&mut self) -> Result<()> {
     let (token, inp) = self.lexer_iresult()?;
+    self.track_blocks();
     self.inp = inp;
     self.token = token;
     Ok(())
   }
 
+  /// Keeps count of the blocks opened and closed
+  /// by the token that is being moved past.
+  fn track_blocks(&mut self) {
+    let after_or = self.after_or;
+    self.after_or = self.token == Keyword::Or;
+    match self.token.kind {
+      TokenKind::Colon if !after_or && self.bracket_depth == 0 => self.block_depth += 1,
+      TokenKind::Keyword(Keyword::End) => {
+        self.block_depth = self.block_depth.saturating_sub(1)
+      }
+      TokenKind::LBrace | TokenKind::LSquareBracket => self.bracket_depth += 1,
+      TokenKind::RBrace | TokenKind::RSquareBracket => {
+        self.bracket_depth = self.bracket_depth.saturating_sub(1)
+      }
+      // Brackets can't be left open across statements,
+      // so don't let one that was stop counting blocks.
+      TokenKind::Semicolon => self.bracket_depth = 0,
+      _ => {}
+    }
+  }
+
+  /// Runs `parse`, and if it fails, records the error and skips to
+  /// the end of the item so the caller can continue with the next one.
+  fn recoverable<F, R>(&mut self, parse: F) -> Option<R>
+  where F: FnOnce(&mut Self) -> Result<R>
+  {
+    let depth = self.block_depth;
+    match parse(self) {
+      Ok(r) => Some(r),
+      Err(e) => {
+        self.recover(e, depth);
+        None
+      }
+    }
+  }
+
+  /// Records an error and skips ahead to the end of the item it
+  /// happened in: just past the next `;` outside of any blocks
+  /// opened after `depth`, or up to the `end` or `or:` that closes
+  /// the block the item is in.
+  fn recover(&mut self, error: Error, depth: u32) {
+    let lexer_error = match *error.kind() {
+      ErrorKind::Nom(_) | ErrorKind::UnclosedString(_) => true,
+      _ => false,
+    };
+    debug!("Recovering from error: {}", error);
+    self.state.errors.push(error);
+    if lexer_error {
+      self.skip_line();
+    }
+    loop {
+      if self.token == TokenKind::Eof || self.block_depth < depth {
+        return;
+      }
+      if depth > 0 && self.block_depth == depth {
+        if self.token == Keyword::End {
+          return;
+        }
+        if self.token == Keyword::Or
+          && self.peek().map(|t| t == TokenKind::Colon).unwrap_or(false)
+        {
+          return;
+        }
+      }
+      let at_end = self.block_depth == depth && self.token == TokenKind::Semicolon;
+      if let Err(e) = self.advance() {
+        self.state.errors.push(e);
+        self.skip_line();
+      }
+      if at_end {
+        return;
+      }
+    }
+  }
+
+  /// The lexer can't continue after an error, so throw
+  /// away the rest of the line and start again on the next one.
+  fn skip_line(&mut self) {
+    let skip = self.inp
+      .iter()
+      .position(|&c| c == b'\n')
+      .map(|pos| pos + 1)
+      .unwrap_or(self.inp.len());
+    self.inp = &self.inp[skip..];
+    let span = TokenSpan::with_position(
+      self.filename.clone(),
+      self.token.span.line + 1,
+      1,
+      1,
+    );
+    self.token = Token::new(TokenKind::Invalid('\0'), span);
+  }
+
   fn advance_x(&mut self, times: u8) -> Result<()> {
     for _ in 0..times {
       self.advance()?;
@@ -992,6 +2232,17 @@ impl<'p, 'ast: 'p> Parser<'p, 'ast> {
     }
   }
 
+  /// Move to the next token, returning the current value if it is an integer.
+  fn take_int(&mut self) -> Result<TokenValue<i64>> {
+    match self.int_token_value() {
+      Some(tv) => {
+        self.advance()?;
+        Ok(tv)
+      }
+      None => self.e_expected("integer"),
+    }
+  }
+
   fn take_next(&mut self) -> Result<Token<'p>> {
     self.advance()?;
     Ok(self.token.clone())
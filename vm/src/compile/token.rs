@@ -97,6 +97,8 @@ pub enum TokenKind<'a> {
   RParen,
   LSquareBracket,
   RSquareBracket,
+  LBrace,
+  RBrace,
   Minus,
   Plus,
   Multiply,
@@ -134,6 +136,8 @@ impl<'a> TokenKind<'a> {
       TK::RParen => ")",
       TK::LSquareBracket => "[",
       TK::RSquareBracket => "]",
+      TK::LBrace => "{",
+      TK::RBrace => "}",
       TK::Minus => "-",
       TK::Plus => "+",
       TK::Multiply => "*",
@@ -371,6 +375,33 @@ impl<'a> PartialEq<TokenMatch> for Token<'a> {
   }
 }
 
+/// A word that only has special meaning in one place in the
+/// grammar, and is otherwise lexed as a plain identifier.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Word(pub &'static str);
+
+impl AsRef<str> for Word {
+  fn as_ref(&self) -> &str {
+    self.0
+  }
+}
+
+impl<'a> PartialEq<Token<'a>> for Word {
+  fn eq(&self, other: &Token<'a>) -> bool {
+    if let TokenKind::Identifier(id) = other.kind {
+      id == self.0
+    } else {
+      false
+    }
+  }
+}
+
+impl<'a> PartialEq<Word> for Token<'a> {
+  fn eq(&self, other: &Word) -> bool {
+    *other == *self
+  }
+}
+
 macro_rules! keywords {
   ( $map:ident, $typ:ident, $($s:expr => $enm:ident),+ ) => (
     #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize)]
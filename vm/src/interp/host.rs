@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::fmt::{self, Display};
+use query::QueryRunner;
+use super::*;
+
+/// Something given to or taken from a user.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Award {
+  pub target: InstanceRef,
+  pub item: AwardItem,
+  /// Negative when the item is taken away.
+  pub amount: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AwardItem {
+  /// An amount of a collectable or a collectable group. Distributions
+  /// are rolled before they get to the host.
+  Type(Arc<str>),
+  /// A particular instance, like `award -chest`.
+  Instance(InstanceRef),
+}
+
+impl Display for Award {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let sign = if self.amount < 0 { '-' } else { '+' };
+    match self.item {
+      AwardItem::Type(ref ty) => write!(f, "{}{} x {}", sign, ty, self.amount.abs())?,
+      AwardItem::Instance(ref instance) => write!(f, "{}{}", sign, instance)?,
+    }
+    write!(f, " to {}", self.target)
+  }
+}
+
+/// What a waiting strand needs before it can go on. The host
+/// schedules a trigger for each wait, like `model::event`'s
+/// `TimerEventTrigger` and `CostEventTrigger`, and resumes the
+/// continuation with the wait's ID when it fires.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Wait {
+  /// A `timer` statement, in milliseconds.
+  Timer(i64),
+  /// A `cost` statement, waiting for the user to agree to pay.
+  /// The host takes the amount when the event resumes.
+  Cost {
+    user: InstanceRef,
+    collectable: Arc<str>,
+    amount: i64,
+  },
+}
+
+impl Display for Wait {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Wait::Timer(duration) => write!(f, "timer for {}", Value::TimeSpan(duration)),
+      Wait::Cost { ref user, ref collectable, amount } => {
+        write!(f, "cost of {} x {} for {}", collectable, amount, user)
+      }
+    }
+  }
+}
+
+/// Everything the interpreter needs from the model. A model accessor
+/// backend implements this to run programs against its storage.
+///
+/// Errors from the host abort the invocation, except that assertion
+/// failures and costs that can't be paid only end an option branch.
+pub trait Host: QueryRunner<Value, Error = ExecError> {
+  /// Called when an invocation starts or resumes. Everything
+  /// the host is asked to do until `commit` or `rollback`
+  /// is part of the invocation.
+  fn begin(&mut self) -> ExecResult<()>;
+
+  fn commit(&mut self) -> ExecResult<()>;
+
+  /// Undoes everything since `begin`. Called when the invocation fails,
+  /// including when it goes over one of its limits.
+  fn rollback(&mut self);
+
+  /// `Ok(None)` if the property has never been set,
+  /// in which case the interpreter runs its initializer.
+  /// A collectable instance's `owner` is the user it was awarded to.
+  fn property(&mut self, instance: &InstanceRef, name: &str) -> ExecResult<Option<Value>>;
+
+  fn set_property(&mut self, instance: &InstanceRef, name: &str, value: Value)
+    -> ExecResult<()>;
+
+  /// `Ok(None)` if there's no such global. Names of types are
+  /// looked up in the program afterwards.
+  fn global(&mut self, name: &str) -> ExecResult<Option<Value>>;
+
+  fn set_global(&mut self, name: &str, value: Value) -> ExecResult<()>;
+
+  /// Whether the user is in a user group. Types are already
+  /// checked against the program, so this is only for groups
+  /// users join while the server runs.
+  fn in_group(&mut self, user: &InstanceRef, group: &str) -> ExecResult<bool>;
+
+  fn award(&mut self, award: &Award) -> ExecResult<()>;
+
+  /// Takes the amount of the collectable from the user,
+  /// or fails with `CantAfford`. This is called when a
+  /// `Wait::Cost` is resumed.
+  fn cost(&mut self, user: &InstanceRef, collectable: &str, amount: i64) -> ExecResult<()>;
+
+  /// Called at `assert` statements. Hosts can override this to log
+  /// failures or to let assertions through while testing.
+  fn assert(&mut self, passed: bool) -> ExecResult<()> {
+    if passed {
+      Ok(())
+    } else {
+      Err(ExecErrorKind::AssertionFailed.into())
+    }
+  }
+}
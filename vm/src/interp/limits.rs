@@ -0,0 +1,119 @@
+use std::fmt::{self, Display};
+use fxhash::FxHashSet;
+use super::*;
+
+/// How much one invocation may do before it's stopped. Programs are
+/// written by designers, so a mistake shouldn't be able to hold up
+/// or run the server out of memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Limits {
+  /// Instructions run. Each pick when rolling a distribution counts too.
+  pub instructions: u64,
+  /// The most bytes one value made by the program may take.
+  pub memory: usize,
+  /// Awards given or taken.
+  pub awards: usize,
+  /// Users and instances that are given awards, charged
+  /// costs or have their properties set.
+  pub entities: usize,
+  /// Functions called inside each other.
+  pub depth: usize,
+}
+
+impl Default for Limits {
+  fn default() -> Self {
+    Limits {
+      instructions: 100_000,
+      memory: 1 << 20,
+      awards: 1_000,
+      entities: 100,
+      depth: 100,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+  Instructions,
+  Memory,
+  Awards,
+  Entities,
+  Depth,
+}
+
+impl Display for Limit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match *self {
+      Limit::Instructions => "instruction",
+      Limit::Memory => "memory",
+      Limit::Awards => "award",
+      Limit::Entities => "entity",
+      Limit::Depth => "call depth",
+    })
+  }
+}
+
+/// What an invocation has used of its limits so far.
+#[derive(Debug)]
+pub struct Budget {
+  limits: Limits,
+  instructions: u64,
+  awards: usize,
+  entities: FxHashSet<InstanceRef>,
+  depth: usize,
+}
+
+impl Budget {
+  pub fn new(limits: Limits) -> Self {
+    Budget {
+      limits,
+      instructions: 0,
+      awards: 0,
+      entities: FxHashSet::default(),
+      depth: 0,
+    }
+  }
+
+  pub fn limits(&self) -> &Limits {
+    &self.limits
+  }
+
+  pub fn run(&mut self, instructions: u64) -> ExecResult<()> {
+    self.instructions = self.instructions.saturating_add(instructions);
+    check(Limit::Instructions, self.instructions, self.limits.instructions)
+  }
+
+  pub fn allocate(&mut self, value: &Value) -> ExecResult<()> {
+    check(Limit::Memory, value.size() as u64, self.limits.memory as u64)
+  }
+
+  pub fn award(&mut self) -> ExecResult<()> {
+    self.awards += 1;
+    check(Limit::Awards, self.awards as u64, self.limits.awards as u64)
+  }
+
+  pub fn touch(&mut self, entity: &InstanceRef) -> ExecResult<()> {
+    if !self.entities.contains(entity) {
+      self.entities.insert(entity.clone());
+    }
+    check(Limit::Entities, self.entities.len() as u64, self.limits.entities as u64)
+  }
+
+  pub fn enter(&mut self) -> ExecResult<()> {
+    self.depth += 1;
+    check(Limit::Depth, self.depth as u64, self.limits.depth as u64)
+  }
+
+  pub fn leave(&mut self) {
+    self.depth -= 1;
+  }
+}
+
+fn check(limit: Limit, used: u64, max: u64) -> ExecResult<()> {
+  if used > max {
+    Err(ExecErrorKind::LimitExceeded(limit, max).into())
+  } else {
+    Ok(())
+  }
+}
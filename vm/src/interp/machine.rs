@@ -0,0 +1,865 @@
+use std::sync::Arc;
+use std::mem;
+use std::fmt::{self, Display};
+use std::collections::BTreeMap;
+use ast::stmt::AwardSign;
+use bc::{Code, CodeKind, Instr, Program, SourcePos};
+use query::Query;
+use strings::Translations;
+use super::*;
+
+/// A runtime error with the code it happened in. The invocation
+/// is over and rolled back, along with any strands that were
+/// still waiting.
+#[derive(Debug)]
+pub struct Failure {
+  pub error: ExecError,
+  /// The event, function or initializer that failed.
+  pub code: Arc<str>,
+  pub file: Arc<str>,
+  pub position: Option<SourcePos>,
+}
+
+impl Display for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if !self.file.is_empty() {
+      f.write_str(&self.file)?;
+      if let Some(pos) = self.position {
+        write!(f, ": ({}, {})", pos.line, pos.column)?;
+      }
+      f.write_str(": ")?;
+    }
+    write!(f, "in {}: {}", self.code, self.error)
+  }
+}
+
+/// How far an invocation got before it finished or had to wait.
+#[derive(Debug)]
+pub struct Outcome {
+  pub awards: Vec<Award>,
+  /// Waits whose strands were cancelled because another branch of
+  /// their option finished first. Their triggers aren't needed anymore.
+  pub cancelled: Vec<u32>,
+  pub state: State,
+  /// Keep this to replay the invocation later.
+  pub record: Record,
+}
+
+#[derive(Debug)]
+pub enum State {
+  Finished(Value),
+  /// Save the continuation and pass it to `Interpreter::resume`
+  /// when one of its waits is triggered.
+  Waiting(Continuation),
+}
+
+/// What was run in an invocation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Invocation {
+  Event {
+    name: Arc<str>,
+    sender: InstanceRef,
+    args: Vec<Value>,
+  },
+  Function {
+    name: Arc<str>,
+    args: Vec<Value>,
+  },
+  Resume {
+    continuation: Continuation,
+    wait: u32,
+  },
+}
+
+/// An invocation with the seed its random numbers came from.
+/// Replaying it against the host as it was before gives the same
+/// awards, which is what's needed to settle disputes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+  pub invocation: Invocation,
+  pub seed: u64,
+}
+
+/// A waiting invocation, saved so it can go on later, even after a
+/// restart. Options run each of their branches as a separate strand,
+/// so there can be more than one thing to wait for at once. When one
+/// branch of an option finishes, the strands in its other branches
+/// are cancelled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Continuation {
+  code: Arc<str>,
+  kind: CodeKind,
+  sender: Option<InstanceRef>,
+  strands: Vec<Strand>,
+  /// For new strands and options.
+  next_id: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Strand {
+  id: u32,
+  pc: u32,
+  stack: Vec<Value>,
+  locals: Vec<Value>,
+  /// The options this strand is in a branch of, innermost last.
+  options: Vec<u32>,
+  wait: Option<Wait>,
+}
+
+/// Where an instruction leaves its strand.
+enum Step {
+  Next,
+  Option(u32),
+  EndBranch,
+  Wait(Wait),
+  Done(Value),
+}
+
+/// The parts of a running invocation that don't change.
+struct Frame<'p> {
+  code: &'p Code,
+  this: Option<Value>,
+  sender: Option<InstanceRef>,
+}
+
+/// Runs events and functions from a program, one invocation at a time.
+pub struct Interpreter<'p, H: Host + 'p> {
+  program: &'p Program,
+  host: &'p mut H,
+  rng: Box<Rng>,
+  /// Where each invocation's seed comes from.
+  seeds: Random,
+  /// Replays are rolled back instead of committed.
+  replaying: bool,
+  limits: Limits,
+  budget: Budget,
+  awards: Vec<Award>,
+  cancelled: Vec<u32>,
+  /// Where the current error came from, set by the innermost
+  /// frame it passes through.
+  failure: Option<(Arc<str>, Arc<str>, Option<SourcePos>)>,
+  /// What results are translated into, if anything.
+  locale: Option<(&'p Translations, Arc<str>)>,
+}
+
+impl Invocation {
+  /// The event or function that was run.
+  pub fn name(&self) -> &Arc<str> {
+    match *self {
+      Invocation::Event { ref name, .. } | Invocation::Function { ref name, .. } => name,
+      Invocation::Resume { ref continuation, .. } => continuation.code(),
+    }
+  }
+}
+
+impl Continuation {
+  fn new(code: &Code, sender: Option<InstanceRef>, args: Vec<Value>) -> Self {
+    Continuation {
+      code: code.name.clone(),
+      kind: code.kind,
+      sender,
+      strands: vec![Strand {
+        id: 0,
+        pc: 0,
+        stack: Vec::new(),
+        locals: args,
+        options: Vec::new(),
+        wait: None,
+      }],
+      next_id: 1,
+    }
+  }
+
+  /// The event or function this continues.
+  pub fn code(&self) -> &Arc<str> {
+    &self.code
+  }
+
+  /// Each wait with the ID to resume it with.
+  pub fn waits(&self) -> Vec<(u32, &Wait)> {
+    self.strands
+      .iter()
+      .filter_map(|s| s.wait.as_ref().map(|w| (s.id, w)))
+      .collect()
+  }
+
+  /// Removes the strands for which `cancel` is true,
+  /// adding the IDs of the ones that were waiting to `cancelled`.
+  fn cancel<F: Fn(&Strand) -> bool>(&mut self, cancelled: &mut Vec<u32>, cancel: F) {
+    let mut i = 0;
+    while i < self.strands.len() {
+      if !cancel(&self.strands[i]) {
+        i += 1;
+        continue;
+      }
+      let strand = self.strands.remove(i);
+      if strand.wait.is_some() {
+        cancelled.push(strand.id);
+      }
+    }
+  }
+}
+
+impl<'p, H: Host + 'p> Interpreter<'p, H> {
+  pub fn new(program: &'p Program, host: &'p mut H) -> Self {
+    Self::with_limits(program, host, Limits::default())
+  }
+
+  pub fn with_limits(program: &'p Program, host: &'p mut H, limits: Limits) -> Self {
+    Self::with_rng(program, host, limits, box Random::new(0))
+  }
+
+  /// Runs programs with random numbers from `rng`.
+  /// It's reseeded at the start of every invocation.
+  pub fn with_rng(program: &'p Program, host: &'p mut H, limits: Limits, rng: Box<Rng>)
+    -> Self
+  {
+    Interpreter {
+      program,
+      host,
+      rng,
+      seeds: Random::from_time(),
+      replaying: false,
+      limits,
+      budget: Budget::new(limits),
+      awards: Vec::new(),
+      cancelled: Vec::new(),
+      failure: None,
+      locale: None,
+    }
+  }
+
+  pub fn host(&mut self) -> &mut H {
+    &mut *self.host
+  }
+
+  /// Translates localized text in results into `locale`.
+  /// Without this, results keep the source text.
+  pub fn set_locale(&mut self, translations: &'p Translations, locale: &str) {
+    self.locale = Some((translations, locale.into()));
+  }
+
+  /// Runs an event sent by `sender` until it finishes or waits.
+  pub fn send_event(&mut self, name: &str, sender: InstanceRef, args: Vec<Value>)
+    -> Result<Outcome, Failure>
+  {
+    self.start(Invocation::Event { name: name.into(), sender, args })
+  }
+
+  pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> Result<Outcome, Failure> {
+    self.start(Invocation::Function { name: name.into(), args })
+  }
+
+  /// Goes on from a wait when its trigger fires. If the wait was
+  /// for a cost, it's taken from the user first, and a cost that
+  /// can't be paid fails the wait's branch.
+  pub fn resume(&mut self, continuation: Continuation, wait: u32) -> Result<Outcome, Failure> {
+    self.start(Invocation::Resume { continuation, wait })
+  }
+
+  /// Runs a recorded invocation again with the same seed. Nothing it
+  /// does is kept: the host is rolled back afterwards either way. For
+  /// it to go the same way as the first time, the host has to give
+  /// the same answers, so it should be restored to how it was then.
+  pub fn replay(&mut self, record: &Record) -> Result<Outcome, Failure> {
+    self.replaying = true;
+    let outcome = self.invoke(record.clone());
+    self.replaying = false;
+    outcome
+  }
+
+  fn start(&mut self, invocation: Invocation) -> Result<Outcome, Failure> {
+    let seed = self.seeds.next_u64();
+    self.invoke(Record { invocation, seed })
+  }
+
+  fn invoke(&mut self, record: Record) -> Result<Outcome, Failure> {
+    debug!("running {} with seed {}", record.invocation.name(), record.seed);
+    self.rng.reseed(record.seed);
+    let program = self.program;
+    let state = match record.invocation.clone() {
+      Invocation::Event { name, sender, args } => match program.event(&name) {
+        Some(code) => self.call(code, args, Some(sender)),
+        None => Err(not_found(&name, "event")),
+      },
+      Invocation::Function { name, args } => match program.function(&name) {
+        Some(code) => self.call(code, args, None),
+        None => Err(not_found(&name, "function")),
+      },
+      Invocation::Resume { continuation, wait } => self.resume_wait(continuation, wait),
+    }?;
+    Ok(Outcome {
+      awards: mem::replace(&mut self.awards, Vec::new()),
+      cancelled: mem::replace(&mut self.cancelled, Vec::new()),
+      state,
+      record,
+    })
+  }
+
+  fn resume_wait(&mut self, mut continuation: Continuation, wait: u32)
+    -> Result<State, Failure>
+  {
+    let program = self.program;
+    let code = match continuation.kind {
+      CodeKind::Event => program.event(&continuation.code),
+      CodeKind::Function => program.function(&continuation.code),
+      CodeKind::Initializer => None,
+    };
+    let code = match code {
+      Some(code) => code,
+      None => return Err(not_found(&continuation.code, "event or function")),
+    };
+    if let Err(e) = self.begin() {
+      return Err(self.fail(code, e));
+    }
+    let index = continuation.strands.iter().position(|s| s.id == wait && s.wait.is_some());
+    let index = match index {
+      Some(index) => index,
+      None => return Err(self.fail(code, ExecErrorKind::NotWaiting(wait).into())),
+    };
+    let mut strand = continuation.strands.remove(index);
+    let charged = match strand.wait.take() {
+      Some(Wait::Cost { ref user, ref collectable, amount }) => {
+        self.budget.touch(user).and_then(|_| self.host.cost(user, collectable, amount))
+      }
+      _ => Ok(()),
+    };
+    let resumed = match charged {
+      Ok(()) => {
+        continuation.strands.insert(index, strand);
+        Ok(())
+      }
+      Err(e) => {
+        self.locate(code, strand.pc - 1);
+        self.drop_strand(&mut continuation, strand, e)
+      }
+    };
+    match resumed {
+      Ok(()) => self.proceed(code, continuation),
+      Err(e) => Err(self.fail(code, e)),
+    }
+  }
+
+  fn call(&mut self, code: &'p Code, args: Vec<Value>, sender: Option<InstanceRef>)
+    -> Result<State, Failure>
+  {
+    if let Err(e) = self.begin() {
+      return Err(self.fail(code, e));
+    }
+    if args.len() != code.params.len() {
+      let error = ExecErrorKind::WrongArgumentCount(
+        code.name.clone(),
+        code.params.len(),
+        args.len(),
+      );
+      return Err(self.fail(code, error.into()));
+    }
+    self.proceed(code, Continuation::new(code, sender, args))
+  }
+
+  fn proceed(&mut self, code: &'p Code, mut continuation: Continuation)
+    -> Result<State, Failure>
+  {
+    let frame = Frame { code, this: None, sender: continuation.sender.clone() };
+    let result = self.run(&frame, &mut continuation);
+    match result.and_then(|r| self.finish().map(|_| r)) {
+      Ok(Some(value)) => Ok(State::Finished(self.localize(value))),
+      Ok(None) => Ok(State::Waiting(continuation)),
+      Err(error) => Err(self.fail(code, error)),
+    }
+  }
+
+  /// Results are what gets shown to users, so this is
+  /// where localized text is translated.
+  fn localize(&self, value: Value) -> Value {
+    match self.locale {
+      Some((translations, ref locale)) => value.localize(translations, locale),
+      None => value,
+    }
+  }
+
+  /// Starts counting against the limits again
+  /// and tells the host a new invocation started.
+  fn begin(&mut self) -> ExecResult<()> {
+    self.failure = None;
+    self.budget = Budget::new(self.limits);
+    self.host.begin()
+  }
+
+  fn finish(&mut self) -> ExecResult<()> {
+    if self.replaying {
+      self.host.rollback();
+      Ok(())
+    } else {
+      self.host.commit()
+    }
+  }
+
+  /// Rolls back the invocation. Going over a limit is logged
+  /// here, since it usually means the program needs fixing.
+  fn fail(&mut self, code: &Code, error: ExecError) -> Failure {
+    self.host.rollback();
+    self.awards.clear();
+    self.cancelled.clear();
+    let (code, file, position) = self.failure.take().unwrap_or_else(|| {
+      (code.name.clone(), code.file.clone(), None)
+    });
+    let failure = Failure { error, code, file, position };
+    if let ExecErrorKind::LimitExceeded(..) = *failure.error.kind() {
+      warn!("{}", failure);
+    }
+    failure
+  }
+
+  /// Records where an error happened, unless a frame
+  /// it came through already did.
+  fn locate(&mut self, code: &Code, pc: u32) {
+    if self.failure.is_none() {
+      let position = code.position(pc).cloned();
+      self.failure = Some((code.name.clone(), code.file.clone(), position));
+    }
+  }
+
+  /// Runs strands until they've all finished or are waiting.
+  /// `Ok(None)` means at least one of them is waiting.
+  fn run(&mut self, frame: &Frame<'p>, state: &mut Continuation) -> ExecResult<Option<Value>> {
+    while let Some(index) = state.strands.iter().position(|s| s.wait.is_none()) {
+      let strand = state.strands.remove(index);
+      if let Some(value) = self.run_strand(frame, state, strand)? {
+        state.cancel(&mut self.cancelled, |_| true);
+        return Ok(Some(value));
+      }
+    }
+    Ok(None)
+  }
+
+  /// Runs a strand until it waits, which puts it back in `state`,
+  /// or until it returns. A strand in an option branch that fails
+  /// one of its checks is dropped.
+  fn run_strand(&mut self, frame: &Frame<'p>, state: &mut Continuation, mut strand: Strand)
+    -> ExecResult<Option<Value>>
+  {
+    loop {
+      let pc = strand.pc;
+      match self.step(frame, &mut strand) {
+        Ok(Step::Next) => {}
+        Ok(Step::Option(table)) => {
+          let branches = &frame.code.branches[table as usize];
+          let id = state.next_id;
+          state.next_id += 1;
+          strand.options.push(id);
+          for &start in &branches[1..] {
+            let mut branch = strand.clone();
+            branch.id = state.next_id;
+            state.next_id += 1;
+            branch.pc = start;
+            state.strands.push(branch);
+          }
+          strand.pc = branches[0];
+        }
+        Ok(Step::EndBranch) => {
+          if let Some(id) = strand.options.pop() {
+            state.cancel(&mut self.cancelled, |s| s.options.contains(&id));
+          }
+        }
+        Ok(Step::Wait(wait)) => {
+          strand.wait = Some(wait);
+          let index = state.strands.iter().position(|s| s.id > strand.id);
+          let index = index.unwrap_or_else(|| state.strands.len());
+          state.strands.insert(index, strand);
+          return Ok(None);
+        }
+        Ok(Step::Done(value)) => return Ok(Some(value)),
+        Err(e) => {
+          self.locate(frame.code, pc);
+          self.drop_strand(state, strand, e)?;
+          return Ok(None);
+        }
+      }
+    }
+  }
+
+  /// The error ends the whole invocation unless it only
+  /// fails an option branch and there are other strands left.
+  fn drop_strand(&mut self, state: &mut Continuation, strand: Strand, error: ExecError)
+    -> ExecResult<()>
+  {
+    if strand.options.is_empty() || !is_branch_failure(&error) || state.strands.is_empty() {
+      state.cancel(&mut self.cancelled, |_| true);
+      return Err(error);
+    }
+    self.failure = None;
+    Ok(())
+  }
+
+  /// Runs one instruction.
+  fn step(&mut self, frame: &Frame<'p>, strand: &mut Strand) -> ExecResult<Step> {
+    let program = self.program;
+    self.budget.run(1)?;
+    let instr = match frame.code.instrs.get(strand.pc as usize) {
+      Some(&instr) => instr,
+      None => return Err(ExecErrorKind::InvalidProgram("ran past the end of the code").into()),
+    };
+    strand.pc += 1;
+    match instr {
+      Instr::Const(n) => strand.push(Value::from(program.constant(n))),
+      Instr::Pop => {
+        strand.pop()?;
+      }
+      Instr::Dup => {
+        let value = strand.peek()?.clone();
+        strand.push(value);
+      }
+      Instr::LoadLocal(n) => {
+        let value = strand.local(n)?.clone();
+        strand.push(value);
+      }
+      Instr::StoreLocal(n) => {
+        let value = strand.pop()?;
+        *strand.local(n)? = value;
+      }
+      Instr::LoadGlobal(n) => {
+        let value = self.global(program.name(n))?;
+        strand.push(value);
+      }
+      Instr::StoreGlobal(n) => {
+        let value = strand.pop()?;
+        self.host.set_global(program.name(n), value)?;
+      }
+      Instr::LoadSelf => {
+        let this = match frame.this {
+          Some(ref this) => this.clone(),
+          None => return Err(ExecErrorKind::InvalidProgram("no instance for `self`").into()),
+        };
+        strand.push(this);
+      }
+      Instr::LoadSender => {
+        let sender = frame.sender()?.clone();
+        strand.push(Value::Instance(sender));
+      }
+      Instr::GetProperty(n) => {
+        let target = strand.pop()?;
+        let value = self.property(target, program.name(n))?;
+        strand.push(value);
+      }
+      Instr::SetProperty(n) => {
+        let value = strand.pop()?;
+        let target = strand.pop()?;
+        let target = target.as_instance()?;
+        self.budget.touch(target)?;
+        self.host.set_property(target, program.name(n), value)?;
+      }
+      Instr::Not => {
+        let value = strand.pop()?.not()?;
+        strand.push(value);
+      }
+      Instr::Neg => {
+        let value = strand.pop()?.neg()?;
+        strand.push(value);
+      }
+      Instr::Binary(operator) => {
+        let right = strand.pop()?;
+        let left = strand.pop()?;
+        let value = Value::binary(operator, &left, &right)?;
+        self.budget.allocate(&value)?;
+        strand.push(value);
+      }
+      Instr::Index => {
+        let index = strand.pop()?;
+        let value = strand.pop()?.index(&index)?;
+        strand.push(value);
+      }
+      Instr::MakeArray(n) => {
+        let value = Value::Array(strand.pop_n(n as usize)?);
+        self.budget.allocate(&value)?;
+        strand.push(value);
+      }
+      Instr::MakeObject(n) => {
+        let mut values = strand.pop_n(2 * n as usize)?.into_iter();
+        let mut object = BTreeMap::new();
+        while let (Some(key), Some(value)) = (values.next(), values.next()) {
+          match key {
+            Value::Text(key) => object.insert(key, value),
+            _ => return Err(ExecErrorKind::InvalidProgram("object keys must be text").into()),
+          };
+        }
+        let value = Value::Object(object);
+        self.budget.allocate(&value)?;
+        strand.push(value);
+      }
+      Instr::Find(n) => {
+        let query = program.query(n).clone();
+        let mut count = 0;
+        let _ = query.clone().map(|()| -> Result<(), ()> {
+          count += 1;
+          Ok(())
+        });
+        let mut operands = strand.pop_n(count)?.into_iter();
+        let query = query.map(|()| -> Result<Value, ()> { Ok(operands.next().unwrap()) }).unwrap();
+        let found = self.host.find(&query)?;
+        strand.push(match query {
+          Query::EventInstance { .. } => Value::Option(found.is_some()),
+          _ => found.unwrap_or(Value::Void),
+        });
+      }
+      Instr::Call(n, args) => {
+        let args = strand.pop_n(args as usize)?;
+        let value = self.function(program.name(n), args)?;
+        strand.push(value);
+      }
+      Instr::Jump(target) => strand.pc = target,
+      Instr::JumpIf(target) => {
+        if strand.pop()?.as_option()? {
+          strand.pc = target;
+        }
+      }
+      Instr::JumpUnless(target) => {
+        if !strand.pop()?.as_option()? {
+          strand.pc = target;
+        }
+      }
+      Instr::Assert => {
+        let passed = strand.pop()?.as_option()?;
+        self.host.assert(passed)?;
+      }
+      Instr::Authorize => {
+        let user = strand.pop()?;
+        let sender = frame.sender()?;
+        if user != Value::Instance(sender.clone()) {
+          return Err(ExecErrorKind::Unauthorized(sender.to_string()).into());
+        }
+      }
+      Instr::AuthorizeType(n) => {
+        let sender = frame.sender()?;
+        let name = program.name(n);
+        if !program.is_a(&sender.ty, name) && !self.host.in_group(sender, name)? {
+          return Err(ExecErrorKind::Unauthorized(sender.to_string()).into());
+        }
+      }
+      Instr::AwardType(sign, n) => {
+        let target = strand.pop()?;
+        let amount = strand.pop()?.as_integer()?;
+        self.award_type(sign, program.name(n), amount, target.as_instance()?)?;
+      }
+      Instr::AwardInstance(sign) => {
+        let target = strand.pop()?;
+        let amount = strand.pop()?.as_integer()?;
+        let target = target.as_instance()?;
+        match strand.pop()? {
+          Value::Type(ref name) => self.award_type(sign, name, amount, target)?,
+          Value::Instance(instance) => self.award(Award {
+            target: target.clone(),
+            item: AwardItem::Instance(instance),
+            amount: signed(sign, amount),
+          })?,
+          value => return Err(ExecErrorKind::TypeMismatch("instance", value.to_string()).into()),
+        }
+      }
+      Instr::Cost(n) => {
+        let amount = strand.pop()?.as_integer()?;
+        return Ok(Step::Wait(Wait::Cost {
+          user: frame.sender()?.clone(),
+          collectable: program.name(n).clone(),
+          amount,
+        }));
+      }
+      Instr::Timer => {
+        let duration = strand.pop()?.as_time_span()?;
+        if duration > 0 {
+          return Ok(Step::Wait(Wait::Timer(duration)));
+        }
+      }
+      Instr::Option(table) => {
+        match frame.code.branches.get(table as usize) {
+          Some(branches) if !branches.is_empty() => {}
+          _ => return Err(ExecErrorKind::InvalidProgram("option without branches").into()),
+        }
+        return Ok(Step::Option(table));
+      }
+      Instr::EndBranch(target) => {
+        strand.pc = target;
+        return Ok(Step::EndBranch);
+      }
+      Instr::Return => return strand.pop().map(Step::Done),
+      Instr::End => return Ok(Step::Done(Value::Void)),
+    }
+    Ok(Step::Next)
+  }
+
+  fn global(&mut self, name: &Arc<str>) -> ExecResult<Value> {
+    if let Some(value) = self.host.global(name)? {
+      return Ok(value);
+    }
+    if self.program.type_info(name).is_some() || self.program.distribution(name).is_some() {
+      Ok(Value::Type(name.clone()))
+    } else {
+      Err(ExecErrorKind::NotDefined(name.clone(), "variable").into())
+    }
+  }
+
+  /// Properties that were never set get their initial value
+  /// from the program, which is then saved on the instance.
+  fn property(&mut self, target: Value, name: &Arc<str>) -> ExecResult<Value> {
+    let instance = match target {
+      Value::Instance(ref instance) => instance,
+      Value::Object(ref object) => {
+        return object.get(name).cloned().ok_or_else(|| {
+          ExecErrorKind::NoProperty(name.clone(), target.to_string()).into()
+        });
+      }
+      _ => return Err(ExecErrorKind::NoProperty(name.clone(), target.to_string()).into()),
+    };
+    if let Some(value) = self.host.property(instance, name)? {
+      return Ok(value);
+    }
+    let program = self.program;
+    let code = match program.initializer(&instance.ty, name) {
+      Some(code) => code,
+      None => return Err(ExecErrorKind::NoProperty(name.clone(), instance.to_string()).into()),
+    };
+    let frame = Frame { code, this: Some(target.clone()), sender: None };
+    let mut state = Continuation::new(code, None, Vec::new());
+    let value = match self.run(&frame, &mut state)? {
+      Some(value) => value,
+      None => return Err(ExecErrorKind::InvalidProgram("initializers can't wait").into()),
+    };
+    self.host.set_property(instance, name, value.clone())?;
+    Ok(value)
+  }
+
+  /// Calls from a program run right away in the same invocation,
+  /// which is fine since functions can't wait.
+  fn function(&mut self, name: &Arc<str>, args: Vec<Value>) -> ExecResult<Value> {
+    let program = self.program;
+    let code = match program.function(name) {
+      Some(code) => code,
+      None => return Err(ExecErrorKind::NotDefined(name.clone(), "function").into()),
+    };
+    if args.len() != code.params.len() {
+      return Err(ExecErrorKind::WrongArgumentCount(
+        name.clone(),
+        code.params.len(),
+        args.len(),
+      ).into());
+    }
+    self.budget.enter()?;
+    let frame = Frame { code, this: None, sender: None };
+    let mut state = Continuation::new(code, None, args);
+    let value = self.run(&frame, &mut state);
+    self.budget.leave();
+    match value? {
+      Some(value) => Ok(value),
+      None => Err(ExecErrorKind::InvalidProgram("functions can't wait").into()),
+    }
+  }
+
+  /// Distributions are rolled once for each of the amount.
+  fn award_type(&mut self, sign: AwardSign, name: &Arc<str>, amount: i64, target: &InstanceRef)
+    -> ExecResult<()>
+  {
+    let program = self.program;
+    let table = match program.distribution(name) {
+      Some(table) => table,
+      None => return self.award(Award {
+        target: target.clone(),
+        item: AwardItem::Type(name.clone()),
+        amount: signed(sign, amount),
+      }),
+    };
+    if sign == AwardSign::Remove {
+      return Err(ExecErrorKind::ValueOutOfRange(
+        name.to_string(),
+        "distributions can't be taken away",
+      ).into());
+    }
+    for _ in 0..amount {
+      for (item, amount) in roll(table, &mut *self.rng, &mut self.budget)? {
+        self.award(Award {
+          target: target.clone(),
+          item: AwardItem::Type(item),
+          amount,
+        })?;
+      }
+    }
+    Ok(())
+  }
+
+  fn award(&mut self, award: Award) -> ExecResult<()> {
+    self.budget.award()?;
+    self.budget.touch(&award.target)?;
+    if let AwardItem::Instance(ref instance) = award.item {
+      self.budget.touch(instance)?;
+    }
+    self.host.award(&award)?;
+    self.awards.push(award);
+    Ok(())
+  }
+}
+
+impl<'p> Frame<'p> {
+  fn sender(&self) -> ExecResult<&InstanceRef> {
+    self.sender
+      .as_ref()
+      .ok_or_else(|| ExecErrorKind::InvalidProgram("only events have a sender").into())
+  }
+}
+
+impl Strand {
+  fn push(&mut self, value: Value) {
+    self.stack.push(value);
+  }
+
+  fn pop(&mut self) -> ExecResult<Value> {
+    self.stack.pop().ok_or_else(stack_underflow)
+  }
+
+  fn peek(&self) -> ExecResult<&Value> {
+    self.stack.last().ok_or_else(stack_underflow)
+  }
+
+  /// The top `n` values, the top one last.
+  fn pop_n(&mut self, n: usize) -> ExecResult<Vec<Value>> {
+    if n > self.stack.len() {
+      return Err(stack_underflow());
+    }
+    let start = self.stack.len() - n;
+    Ok(self.stack.split_off(start))
+  }
+
+  fn local(&mut self, n: u16) -> ExecResult<&mut Value> {
+    self.locals
+      .get_mut(n as usize)
+      .ok_or_else(|| ExecErrorKind::InvalidProgram("no such local").into())
+  }
+}
+
+fn not_found(name: &str, what: &'static str) -> Failure {
+  Failure {
+    error: ExecErrorKind::NotDefined(name.into(), what).into(),
+    code: name.into(),
+    file: "".into(),
+    position: None,
+  }
+}
+
+/// These end only the strand they happen in when it's in an option
+/// branch, since one of the option's other branches may still finish.
+fn is_branch_failure(error: &ExecError) -> bool {
+  match *error.kind() {
+    ExecErrorKind::AssertionFailed
+    | ExecErrorKind::Unauthorized(_)
+    | ExecErrorKind::CantAfford(..) => true,
+    _ => false,
+  }
+}
+
+fn stack_underflow() -> ExecError {
+  ExecErrorKind::InvalidProgram("stack underflow").into()
+}
+
+fn signed(sign: AwardSign, amount: i64) -> i64 {
+  match sign {
+    AwardSign::Add => amount,
+    AwardSign::Remove => -amount,
+  }
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+use fxhash::FxHashMap;
+use query::{Query, QueryRunner, UserFilter};
+use super::*;
+
+/// A host that keeps everything in memory, for `scifiweb run` and for
+/// trying programs out. Nothing is saved, so continuations have
+/// to be resumed by whoever holds them.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryHost {
+  next_id: u64,
+  properties: FxHashMap<InstanceRef, FxHashMap<Arc<str>, Value>>,
+  globals: FxHashMap<Arc<str>, Value>,
+  /// How much of each collectable type each user has.
+  amounts: FxHashMap<InstanceRef, FxHashMap<Arc<str>, i64>>,
+  /// The user each collectable instance belongs to.
+  owners: FxHashMap<InstanceRef, InstanceRef>,
+  groups: FxHashMap<InstanceRef, Vec<Arc<str>>>,
+  awards: Vec<Award>,
+  /// Everything as it was when the invocation began.
+  saved: Option<Box<MemoryHost>>,
+}
+
+impl MemoryHost {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Makes a new instance with all of its properties unset.
+  pub fn create(&mut self, ty: Arc<str>) -> InstanceRef {
+    self.next_id += 1;
+    let instance = InstanceRef::new(ty, self.next_id);
+    self.properties.insert(instance.clone(), FxHashMap::default());
+    instance
+  }
+
+  pub fn amount(&self, user: &InstanceRef, collectable: &str) -> i64 {
+    self.amounts.get(user).and_then(|a| a.get(collectable)).cloned().unwrap_or(0)
+  }
+
+  pub fn owner(&self, instance: &InstanceRef) -> Option<&InstanceRef> {
+    self.owners.get(instance)
+  }
+
+  pub fn join_group(&mut self, user: &InstanceRef, group: Arc<str>) {
+    self.groups.entry(user.clone()).or_insert_with(Vec::new).push(group);
+  }
+
+  /// Every award so far, in order.
+  pub fn awards(&self) -> &[Award] {
+    &self.awards
+  }
+
+  fn add_amount(&mut self, user: &InstanceRef, collectable: &Arc<str>, amount: i64)
+    -> ExecResult<()>
+  {
+    let current = self.amount(user, collectable);
+    let new_amount = match current.checked_add(amount) {
+      Some(new_amount) if new_amount >= 0 => new_amount,
+      _ => return Err(ExecErrorKind::CantAfford(
+        user.to_string(),
+        collectable.clone(),
+        -amount,
+      ).into()),
+    };
+    self.amounts
+      .entry(user.clone())
+      .or_insert_with(FxHashMap::default)
+      .insert(collectable.clone(), new_amount);
+    Ok(())
+  }
+}
+
+impl QueryRunner<Value> for MemoryHost {
+  type Error = ExecError;
+
+  /// Nothing waits in memory, so there are never any events
+  /// to find. Users are found in the order they were created.
+  fn find(&mut self, query: &Query<Value>) -> ExecResult<Option<Value>> {
+    let (user_type, filter) = match *query {
+      Query::User { ref user_type, ref filter } => (user_type, filter),
+      Query::EventInstance { .. } | Query::GameServer { .. } => return Ok(None),
+    };
+    let mut users = self.properties
+      .keys()
+      .filter(|i| &i.ty == user_type || self.groups.get(i).map_or(false, |g| g.contains(user_type)))
+      .collect::<Vec<_>>();
+    users.sort();
+    let found = users.into_iter().find(|user| match *filter {
+      Some(UserFilter::Property { ref name, ref value }) => {
+        self.properties[*user].get(name) == Some(value)
+      }
+      Some(UserFilter::Amount { ref collectable, min, max }) => {
+        let amount = self.amount(user, collectable);
+        min.map_or(true, |min| amount >= min) && max.map_or(true, |max| amount <= max)
+      }
+      Some(UserFilter::SimilarAmount { .. }) | None => true,
+    });
+    Ok(found.map(|user| Value::Instance(user.clone())))
+  }
+}
+
+impl Host for MemoryHost {
+  fn begin(&mut self) -> ExecResult<()> {
+    self.saved = None;
+    self.saved = Some(box self.clone());
+    Ok(())
+  }
+
+  fn commit(&mut self) -> ExecResult<()> {
+    self.saved = None;
+    Ok(())
+  }
+
+  fn rollback(&mut self) {
+    if let Some(saved) = self.saved.take() {
+      *self = *saved;
+    }
+  }
+
+  fn property(&mut self, instance: &InstanceRef, name: &str) -> ExecResult<Option<Value>> {
+    match self.properties.get(instance) {
+      Some(properties) if name == "owner" && !properties.contains_key(name) => {
+        Ok(self.owners.get(instance).cloned().map(Value::Instance))
+      }
+      Some(properties) => Ok(properties.get(name).cloned()),
+      None => Err(ExecErrorKind::NotDefined(instance.to_string().into(), "instance").into()),
+    }
+  }
+
+  fn set_property(&mut self, instance: &InstanceRef, name: &str, value: Value)
+    -> ExecResult<()>
+  {
+    match self.properties.get_mut(instance) {
+      Some(properties) => {
+        properties.insert(name.into(), value);
+        Ok(())
+      }
+      None => Err(ExecErrorKind::NotDefined(instance.to_string().into(), "instance").into()),
+    }
+  }
+
+  fn global(&mut self, name: &str) -> ExecResult<Option<Value>> {
+    Ok(self.globals.get(name).cloned())
+  }
+
+  fn set_global(&mut self, name: &str, value: Value) -> ExecResult<()> {
+    self.globals.insert(name.into(), value);
+    Ok(())
+  }
+
+  fn in_group(&mut self, user: &InstanceRef, group: &str) -> ExecResult<bool> {
+    Ok(self.groups.get(user).map_or(false, |g| g.iter().any(|g| &**g == group)))
+  }
+
+  /// Taking away an instance destroys it.
+  fn award(&mut self, award: &Award) -> ExecResult<()> {
+    match award.item {
+      AwardItem::Type(ref ty) => self.add_amount(&award.target, ty, award.amount)?,
+      AwardItem::Instance(ref instance) => {
+        if award.amount > 0 {
+          self.owners.insert(instance.clone(), award.target.clone());
+        } else if self.owners.get(instance) == Some(&award.target) {
+          self.owners.remove(instance);
+          self.properties.remove(instance);
+        }
+      }
+    }
+    debug!("award {}", award);
+    self.awards.push(award.clone());
+    Ok(())
+  }
+
+  fn cost(&mut self, user: &InstanceRef, collectable: &str, amount: i64) -> ExecResult<()> {
+    let collectable: Arc<str> = collectable.into();
+    self.add_amount(user, &collectable, -amount)
+  }
+}
@@ -0,0 +1,112 @@
+//! Runs compiled programs. The interpreter works on `bc::Program`s, so
+//! it doesn't need the AST, and reaches the model only through a `Host`.
+//!
+//! Events that reach a timer, a cost or an option that can't finish
+//! yet stop with a `Continuation`. It's serializable, so the host can
+//! keep it for as long as the wait takes and resume it when the
+//! wait's trigger fires.
+//!
+//! Every invocation seeds the `Rng` anew and records the seed, so an
+//! invocation can be replayed later and roll the same distributions.
+
+mod host;
+mod limits;
+mod machine;
+mod memory;
+mod random;
+mod value;
+
+pub use self::host::*;
+pub use self::limits::*;
+pub use self::machine::*;
+pub use self::memory::*;
+pub use self::random::*;
+pub use self::value::*;
+
+mod errors {
+  #![allow(unused_doc_comment)]
+  use std::sync::Arc;
+  use super::Limit;
+
+  error_chain! {
+    errors {
+      TypeMismatch(expected: &'static str, found: String) {
+        description("type mismatch")
+        display("expected {}, found '{}'", expected, &found)
+      }
+
+      IntegerOutOfRange {
+        description("integer out of range")
+        display("integer out of range")
+      }
+
+      DivisionByZero {
+        description("division by zero")
+        display("division by zero")
+      }
+
+      ValueOutOfRange(value: String, reason: &'static str) {
+        description("value out of range")
+        display("value '{}' out of range: {}", &value, reason)
+      }
+
+      IndexOutOfRange(index: i64, length: usize) {
+        description("array index out of range")
+        display("index {} is out of range for an array of length {}", index, length)
+      }
+
+      NotDefined(name: Arc<str>, what: &'static str) {
+        description("item not defined")
+        display("no definition for {} '{}'", what, &name)
+      }
+
+      NoProperty(name: Arc<str>, value: String) {
+        description("property not found")
+        display("'{}' has no property '{}'", &value, &name)
+      }
+
+      WrongArgumentCount(name: Arc<str>, expected: usize, found: usize) {
+        description("wrong number of arguments")
+        display("'{}' takes {} argument(s), but {} were given", &name, expected, found)
+      }
+
+      AssertionFailed {
+        description("assertion failed")
+        display("assertion failed")
+      }
+
+      Unauthorized(sender: String) {
+        description("sender not authorized")
+        display("'{}' isn't allowed to send this event", &sender)
+      }
+
+      CantAfford(user: String, collectable: Arc<str>, amount: i64) {
+        description("not enough to pay the cost")
+        display("'{}' doesn't have {} of '{}'", &user, amount, &collectable)
+      }
+
+      NotWaiting(id: u32) {
+        description("nothing is waiting")
+        display("there's no strand waiting with ID {}", id)
+      }
+
+      LimitExceeded(limit: Limit, max: u64) {
+        description("limit exceeded")
+        display("went over the {} limit of {}", limit, max)
+      }
+
+      // The program was changed or compiled wrong.
+      InvalidProgram(reason: &'static str) {
+        description("invalid program")
+        display("invalid program: {}", reason)
+      }
+    }
+  }
+}
+
+pub use self::errors::{
+  Error as ExecError,
+  ErrorKind as ExecErrorKind,
+  Result as ExecResult,
+  ResultExt as ExecResultExt,
+};
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bc::{Bounds, DistributionTable};
+use super::*;
+
+/// Where random numbers come from. Each invocation reseeds the
+/// generator with a new seed that's recorded with it, so the same
+/// seed must always give the same numbers for replays to work.
+pub trait Rng {
+  fn reseed(&mut self, seed: u64);
+
+  fn next_u64(&mut self) -> u64;
+
+  /// A number from 0 up to but not including `n`, or 0 if `n` is 0.
+  fn below(&mut self, n: u64) -> u64 {
+    if n == 0 { 0 } else { self.next_u64() % n }
+  }
+
+  /// A number from 0 up to but not including 1.
+  fn fraction(&mut self) -> f64 {
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+
+  /// A number in the range. A missing minimum is `default_min`,
+  /// and a missing maximum is the minimum.
+  fn between(&mut self, bounds: &Bounds, default_min: i64) -> i64 {
+    let min = bounds.min.unwrap_or(default_min);
+    let max = bounds.max.unwrap_or(min).max(min);
+    min + self.below((max - min) as u64 + 1) as i64
+  }
+}
+
+/// A small xorshift generator, the default `Rng`.
+/// It doesn't need to be unpredictable, only uniform.
+#[derive(Debug, Clone)]
+pub struct Random {
+  state: u64,
+}
+
+impl Random {
+  pub fn new(seed: u64) -> Self {
+    let mut random = Random { state: 0 };
+    random.reseed(seed);
+    random
+  }
+
+  /// Seeded from the clock.
+  pub fn from_time() -> Self {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Random::new(now.as_secs() ^ (now.subsec_nanos() as u64) << 32)
+  }
+}
+
+impl Rng for Random {
+  fn reseed(&mut self, seed: u64) {
+    // Zero is the one state xorshift can't leave.
+    self.state = if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed };
+  }
+
+  fn next_u64(&mut self) -> u64 {
+    let mut x = self.state;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    self.state = x;
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+  }
+}
+
+/// Picks entries from a distribution and how much of each is
+/// awarded, in the order they were first picked.
+///
+/// Each pick takes an entry by weight, and the entries without a
+/// weight share what the others leave. Every pick gets at least its
+/// entry's minimum amount (1 if there isn't one), then the rest of the
+/// distribution's total amount goes to picks that are under their maximum.
+/// Each pick and each share of the total counts as an instruction.
+pub fn roll(table: &DistributionTable, random: &mut Rng, budget: &mut Budget)
+  -> ExecResult<Vec<(Arc<str>, i64)>>
+{
+  if table.entries.is_empty() {
+    return Ok(Vec::new());
+  }
+  let picks = table.picks.as_ref().map(|p| random.between(p, 1)).unwrap_or(1);
+  budget.run(picks.max(0) as u64)?;
+  let explicit = table.entries.iter().filter_map(|e| e.weight).sum::<f64>();
+  let unweighted = table.entries.iter().filter(|e| e.weight.is_none()).count();
+  let share = if unweighted == 0 {
+    0.0
+  } else {
+    ((1.0 - explicit) / unweighted as f64).max(0.0)
+  };
+  let weights = table.entries.iter().map(|e| e.weight.unwrap_or(share)).collect::<Vec<_>>();
+  let total_weight = weights.iter().sum::<f64>();
+
+  let mut picked = Vec::with_capacity(picks.max(0) as usize);
+  for _ in 0..picks {
+    let mut x = random.fraction() * total_weight;
+    let mut index = weights.len() - 1;
+    for (i, &weight) in weights.iter().enumerate() {
+      if x < weight {
+        index = i;
+        break;
+      }
+      x -= weight;
+    }
+    let entry = &table.entries[index];
+    let amount = match (table.amount.is_some(), entry.amount.as_ref()) {
+      (true, Some(amount)) => amount.min.unwrap_or(1),
+      (false, Some(amount)) => random.between(amount, 1),
+      (_, None) => 1,
+    };
+    picked.push((index, amount));
+  }
+
+  if let Some(ref amount) = table.amount {
+    let mut remaining = random.between(amount, 0) - picked.iter().map(|p| p.1).sum::<i64>();
+    while remaining > 0 {
+      budget.run(1)?;
+      let open = picked
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &(entry, amount))| {
+          let max = table.entries[entry].amount.as_ref().and_then(|a| a.max);
+          match max {
+            Some(max) if amount >= max => None,
+            Some(max) => Some((i, max - amount)),
+            None => Some((i, remaining)),
+          }
+        })
+        .collect::<Vec<_>>();
+      if open.is_empty() {
+        break;
+      }
+      let (i, room) = open[random.below(open.len() as u64) as usize];
+      let add = 1 + random.below(room.min(remaining) as u64) as i64;
+      picked[i].1 += add;
+      remaining -= add;
+    }
+  }
+
+  let mut awards: Vec<(Arc<str>, i64)> = Vec::new();
+  for (entry, amount) in picked {
+    let item = &table.entries[entry].item;
+    match awards.iter().position(|a| &a.0 == item) {
+      Some(i) => awards[i].1 += amount,
+      None => awards.push((item.clone(), amount)),
+    }
+  }
+  Ok(awards)
+}
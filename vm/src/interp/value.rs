@@ -0,0 +1,398 @@
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::{i64, mem};
+use ast::expr::{BinaryOperator, Constant};
+use ast::ty::PrimitiveType;
+use strings::{Translations, string_key};
+use super::*;
+
+/// A value while a program runs. There's one variant for each primitive
+/// type, and instances of custom types are references the host resolves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Value {
+  Void,
+  Option(bool),
+  Text(Arc<str>),
+  LocalizedText(Arc<str>),
+  Integer(i64),
+  Decimal(f64),
+  /// Milliseconds since the Unix epoch.
+  DateTime(i64),
+  /// In milliseconds.
+  TimeSpan(i64),
+  Object(BTreeMap<Arc<str>, Value>),
+  Array(Vec<Value>),
+  Instance(InstanceRef),
+  /// A type named where a value goes, like a distribution
+  /// set as a property's default.
+  Type(Arc<str>),
+}
+
+/// An instance of a custom type: a user, a collectable, an object
+/// and so on. Only the host knows what's in it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct InstanceRef {
+  pub ty: Arc<str>,
+  pub id: u64,
+}
+
+impl InstanceRef {
+  pub fn new(ty: Arc<str>, id: u64) -> Self {
+    InstanceRef { ty, id }
+  }
+}
+
+impl Display for InstanceRef {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}#{}", self.ty, self.id)
+  }
+}
+
+impl<'c> From<&'c Constant> for Value {
+  fn from(constant: &'c Constant) -> Self {
+    match *constant {
+      Constant::Option(o) => Value::Option(o),
+      Constant::Text(ref t) => Value::Text(t.clone()),
+      Constant::LocalizedText(ref t) => Value::LocalizedText(t.clone()),
+      Constant::Integer(i) => Value::Integer(i),
+      Constant::Decimal(d) => Value::Decimal(d),
+      Constant::TimeSpan(t) => Value::TimeSpan(t),
+      Constant::Array(ref a) => Value::Array(a.iter().map(Value::from).collect()),
+    }
+  }
+}
+
+impl Value {
+  /// What a value of the type starts as when nothing else is given.
+  pub fn default_for(ty: PrimitiveType) -> Self {
+    match ty {
+      PrimitiveType::Void => Value::Void,
+      PrimitiveType::Option => Value::Option(false),
+      PrimitiveType::Text => Value::Text("".into()),
+      PrimitiveType::LocalizedText => Value::LocalizedText("".into()),
+      PrimitiveType::Integer => Value::Integer(0),
+      PrimitiveType::Decimal => Value::Decimal(0.0),
+      PrimitiveType::DateTime => Value::DateTime(0),
+      PrimitiveType::TimeSpan => Value::TimeSpan(0),
+      PrimitiveType::Object => Value::Object(BTreeMap::new()),
+      PrimitiveType::Array => Value::Array(Vec::new()),
+    }
+  }
+
+  /// `None` for instances and types.
+  pub fn primitive_type(&self) -> Option<PrimitiveType> {
+    Some(match *self {
+      Value::Void => PrimitiveType::Void,
+      Value::Option(_) => PrimitiveType::Option,
+      Value::Text(_) => PrimitiveType::Text,
+      Value::LocalizedText(_) => PrimitiveType::LocalizedText,
+      Value::Integer(_) => PrimitiveType::Integer,
+      Value::Decimal(_) => PrimitiveType::Decimal,
+      Value::DateTime(_) => PrimitiveType::DateTime,
+      Value::TimeSpan(_) => PrimitiveType::TimeSpan,
+      Value::Object(_) => PrimitiveType::Object,
+      Value::Array(_) => PrimitiveType::Array,
+      Value::Instance(_) | Value::Type(_) => return None,
+    })
+  }
+
+  /// About how many bytes the value takes, counting what it points to.
+  pub fn size(&self) -> usize {
+    mem::size_of::<Value>() + match *self {
+      Value::Text(ref t) | Value::LocalizedText(ref t) | Value::Type(ref t) => t.len(),
+      Value::Instance(ref i) => i.ty.len(),
+      Value::Array(ref a) => a.iter().map(Value::size).sum(),
+      Value::Object(ref o) => o.iter().map(|(k, v)| k.len() + v.size()).sum(),
+      _ => 0,
+    }
+  }
+
+  pub fn as_option(&self) -> ExecResult<bool> {
+    match *self {
+      Value::Option(o) => Ok(o),
+      _ => Err(self.mismatch("option")),
+    }
+  }
+
+  pub fn as_integer(&self) -> ExecResult<i64> {
+    match *self {
+      Value::Integer(i) => Ok(i),
+      _ => Err(self.mismatch("integer")),
+    }
+  }
+
+  pub fn as_time_span(&self) -> ExecResult<i64> {
+    match *self {
+      Value::TimeSpan(t) => Ok(t),
+      _ => Err(self.mismatch("timespan")),
+    }
+  }
+
+  pub fn as_instance(&self) -> ExecResult<&InstanceRef> {
+    match *self {
+      Value::Instance(ref i) => Ok(i),
+      _ => Err(self.mismatch("instance")),
+    }
+  }
+
+  fn as_decimal(&self) -> Option<f64> {
+    match *self {
+      Value::Integer(i) => Some(i as f64),
+      Value::Decimal(d) => Some(d),
+      _ => None,
+    }
+  }
+
+  /// Swaps localized text, including inside arrays and objects, for
+  /// its translation into `locale`. While the program runs, localized
+  /// values hold the source text, which is what their keys come from.
+  pub fn localize(self, translations: &Translations, locale: &str) -> Value {
+    match self {
+      Value::LocalizedText(text) => {
+        let translated = translations.resolve(locale, &string_key(&text)).map(Arc::from);
+        Value::Text(translated.unwrap_or(text))
+      }
+      Value::Array(a) => {
+        Value::Array(a.into_iter().map(|v| v.localize(translations, locale)).collect())
+      }
+      Value::Object(o) => {
+        Value::Object(o.into_iter().map(|(k, v)| (k, v.localize(translations, locale))).collect())
+      }
+      value => value,
+    }
+  }
+
+  fn mismatch(&self, expected: &'static str) -> ExecError {
+    ExecErrorKind::TypeMismatch(expected, self.to_string()).into()
+  }
+
+  pub fn not(&self) -> ExecResult<Value> {
+    Ok(Value::Option(!self.as_option()?))
+  }
+
+  pub fn neg(&self) -> ExecResult<Value> {
+    match *self {
+      Value::Integer(i) => checked(i.checked_neg()).map(Value::Integer),
+      Value::Decimal(d) => Ok(Value::Decimal(-d)),
+      Value::TimeSpan(t) => checked(t.checked_neg()).map(Value::TimeSpan),
+      _ => Err(self.mismatch("number or timespan")),
+    }
+  }
+
+  pub fn index(&self, index: &Value) -> ExecResult<Value> {
+    let array = match *self {
+      Value::Array(ref a) => a,
+      _ => return Err(self.mismatch("array")),
+    };
+    let i = index.as_integer()?;
+    if i < 0 || i as usize >= array.len() {
+      return Err(ExecErrorKind::IndexOutOfRange(i, array.len()).into());
+    }
+    Ok(array[i as usize].clone())
+  }
+
+  /// The same operations as `Constant::binary`, plus date-times,
+  /// which are never constant. `.`, `and` and `or` don't get here.
+  pub fn binary(operator: BinaryOperator, left: &Value, right: &Value) -> ExecResult<Value> {
+    use self::BinaryOperator as B;
+    use self::Value as V;
+
+    Ok(match (operator, left, right) {
+      (B::Add, &V::Text(ref l), &V::Text(ref r)) => V::Text(format!("{}{}", l, r).into()),
+
+      (B::Add, &V::DateTime(d), &V::TimeSpan(t)) | (B::Add, &V::TimeSpan(t), &V::DateTime(d)) => {
+        V::DateTime(checked(d.checked_add(t))?)
+      }
+      (B::Sub, &V::DateTime(d), &V::TimeSpan(t)) => V::DateTime(checked(d.checked_sub(t))?),
+      (B::Sub, &V::DateTime(l), &V::DateTime(r)) => V::TimeSpan(checked(l.checked_sub(r))?),
+
+      (B::Add, &V::TimeSpan(l), &V::TimeSpan(r)) => V::TimeSpan(checked(l.checked_add(r))?),
+      (B::Sub, &V::TimeSpan(l), &V::TimeSpan(r)) => V::TimeSpan(checked(l.checked_sub(r))?),
+      (B::Mul, &V::TimeSpan(t), &V::Integer(n)) | (B::Mul, &V::Integer(n), &V::TimeSpan(t)) => {
+        V::TimeSpan(checked(t.checked_mul(n))?)
+      }
+      (B::Mul, &V::TimeSpan(t), &V::Decimal(n)) | (B::Mul, &V::Decimal(n), &V::TimeSpan(t)) => {
+        V::TimeSpan(decimal_to_integer(t as f64 * n)?)
+      }
+      (B::Div, &V::TimeSpan(t), &V::Integer(n)) => {
+        if n == 0 {
+          return Err(ExecErrorKind::DivisionByZero.into());
+        }
+        V::TimeSpan(checked(t.checked_div(n))?)
+      }
+      (B::Div, &V::TimeSpan(t), &V::Decimal(n)) => {
+        if n == 0.0 {
+          return Err(ExecErrorKind::DivisionByZero.into());
+        }
+        V::TimeSpan(decimal_to_integer(t as f64 / n)?)
+      }
+      (B::Mod, &V::TimeSpan(l), &V::TimeSpan(r)) => {
+        if r == 0 {
+          return Err(ExecErrorKind::DivisionByZero.into());
+        }
+        V::TimeSpan(checked(l.checked_rem(r))?)
+      }
+      (B::Div, &V::TimeSpan(l), &V::TimeSpan(r)) => {
+        if r == 0 {
+          return Err(ExecErrorKind::DivisionByZero.into());
+        }
+        V::Decimal(l as f64 / r as f64)
+      }
+
+      (_, &V::Integer(l), &V::Integer(r)) => integer_op(operator, l, r)?,
+      (_, l, r) if l.as_decimal().is_some() && r.as_decimal().is_some() => {
+        decimal_op(operator, l.as_decimal().unwrap(), r.as_decimal().unwrap())?
+      }
+
+      (B::Eq, l, r) => V::Option(l == r),
+      (B::Ne, l, r) => V::Option(l != r),
+      (B::Lt, &V::Text(ref l), &V::Text(ref r)) => V::Option(l < r),
+      (B::Le, &V::Text(ref l), &V::Text(ref r)) => V::Option(l <= r),
+      (B::Gt, &V::Text(ref l), &V::Text(ref r)) => V::Option(l > r),
+      (B::Ge, &V::Text(ref l), &V::Text(ref r)) => V::Option(l >= r),
+      | (B::Lt, &V::TimeSpan(l), &V::TimeSpan(r))
+      | (B::Lt, &V::DateTime(l), &V::DateTime(r)) => V::Option(l < r),
+      | (B::Le, &V::TimeSpan(l), &V::TimeSpan(r))
+      | (B::Le, &V::DateTime(l), &V::DateTime(r)) => V::Option(l <= r),
+      | (B::Gt, &V::TimeSpan(l), &V::TimeSpan(r))
+      | (B::Gt, &V::DateTime(l), &V::DateTime(r)) => V::Option(l > r),
+      | (B::Ge, &V::TimeSpan(l), &V::TimeSpan(r))
+      | (B::Ge, &V::DateTime(l), &V::DateTime(r)) => V::Option(l >= r),
+      (_, l, r) => {
+        return Err(ExecErrorKind::TypeMismatch(
+          "operands the operator applies to",
+          format!("{} {} {}", l, operator, r),
+        ).into());
+      }
+    })
+  }
+}
+
+impl Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Value::Void => f.write_str("void"),
+      Value::Option(o) => f.write_str(if o { "yes" } else { "no" }),
+      Value::Text(ref t) => write!(f, "'{}'", t),
+      Value::LocalizedText(ref t) => write!(f, "localized '{}'", t),
+      Value::Integer(i) => write!(f, "{}", i),
+      Value::Decimal(d) => write!(f, "{}", d),
+      Value::DateTime(d) => write!(f, "{} milliseconds since 1970", d),
+      Value::TimeSpan(t) => write!(f, "{} milliseconds", t),
+      Value::Object(ref o) => {
+        f.write_str("{")?;
+        for (i, (key, value)) in o.iter().enumerate() {
+          if i > 0 { f.write_str(",")?; }
+          write!(f, " {}: {}", key, value)?;
+        }
+        f.write_str(" }")
+      }
+      Value::Array(ref a) => {
+        f.write_str("[")?;
+        for (i, value) in a.iter().enumerate() {
+          if i > 0 { f.write_str(", ")?; }
+          write!(f, "{}", value)?;
+        }
+        f.write_str("]")
+      }
+      Value::Instance(ref i) => Display::fmt(i, f),
+      Value::Type(ref t) => f.write_str(t),
+    }
+  }
+}
+
+fn checked(value: Option<i64>) -> ExecResult<i64> {
+  value.ok_or_else(|| ExecErrorKind::IntegerOutOfRange.into())
+}
+
+fn decimal_to_integer(value: f64) -> ExecResult<i64> {
+  let value = value.round();
+  if value.is_nan() || value < i64::MIN as f64 || value >= i64::MAX as f64 {
+    Err(ExecErrorKind::IntegerOutOfRange.into())
+  } else {
+    Ok(value as i64)
+  }
+}
+
+fn integer_op(operator: BinaryOperator, l: i64, r: i64) -> ExecResult<Value> {
+  use self::BinaryOperator as B;
+
+  let value = match operator {
+    B::Add => l.checked_add(r),
+    B::Sub => l.checked_sub(r),
+    B::Mul => l.checked_mul(r),
+    B::Div | B::Mod if r == 0 => return Err(ExecErrorKind::DivisionByZero.into()),
+    B::Div => l.checked_div(r),
+    B::Mod => l.checked_rem(r),
+    B::Pow => {
+      if r < 0 {
+        return Err(ExecErrorKind::ValueOutOfRange(
+          r.to_string(),
+          "integers can't be raised to a negative power",
+        ).into());
+      }
+      checked_pow(l, r)
+    }
+    B::Eq => return Ok(Value::Option(l == r)),
+    B::Ne => return Ok(Value::Option(l != r)),
+    B::Lt => return Ok(Value::Option(l < r)),
+    B::Le => return Ok(Value::Option(l <= r)),
+    B::Gt => return Ok(Value::Option(l > r)),
+    B::Ge => return Ok(Value::Option(l >= r)),
+    B::Dot | B::And | B::Or => {
+      return Err(ExecErrorKind::InvalidProgram("operator has its own instructions").into());
+    }
+  };
+  checked(value).map(Value::Integer)
+}
+
+fn checked_pow(base: i64, exponent: i64) -> Option<i64> {
+  match base {
+    0 | 1 => return Some(if exponent == 0 { 1 } else { base }),
+    -1 => return Some(if exponent % 2 == 0 { 1 } else { -1 }),
+    _ => {}
+  }
+  if exponent > 64 {
+    return None;
+  }
+  let mut result: i64 = 1;
+  for _ in 0..exponent {
+    result = match result.checked_mul(base) {
+      Some(result) => result,
+      None => return None,
+    };
+  }
+  Some(result)
+}
+
+fn decimal_op(operator: BinaryOperator, l: f64, r: f64) -> ExecResult<Value> {
+  use self::BinaryOperator as B;
+
+  let value = match operator {
+    B::Add => l + r,
+    B::Sub => l - r,
+    B::Mul => l * r,
+    B::Div | B::Mod if r == 0.0 => return Err(ExecErrorKind::DivisionByZero.into()),
+    B::Div => l / r,
+    B::Mod => l % r,
+    B::Pow => l.powf(r),
+    B::Eq => return Ok(Value::Option(l == r)),
+    B::Ne => return Ok(Value::Option(l != r)),
+    B::Lt => return Ok(Value::Option(l < r)),
+    B::Le => return Ok(Value::Option(l <= r)),
+    B::Gt => return Ok(Value::Option(l > r)),
+    B::Ge => return Ok(Value::Option(l >= r)),
+    B::Dot | B::And | B::Or => {
+      return Err(ExecErrorKind::InvalidProgram("operator has its own instructions").into());
+    }
+  };
+  if value.is_finite() {
+    Ok(Value::Decimal(value))
+  } else {
+    Err(ExecErrorKind::ValueOutOfRange(
+      value.to_string(),
+      "the result isn't a finite number",
+    ).into())
+  }
+}
@@ -16,6 +16,7 @@ extern crate error_chain;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate erased_serde;
 #[macro_use]
 extern crate bitflags;
@@ -25,5 +26,9 @@ extern crate scifi_util as util;
 mod macros;
 
 pub mod ast;
+pub mod bc;
 pub mod compile;
-pub use compile::{compile_file, compile_string};
+pub mod interp;
+pub mod query;
+pub mod strings;
+pub use compile::{compile_file, compile_string, Compiled, CompileOptions};
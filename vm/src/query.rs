@@ -0,0 +1,87 @@
+//! The executable form of `find` expressions. The compiler fills in
+//! the names from the program and the runtime fills in the operands,
+//! then the query is run by whatever the runtime uses to reach the model.
+
+use std::sync::Arc;
+
+/// `V` is whatever stands in for the operands: values at runtime, or
+/// wherever the compiled code leaves them before that.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Query<V> {
+  /// Whether `owner` has an instance of `event` that hasn't finished yet.
+  EventInstance {
+    event: Arc<str>,
+    owner: V,
+  },
+  /// A user of `user_type`, or of a user type in the group.
+  User {
+    user_type: Arc<str>,
+    filter: Option<UserFilter<V>>,
+  },
+  /// The id of a game server whose switch is set to `value`.
+  GameServer {
+    switch: Arc<str>,
+    value: V,
+  },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum UserFilter<V> {
+  Property {
+    name: Arc<str>,
+    value: V,
+  },
+  /// Either end of the range may be open.
+  Amount {
+    collectable: Arc<str>,
+    min: Option<i64>,
+    max: Option<i64>,
+  },
+  /// Users whose amount is close to the amount the user running the
+  /// event has. How close is up to the runner, which may also widen
+  /// the search when nobody is close enough.
+  SimilarAmount {
+    collectable: Arc<str>,
+  },
+}
+
+impl<V> Query<V> {
+  /// Replaces the operands, e.g. with their values once they're known.
+  pub fn map<W, E, F>(self, mut f: F) -> Result<Query<W>, E>
+  where F: FnMut(V) -> Result<W, E>
+  {
+    Ok(match self {
+      Query::EventInstance { event, owner } => {
+        Query::EventInstance { event, owner: f(owner)? }
+      }
+      Query::User { user_type, filter } => Query::User {
+        user_type,
+        filter: match filter {
+          Some(UserFilter::Property { name, value }) => {
+            Some(UserFilter::Property { name, value: f(value)? })
+          }
+          Some(UserFilter::Amount { collectable, min, max }) => {
+            Some(UserFilter::Amount { collectable, min, max })
+          }
+          Some(UserFilter::SimilarAmount { collectable }) => {
+            Some(UserFilter::SimilarAmount { collectable })
+          }
+          None => None,
+        },
+      },
+      Query::GameServer { switch, value } => {
+        Query::GameServer { switch, value: f(value)? }
+      }
+    })
+  }
+}
+
+/// Runs queries against the model.
+pub trait QueryRunner<V> {
+  type Error;
+
+  /// `Ok(None)` when nothing matches. When several instances match,
+  /// which one is returned is up to the runner. `find event` only
+  /// says whether there is one, so its result is just checked for `None`.
+  fn find(&mut self, query: &Query<V>) -> Result<Option<V>, Self::Error>;
+}
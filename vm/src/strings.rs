@@ -0,0 +1,294 @@
+//! Localized strings. The compiler collects every `localized '...'`
+//! literal into a `StringTable`, which can be exported for translators
+//! as JSON or as a gettext `.po` template. Programs run with the source
+//! text, and the interpreter swaps in the translation from `Translations`
+//! when a result is shown in a locale set with `Interpreter::set_locale`,
+//! falling back to the source text when a string hasn't been translated.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+use fxhash::FxHashMap;
+use serde::ser::{Serializer, SerializeSeq};
+use serde_json;
+use compile::TokenSpan;
+
+/// Keys only depend on the text, so they stay the same when strings
+/// move around or other strings are added, and the same text used in
+/// several places is translated once. This is 64 bit FNV-1a, which
+/// unlike the std hashers is guaranteed not to change between builds.
+pub fn string_key(text: &str) -> String {
+  let mut hash: u64 = 0xcbf29ce484222325;
+  for byte in text.bytes() {
+    hash ^= byte as u64;
+    hash = hash.wrapping_mul(0x100000001b3);
+  }
+  format!("{:016x}", hash)
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocalizedString {
+  key: Arc<str>,
+  text: Arc<str>,
+  #[serde(serialize_with = "serialize_locations")]
+  locations: Vec<TokenSpan>,
+}
+
+/// As `file:line:column`, since the string table
+/// doesn't otherwise say which file a span is in.
+fn serialize_locations<S: Serializer>(locations: &Vec<TokenSpan>, serializer: S)
+  -> ::std::result::Result<S::Ok, S::Error>
+{
+  let mut seq = serializer.serialize_seq(Some(locations.len()))?;
+  for span in locations {
+    seq.serialize_element(
+      &format!("{}:{}:{}", span.filename.display(), span.line, span.start)
+    )?;
+  }
+  seq.end()
+}
+
+impl LocalizedString {
+  pub fn key(&self) -> &Arc<str> {
+    &self.key
+  }
+
+  pub fn text(&self) -> &Arc<str> {
+    &self.text
+  }
+
+  /// Every place the string is used, in the order they were parsed.
+  pub fn locations(&self) -> &[TokenSpan] {
+    &self.locations
+  }
+}
+
+/// The file formats a string table can be exported to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StringFormat {
+  Json,
+  Po,
+}
+
+impl StringFormat {
+  /// `.po` and `.pot` files are gettext, anything else is JSON.
+  pub fn from_path(path: &Path) -> Self {
+    match path.extension().and_then(|e| e.to_str()) {
+      Some("po") | Some("pot") => StringFormat::Po,
+      _ => StringFormat::Json,
+    }
+  }
+}
+
+#[derive(Debug, Default)]
+pub struct StringTable {
+  strings: Vec<LocalizedString>,
+  keys: FxHashMap<Arc<str>, usize>,
+}
+
+impl StringTable {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Adds a use of `text` at `span` and returns its key.
+  pub fn insert(&mut self, text: Arc<str>, span: TokenSpan) -> Arc<str> {
+    let key: Arc<str> = string_key(&text).into();
+    if let Some(&index) = self.keys.get(&key) {
+      self.strings[index].locations.push(span);
+      return key;
+    }
+    self.keys.insert(key.clone(), self.strings.len());
+    self.strings.push(LocalizedString {
+      key: key.clone(),
+      text,
+      locations: vec![span],
+    });
+    key
+  }
+
+  pub fn get(&self, key: &str) -> Option<&LocalizedString> {
+    self.keys.get(key).map(|&index| &self.strings[index])
+  }
+
+  pub fn iter(&self) -> ::std::slice::Iter<LocalizedString> {
+    self.strings.iter()
+  }
+
+  pub fn len(&self) -> usize {
+    self.strings.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.strings.is_empty()
+  }
+
+  pub fn export<W: Write>(&self, out: &mut W, format: StringFormat) -> io::Result<()> {
+    match format {
+      StringFormat::Json => {
+        serde_json::to_writer_pretty(&mut *out, &self.strings)
+          .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        writeln!(out)
+      }
+      StringFormat::Po => self.write_po(out),
+    }
+  }
+
+  /// The key goes in `msgctxt` so translations can be matched back
+  /// to it, and translators still see the source text as `msgid`.
+  fn write_po<W: Write>(&self, out: &mut W) -> io::Result<()> {
+    writeln!(out, "msgid \"\"")?;
+    writeln!(out, "msgstr \"\"")?;
+    writeln!(out, "\"Content-Type: text/plain; charset=UTF-8\\n\"")?;
+    for string in &self.strings {
+      writeln!(out)?;
+      for location in &string.locations {
+        writeln!(out, "#: {}:{}", location.filename.display(), location.line)?;
+      }
+      writeln!(out, "msgctxt \"{}\"", string.key)?;
+      writeln!(out, "msgid \"{}\"", escape_po(&string.text))?;
+      writeln!(out, "msgstr \"\"")?;
+    }
+    Ok(())
+  }
+}
+
+/// Translated strings for each locale, keyed the same as the `StringTable`
+/// they were made from.
+#[derive(Debug, Default)]
+pub struct Translations {
+  source: FxHashMap<Arc<str>, Arc<str>>,
+  locales: FxHashMap<String, FxHashMap<String, String>>,
+}
+
+impl Translations {
+  pub fn new(table: &StringTable) -> Self {
+    Translations {
+      source: table.iter().map(|s| (s.key.clone(), s.text.clone())).collect(),
+      locales: Default::default(),
+    }
+  }
+
+  /// Adds to any strings already loaded for `locale`.
+  pub fn insert_locale<I>(&mut self, locale: &str, strings: I)
+  where I: IntoIterator<Item = (String, String)>
+  {
+    self.locales
+      .entry(locale.to_owned())
+      .or_insert_with(Default::default)
+      .extend(strings.into_iter().filter(|&(_, ref text)| !text.is_empty()));
+  }
+
+  /// Loads a JSON object of `{ "key": "translation" }`.
+  pub fn load_json<R: Read>(&mut self, locale: &str, reader: R) -> serde_json::Result<()> {
+    let strings: FxHashMap<String, String> = serde_json::from_reader(reader)?;
+    self.insert_locale(locale, strings);
+    Ok(())
+  }
+
+  /// Loads a translated copy of the `.po` template.
+  pub fn load_po(&mut self, locale: &str, po: &str) {
+    let strings = parse_po(po);
+    self.insert_locale(locale, strings);
+  }
+
+  pub fn has_locale(&self, locale: &str) -> bool {
+    self.locales.contains_key(locale)
+  }
+
+  /// Tries the exact locale, then just its language (`pt` for `pt-BR`),
+  /// then the text from the source.
+  pub fn resolve(&self, locale: &str, key: &str) -> Option<&str> {
+    let language = locale.split(|c| c == '-' || c == '_').next().unwrap_or(locale);
+    self.lookup(locale, key)
+      .or_else(|| self.lookup(language, key))
+      .or_else(|| self.source.get(key).map(|text| &**text))
+  }
+
+  fn lookup(&self, locale: &str, key: &str) -> Option<&str> {
+    self.locales
+      .get(locale)
+      .and_then(|strings| strings.get(key))
+      .map(String::as_str)
+  }
+}
+
+fn escape_po(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+  for c in text.chars() {
+    match c {
+      '"' => escaped.push_str("\\\""),
+      '\\' => escaped.push_str("\\\\"),
+      '\n' => escaped.push_str("\\n"),
+      '\r' => escaped.push_str("\\r"),
+      '\t' => escaped.push_str("\\t"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+fn unescape_po(text: &str) -> String {
+  let mut unescaped = String::with_capacity(text.len());
+  let mut chars = text.chars();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      unescaped.push(c);
+      continue;
+    }
+    match chars.next() {
+      Some('n') => unescaped.push('\n'),
+      Some('r') => unescaped.push('\r'),
+      Some('t') => unescaped.push('\t'),
+      Some(c) => unescaped.push(c),
+      None => {}
+    }
+  }
+  unescaped
+}
+
+/// Reads the `msgctxt` and `msgstr` of each entry, including strings
+/// continued on the following lines. Anything else is ignored.
+fn parse_po(po: &str) -> Vec<(String, String)> {
+  enum Field { None, Context, Message }
+
+  fn quoted(line: &str) -> String {
+    let start = line.find('"').map(|i| i + 1).unwrap_or(line.len());
+    let end = line.rfind('"').unwrap_or(line.len()).max(start);
+    unescape_po(&line[start..end])
+  }
+
+  fn finish(strings: &mut Vec<(String, String)>, context: &mut Option<String>, message: &mut String) {
+    if let Some(context) = context.take() {
+      strings.push((context, ::std::mem::replace(message, String::new())));
+    }
+    message.clear();
+  }
+
+  let mut strings = Vec::new();
+  let mut context = None;
+  let mut message = String::new();
+  let mut field = Field::None;
+  for line in po.lines().map(str::trim) {
+    if line.starts_with("msgctxt ") {
+      finish(&mut strings, &mut context, &mut message);
+      context = Some(quoted(line));
+      field = Field::Context;
+    } else if line.starts_with("msgstr ") {
+      message = quoted(line);
+      field = Field::Message;
+    } else if line.starts_with("msgid ") {
+      field = Field::None;
+    } else if line.starts_with('"') {
+      match field {
+        Field::Context => if let Some(ref mut context) = context {
+          context.push_str(&quoted(line));
+        },
+        Field::Message => message.push_str(&quoted(line)),
+        Field::None => {}
+      }
+    }
+  }
+  finish(&mut strings, &mut context, &mut message);
+  strings
+}